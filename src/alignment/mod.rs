@@ -1,28 +1,56 @@
 mod sampler;
 
 use crate::core::context::SpatialContext;
-use crate::core::terrain::TerrainGrid;
+use crate::core::layer::{LayerData, LayerKind, LayerMeta};
+use crate::core::roi::{OUTSIDE_MARGIN, distance_to_inside};
+use crate::core::terrain::{ROI_MASK, TerrainGrid};
 use crate::loader::bundle::LayerBundle;
 use crate::utils::progress::create_progress_bar;
 use anyhow::Result;
 use rayon::prelude::*;
 
-pub fn layers_align_and_resample(assets: &LayerBundle, ctx: &SpatialContext) -> Result<TerrainGrid> {
-    let mut grid = TerrainGrid::new(ctx.width, ctx.height);
+pub fn layers_align_and_resample(
+    assets: &LayerBundle,
+    ctx: &SpatialContext,
+) -> Result<TerrainGrid> {
+    let mut grid = TerrainGrid::new(ctx.width, ctx.height, ctx.pixel_size, assets.metas());
+    grid.offset = ctx.offset;
+    let inside = ctx.inside_mask();
+    // Pixels within the margin around the polygon are sampled too; the rest
+    // stay empty and are filled from the sampled ones.
+    let sampled: Option<Vec<bool>> = inside.as_ref().map(|inside| {
+        distance_to_inside(inside, ctx.width, ctx.height)
+            .into_iter()
+            .map(|d| d <= OUTSIDE_MARGIN)
+            .collect()
+    });
     let bar = create_progress_bar(ctx.total_pixels, "Layers Alignment & Resample");
 
     grid.par_rows_mut().enumerate().for_each_init(
         || sampler::SamplingSession::new(assets),
         |session, (y, mut row)| {
             for x in 0..ctx.width {
+                if sampled
+                    .as_ref()
+                    .is_some_and(|sampled| !sampled[y * ctx.width + x])
+                {
+                    continue;
+                }
                 let geo = ctx.get_geo_coord(x, y);
                 let pixel = session.sample(geo.x, geo.y);
-                row.set(x, pixel);
+                row.set(x, &pixel);
             }
             bar.inc(ctx.width as u64);
         },
     );
 
     bar.finish();
+
+    if let Some(inside) = inside {
+        grid.add_layer(
+            LayerMeta::new(ROI_MASK, "ROI Mask", LayerKind::Mask, "flag"),
+            LayerData::Mask(inside),
+        );
+    }
     Ok(grid)
 }
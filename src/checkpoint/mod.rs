@@ -0,0 +1,625 @@
+use crate::alignment::layers_align_and_resample;
+use crate::core::context::SpatialContext;
+use crate::core::layer::{Layer, LayerData, LayerKind, LayerMeta, LayerSlice};
+use crate::core::soil::{SoilDepth, SoilLayerKey, SoilProperty};
+use crate::core::terrain::TerrainGrid;
+use crate::core::validator::validate_terrain_grid;
+use crate::loader::bundle::LayerBundle;
+use crate::physics::hydro::FLOW_REACH;
+use crate::physics::{self, PhysicsMap, physics_analyze};
+use crate::post_process::backdrop::{CELL, LazyBackdrop};
+use crate::post_process::{self, terrain_post_process};
+use anyhow::{Context, Result, anyhow};
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::hash::Hash;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use twox_hash::xxh3::{Hash128, HasherExt};
+
+pub const CHECKPOINTS_PATH: &str = "checkpoints";
+
+const MAGIC: &[u8; 8] = b"LINGCKPT";
+/// Bumped whenever the file layout or what a stage computes changes, so
+/// older checkpoints are rebuilt.
+const FORMAT_VERSION: u32 = 2;
+/// Arrays start on page boundaries, so each can be sliced straight out of a
+/// memory-mapped file.
+const ALIGN: u64 = 4096;
+/// Bytes converted per write when streaming arrays.
+const IO_CHUNK: usize = 1 << 20;
+
+/// Pipeline stages whose output is saved.
+#[derive(Debug, Clone, Copy)]
+enum Stage {
+    Aligned,
+    PostProcessed,
+    Physics,
+}
+
+impl Stage {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Aligned => "aligned",
+            Self::PostProcessed => "post_processed",
+            Self::Physics => "physics",
+        }
+    }
+}
+
+/// Stage outputs of one grid, saved under a key hashed from everything they
+/// depend on, so a rerun with the same inputs resumes where it left off.
+/// The key covers the grid, the source files, the format version and the
+/// parameters the stages run with, so rebuilding the program keeps earlier
+/// checkpoints unless it changes what they hold.
+///
+/// Each file is a magic number, the format version, a TOML header and then
+/// the arrays it lists, little-endian and each aligned to [`ALIGN`] bytes:
+/// continuous layers as f32, categorical layers as u8 codes followed by a
+/// validity bitmap, masks as bitmaps. Files are memory-mapped to load, and
+/// continuous arrays copied out of the mapping as they are on little-endian
+/// hosts.
+pub struct Checkpoints {
+    dir: PathBuf,
+    key: u128,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    width: usize,
+    height: usize,
+    pixel_size: f64,
+    offset: (usize, usize),
+    min_elevation: f32,
+    max_elevation: f32,
+    arrays: Vec<ArrayHeader>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ArrayHeader {
+    name: String,
+    label: String,
+    kind: LayerKind,
+    units: String,
+    median: bool,
+    /// Soil property and depth interval, e.g. `["clay", "0-5"]`
+    soil: Option<(String, String)>,
+    /// Byte offset from the start of the array section
+    offset: u64,
+    len: u64,
+}
+
+fn byte_len(values: LayerSlice) -> u64 {
+    let bitmap = |n: usize| n.div_ceil(8) as u64;
+    match values {
+        LayerSlice::Continuous(v) => v.len() as u64 * 4,
+        LayerSlice::Categorical(v) => v.len() as u64 + bitmap(v.len()),
+        LayerSlice::Mask(v) => bitmap(v.len()),
+    }
+}
+
+/// Aligns, post-processes and analyses the grid `ctx` describes, against the
+/// full ROI's `backdrop` when it is a tile, resuming from the latest stage
+/// saved for the same inputs when `checkpoints_dir` is set, and saving each
+/// stage it runs. The backdrop is only sampled once a stage has to run.
+pub fn run_stages(
+    assets: &LayerBundle,
+    ctx: &SpatialContext,
+    backdrop: Option<&LazyBackdrop>,
+    checkpoints_dir: Option<&Path>,
+) -> Result<(TerrainGrid, PhysicsMap)> {
+    let checkpoints = checkpoints_dir.map(|dir| Checkpoints::new(dir, ctx, assets, backdrop));
+    let checkpoints = checkpoints.as_ref();
+    let backdrop = || backdrop.map(LazyBackdrop::get).transpose();
+
+    let terrain = match checkpoints.and_then(|c| c.load_terrain(Stage::PostProcessed)) {
+        Some(terrain) => terrain,
+        None => {
+            let mut terrain = match checkpoints.and_then(|c| c.load_terrain(Stage::Aligned)) {
+                Some(terrain) => terrain,
+                None => {
+                    let terrain = layers_align_and_resample(assets, ctx)?;
+                    if let Some(c) = checkpoints {
+                        c.save_terrain(Stage::Aligned, &terrain);
+                    }
+                    terrain
+                }
+            };
+            terrain_post_process(&mut terrain, backdrop()?)?;
+            if let Some(c) = checkpoints {
+                c.save_terrain(Stage::PostProcessed, &terrain);
+            }
+            terrain
+        }
+    };
+    validate_terrain_grid(&terrain)?;
+
+    let physics_map = match checkpoints.and_then(|c| c.load_physics(&terrain)) {
+        Some(physics_map) => physics_map,
+        None => {
+            let physics_map = physics_analyze(&terrain, ctx, backdrop()?)?;
+            if let Some(c) = checkpoints {
+                c.save_physics(&physics_map, &terrain);
+            }
+            physics_map
+        }
+    };
+
+    Ok((terrain, physics_map))
+}
+
+impl Checkpoints {
+    /// Checkpoints of the grid `ctx` describes, sampled from `assets` and
+    /// processed against `backdrop` if any.
+    pub fn new(
+        dir: &Path,
+        ctx: &SpatialContext,
+        assets: &LayerBundle,
+        backdrop: Option<&LazyBackdrop>,
+    ) -> Self {
+        let mut state = Hash128::with_seed(0);
+        FORMAT_VERSION.hash(&mut state);
+        (post_process::HALO, physics::HALO, FLOW_REACH, CELL).hash(&mut state);
+
+        ctx.projection.to_string().hash(&mut state);
+        let center = ctx.roi_meters.center();
+        let center = ctx.projection.unproject(center.x, center.y);
+        let (bounds, meters) = (ctx.bounds, ctx.roi_meters);
+        for v in [
+            center.x,
+            center.y,
+            bounds.min().x,
+            bounds.min().y,
+            bounds.max().x,
+            bounds.max().y,
+            meters.min().x,
+            meters.min().y,
+            ctx.pixel_size,
+        ] {
+            v.to_bits().hash(&mut state);
+        }
+        (ctx.width, ctx.height, ctx.offset).hash(&mut state);
+        format!("{:?}", ctx.polygon).hash(&mut state);
+
+        assets.hash_inputs(&mut state);
+        // The backdrop starts where the full ROI grid does, which the window
+        // above already fixes, so its size is all that is left to tell apart.
+        backdrop.map(LazyBackdrop::size).hash(&mut state);
+
+        Self {
+            dir: dir.to_path_buf(),
+            key: state.finish_ext(),
+        }
+    }
+
+    fn path(&self, stage: Stage) -> PathBuf {
+        self.dir
+            .join(format!("{:032x}.{}.ckpt", self.key, stage.name()))
+    }
+
+    fn load_terrain(&self, stage: Stage) -> Option<TerrainGrid> {
+        let (header, arrays) = self.load(stage)?;
+        let layers = header
+            .arrays
+            .iter()
+            .zip(arrays)
+            .map(|(array, data)| {
+                Ok(Layer {
+                    meta: array.meta()?,
+                    data,
+                })
+            })
+            .collect::<Result<Vec<_>>>();
+        let layers = self.report(stage, layers)?;
+
+        Some(TerrainGrid {
+            width: header.width,
+            height: header.height,
+            pixel_size: header.pixel_size,
+            offset: header.offset,
+            min_elevation: header.min_elevation,
+            max_elevation: header.max_elevation,
+            layers,
+        })
+    }
+
+    fn save_terrain(&self, stage: Stage, grid: &TerrainGrid) {
+        let arrays: Vec<_> = grid
+            .layers
+            .iter()
+            .map(|l| (l.meta.clone(), l.data.as_slice()))
+            .collect();
+        self.save(stage, grid, &arrays);
+    }
+
+    fn load_physics(&self, grid: &TerrainGrid) -> Option<PhysicsMap> {
+        use LayerData::Continuous;
+
+        let (header, arrays) = self.load(Stage::Physics)?;
+        let physics_map = match <[LayerData; 5]>::try_from(arrays) {
+            Ok(
+                [
+                    Continuous(slope),
+                    Continuous(aspect),
+                    Continuous(tpi),
+                    Continuous(twi),
+                    Continuous(hli),
+                ],
+            ) if (header.width, header.height) == (grid.width, grid.height) => Ok(PhysicsMap {
+                slope,
+                aspect,
+                tpi,
+                twi,
+                hli,
+            }),
+            _ => Err(anyhow!("Physics arrays do not match the terrain grid")),
+        };
+        self.report(Stage::Physics, physics_map)
+    }
+
+    fn save_physics(&self, physics_map: &PhysicsMap, grid: &TerrainGrid) {
+        let arrays: Vec<_> = PhysicsMap::LAYERS
+            .iter()
+            .filter_map(|&(name, label, units)| {
+                Some((
+                    LayerMeta::new(name, label, LayerKind::Continuous, units),
+                    LayerSlice::Continuous(physics_map.layer(name)?),
+                ))
+            })
+            .collect();
+        self.save(Stage::Physics, grid, &arrays);
+    }
+
+    /// Header and arrays of a stage, or `None` when it was never saved or the
+    /// file cannot be used.
+    fn load(&self, stage: Stage) -> Option<(Header, Vec<LayerData>)> {
+        let path = self.path(stage);
+        if !path.exists() {
+            return None;
+        }
+        let loaded = read_file(&path);
+        self.report(stage, loaded)
+            .inspect(|_| println!("Checkpoint: resumed {} grid from {:?}", stage.name(), path))
+    }
+
+    fn save(&self, stage: Stage, grid: &TerrainGrid, arrays: &[(LayerMeta, LayerSlice)]) {
+        let path = self.path(stage);
+        // Written under a temporary name first, so an interrupted run never
+        // leaves a truncated checkpoint behind.
+        let tmp = path.with_extension("ckpt.tmp");
+        let saved = fs::create_dir_all(&self.dir)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| write_file(&tmp, grid, arrays))
+            .and_then(|_| fs::rename(&tmp, &path).map_err(anyhow::Error::from))
+            .with_context(|| format!("Failed to save checkpoint {:?}", path));
+        if let Err(e) = saved {
+            eprintln!("{e:#}");
+            let _ = fs::remove_file(&tmp);
+        }
+    }
+
+    /// A checkpoint that cannot be read only costs recomputing its stage.
+    fn report<T>(&self, stage: Stage, result: Result<T>) -> Option<T> {
+        result
+            .inspect_err(|e| {
+                eprintln!(
+                    "Ignoring checkpoint {:?}: {e:#}",
+                    self.path(stage).file_name().unwrap_or_default()
+                )
+            })
+            .ok()
+    }
+}
+
+impl ArrayHeader {
+    fn meta(&self) -> Result<LayerMeta> {
+        let soil = match &self.soil {
+            Some((property, depth)) => Some(SoilLayerKey {
+                property: *SoilProperty::ALL
+                    .iter()
+                    .find(|p| p.dir_name() == property)
+                    .ok_or_else(|| anyhow!("Unknown soil property {property:?}"))?,
+                depth: depth.parse::<SoilDepth>()?,
+            }),
+            None => None,
+        };
+        Ok(LayerMeta {
+            median: self.median,
+            soil,
+            ..LayerMeta::new(&self.name, &self.label, self.kind, &self.units)
+        })
+    }
+}
+
+fn write_file(path: &Path, grid: &TerrainGrid, arrays: &[(LayerMeta, LayerSlice)]) -> Result<()> {
+    let mut offset = 0;
+    let header = Header {
+        width: grid.width,
+        height: grid.height,
+        pixel_size: grid.pixel_size,
+        offset: grid.offset,
+        min_elevation: grid.min_elevation,
+        max_elevation: grid.max_elevation,
+        arrays: arrays
+            .iter()
+            .map(|(meta, values)| {
+                let array = ArrayHeader {
+                    name: meta.name.clone(),
+                    label: meta.label.clone(),
+                    kind: meta.kind,
+                    units: meta.units.clone(),
+                    median: meta.median,
+                    soil: meta
+                        .soil
+                        .map(|k| (k.property.dir_name().to_string(), k.depth.dir_name())),
+                    offset,
+                    len: byte_len(*values),
+                };
+                offset = (offset + array.len).next_multiple_of(ALIGN);
+                array
+            })
+            .collect(),
+    };
+    let header_text = toml::to_string(&header)?;
+
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(MAGIC)?;
+    out.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    out.write_all(&(header_text.len() as u32).to_le_bytes())?;
+    out.write_all(header_text.as_bytes())?;
+    let mut written = (MAGIC.len() + 8 + header_text.len()) as u64;
+
+    for (array, (_, values)) in header.arrays.iter().zip(arrays) {
+        let start = array_start(header_text.len()) + array.offset;
+        out.write_all(&vec![0; (start - written) as usize])?;
+        write_values(&mut out, *values)?;
+        written = start + array.len;
+    }
+    out.into_inner()
+        .map_err(io::IntoInnerError::into_error)?
+        .sync_all()?;
+    Ok(())
+}
+
+fn write_values(out: &mut impl Write, values: LayerSlice) -> io::Result<()> {
+    match values {
+        LayerSlice::Continuous(v) => {
+            for chunk in v.chunks(IO_CHUNK / 4) {
+                let bytes: Vec<u8> = chunk.iter().flat_map(|x| x.to_le_bytes()).collect();
+                out.write_all(&bytes)?;
+            }
+            Ok(())
+        }
+        LayerSlice::Categorical(v) => {
+            let codes: Vec<u8> = v.iter().map(|c| c.unwrap_or(0)).collect();
+            out.write_all(&codes)?;
+            out.write_all(&pack_bits(v.iter().map(Option::is_some)))
+        }
+        LayerSlice::Mask(v) => out.write_all(&pack_bits(v.iter().copied())),
+    }
+}
+
+fn read_file(path: &Path) -> Result<(Header, Vec<LayerData>)> {
+    let file = File::open(path)?;
+    // Checkpoints are written under a temporary name and renamed into place,
+    // so a mapped file is never written to.
+    let map = unsafe { Mmap::map(&file)? };
+
+    let prefix = map.get(..16).ok_or_else(|| anyhow!("Not a checkpoint"))?;
+    if &prefix[..8] != MAGIC {
+        return Err(anyhow!("Not a checkpoint"));
+    }
+    let version = u32::from_le_bytes(prefix[8..12].try_into()?);
+    if version != FORMAT_VERSION {
+        return Err(anyhow!(
+            "Format version {version}, expected {FORMAT_VERSION}"
+        ));
+    }
+    let header_len = u32::from_le_bytes(prefix[12..16].try_into()?) as usize;
+    let header_text = map
+        .get(16..16 + header_len)
+        .ok_or_else(|| anyhow!("Truncated header"))?;
+    let header: Header = toml::from_str(std::str::from_utf8(header_text)?)?;
+
+    let len = header.width * header.height;
+    let mut arrays = Vec::with_capacity(header.arrays.len());
+    for array in &header.arrays {
+        let values = LayerData::empty(array.kind, len);
+        if byte_len(values.as_slice()) != array.len {
+            return Err(anyhow!(
+                "Array {:?} does not match the grid size",
+                array.name
+            ));
+        }
+        let start = array_start(header_len) + array.offset;
+        let bytes = usize::try_from(start)
+            .ok()
+            .zip(usize::try_from(start + array.len).ok())
+            .and_then(|(start, end)| map.get(start..end))
+            .ok_or_else(|| anyhow!("Array {:?} is truncated", array.name))?;
+        arrays.push(read_values(bytes, values));
+    }
+    Ok((header, arrays))
+}
+
+fn read_values(bytes: &[u8], mut values: LayerData) -> LayerData {
+    match &mut values {
+        LayerData::Continuous(v) => {
+            // SAFETY: every bit pattern is a valid f32.
+            match unsafe { bytes.align_to::<f32>() } {
+                ([], floats, []) if cfg!(target_endian = "little") => v.copy_from_slice(floats),
+                _ => {
+                    for (x, b) in v.iter_mut().zip(bytes.chunks_exact(4)) {
+                        *x = f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+                    }
+                }
+            }
+        }
+        LayerData::Categorical(v) => {
+            let (codes, valid) = bytes.split_at(v.len());
+            for (i, (x, &code)) in v.iter_mut().zip(codes).enumerate() {
+                *x = bit(valid, i).then_some(code);
+            }
+        }
+        LayerData::Mask(v) => {
+            for (i, x) in v.iter_mut().enumerate() {
+                *x = bit(bytes, i);
+            }
+        }
+    }
+    values
+}
+
+/// Start of the array section, after the prefix and header.
+fn array_start(header_len: usize) -> u64 {
+    (MAGIC.len() as u64 + 8 + header_len as u64).next_multiple_of(ALIGN)
+}
+
+fn pack_bits(bits: impl Iterator<Item = bool>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for (i, bit) in bits.enumerate() {
+        if i % 8 == 0 {
+            bytes.push(0);
+        }
+        if bit {
+            *bytes.last_mut().unwrap() |= 1 << (i % 8);
+        }
+    }
+    bytes
+}
+
+fn bit(bytes: &[u8], i: usize) -> bool {
+    bytes[i / 8] & (1 << (i % 8)) != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::terrain::{ELEVATION, LANDCOVER, ROI_MASK};
+
+    const W: usize = 13;
+    const H: usize = 7;
+
+    fn checkpoints(name: &str) -> Checkpoints {
+        let dir = std::env::temp_dir().join(format!("lingine-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        Checkpoints { dir, key: 42 }
+    }
+
+    fn grid() -> TerrainGrid {
+        let soil = SoilLayerKey::DEFAULTS[0];
+        let mut grid = TerrainGrid::new(
+            W,
+            H,
+            30.0,
+            vec![
+                LayerMeta::new(ELEVATION, "Elevation", LayerKind::Continuous, "m"),
+                LayerMeta::new(LANDCOVER, "Landcover", LayerKind::Categorical, "class"),
+                LayerMeta::soil(soil, "%"),
+            ],
+        );
+        grid.offset = (512, 1024);
+        grid.min_elevation = -3.5;
+        grid.max_elevation = 812.25;
+        for (i, layer) in grid.layers.iter_mut().enumerate() {
+            match &mut layer.data {
+                LayerData::Continuous(v) => v
+                    .iter_mut()
+                    .enumerate()
+                    .filter(|(j, _)| j % 5 != 0)
+                    .for_each(|(j, x)| *x = (i * 1000 + j) as f32 * 0.25 - 7.0),
+                LayerData::Categorical(v) => v
+                    .iter_mut()
+                    .enumerate()
+                    .filter(|(j, _)| j % 3 != 0)
+                    .for_each(|(j, x)| *x = Some((j % 11) as u8 * 10)),
+                LayerData::Mask(_) => {}
+            }
+        }
+        grid.add_layer(
+            LayerMeta::new(ROI_MASK, "ROI Mask", LayerKind::Mask, "flag"),
+            LayerData::Mask((0..W * H).map(|j| j % 7 < 3).collect()),
+        );
+        grid
+    }
+
+    fn same_data(a: &LayerData, b: &LayerData) -> bool {
+        match (a, b) {
+            (LayerData::Continuous(a), LayerData::Continuous(b)) => a
+                .iter()
+                .map(|v| v.to_bits())
+                .eq(b.iter().map(|v| v.to_bits())),
+            (LayerData::Categorical(a), LayerData::Categorical(b)) => a == b,
+            (LayerData::Mask(a), LayerData::Mask(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn terrain_round_trips() {
+        let checkpoints = checkpoints("terrain");
+        let grid = grid();
+        checkpoints.save_terrain(Stage::Aligned, &grid);
+        let loaded = checkpoints.load_terrain(Stage::Aligned).unwrap();
+        fs::remove_dir_all(&checkpoints.dir).unwrap();
+
+        assert_eq!((loaded.width, loaded.height), (W, H));
+        assert_eq!(loaded.pixel_size, grid.pixel_size);
+        assert_eq!(loaded.offset, grid.offset);
+        assert_eq!(
+            (loaded.min_elevation, loaded.max_elevation),
+            (grid.min_elevation, grid.max_elevation)
+        );
+        assert_eq!(loaded.layers.len(), grid.layers.len());
+        for (a, b) in grid.layers.iter().zip(&loaded.layers) {
+            assert_eq!(a.meta.name, b.meta.name);
+            assert_eq!(a.meta.label, b.meta.label);
+            assert_eq!(a.meta.kind, b.meta.kind);
+            assert_eq!(a.meta.units, b.meta.units);
+            assert_eq!(a.meta.median, b.meta.median);
+            assert_eq!(a.meta.soil, b.meta.soil);
+            assert!(same_data(&a.data, &b.data), "{} differs", a.meta.name);
+        }
+    }
+
+    #[test]
+    fn physics_round_trips() {
+        let checkpoints = checkpoints("physics");
+        let grid = grid();
+        let layer = |k: f32| (0..W * H).map(|j| j as f32 * k).collect::<Vec<_>>();
+        let physics_map = PhysicsMap {
+            slope: layer(0.01),
+            aspect: layer(0.02),
+            tpi: layer(-0.5),
+            twi: layer(3.0),
+            hli: layer(0.001),
+        };
+        checkpoints.save_physics(&physics_map, &grid);
+        let loaded = checkpoints.load_physics(&grid).unwrap();
+        fs::remove_dir_all(&checkpoints.dir).unwrap();
+
+        for (name, ..) in PhysicsMap::LAYERS {
+            assert_eq!(
+                physics_map.layer(name),
+                loaded.layer(name),
+                "{name} differs"
+            );
+        }
+    }
+
+    #[test]
+    fn unreadable_checkpoints_are_ignored() {
+        let checkpoints = checkpoints("corrupt");
+        assert!(checkpoints.load_terrain(Stage::PostProcessed).is_none());
+
+        checkpoints.save_terrain(Stage::PostProcessed, &grid());
+        let path = checkpoints.path(Stage::PostProcessed);
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+        let truncated = checkpoints.load_terrain(Stage::PostProcessed);
+        fs::remove_dir_all(&checkpoints.dir).unwrap();
+
+        assert!(truncated.is_none());
+    }
+}
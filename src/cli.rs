@@ -0,0 +1,415 @@
+use crate::core::context::{Length, Snap, SpatialContext};
+use crate::core::lonlat::normalize_lon;
+use crate::core::projection::ProjectionKind;
+use crate::core::roi::Roi;
+use crate::core::soil::{SoilDepth, SoilLayerKey, SoilProperty};
+use crate::exporter::OutsideFill;
+use crate::loader::cache::DEFAULT_BUDGET_MIB;
+use crate::loader::mosaic::BlendMode;
+use crate::preview::{GridOverlay, PreviewKind};
+use crate::scanner::types::{EpochPolicy, FootprintMode, ScanOptions};
+use crate::tiling::TILE_ALIGN;
+use anyhow::{Result, anyhow};
+use clap::{Args, Parser, Subcommand};
+use geo::{Coord, Rect};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "Generate Minecraft worlds from real-world terrain datasets",
+    args_conflicts_with_subcommands = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub build: BuildArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Reproject raw dataset downloads to EPSG:4326 tiled GeoTIFFs with overviews
+    Preprocess(PreprocessArgs),
+    /// List the dataset tiles a ROI needs that are missing locally
+    Plan(Box<PlanArgs>),
+}
+
+#[derive(Args, Debug)]
+pub struct BuildArgs {
+    /// Region of interest as `west,south,east,north` in degrees, where an east
+    /// edge smaller than the west edge crosses the antimeridian, or a GeoJSON,
+    /// KML or WKT file whose polygons are built inside their bounding box
+    #[arg(
+        long,
+        value_parser = parse_roi,
+        default_value = "93.84993,29.97956,94.02376,30.15698",
+        allow_hyphen_values = true
+    )]
+    pub roi: Roi,
+
+    /// Centre of the ROI as `lon,lat` in degrees, used instead of `--roi`
+    /// together with `--size`
+    #[arg(
+        long,
+        value_parser = parse_center,
+        conflicts_with = "roi",
+        requires = "size",
+        allow_hyphen_values = true
+    )]
+    pub center: Option<Coord<f64>>,
+
+    /// Size of a centred ROI as `<width>` or `<width>x<height>`, each in
+    /// meters by default or with an `m`, `km` or `blocks` suffix
+    #[arg(long, value_parser = parse_size, requires = "center")]
+    pub size: Option<(Length, Length)>,
+
+    /// Rounds a centred ROI up to whole chunks or region files
+    #[arg(long, value_enum, default_value_t = Snap::Region)]
+    pub snap: Snap,
+
+    /// Terrain exported where the grid lies outside a polygon ROI
+    #[arg(long, value_enum, default_value_t = OutsideFill::Flat)]
+    pub outside: OutsideFill,
+
+    /// Output grid resolution in meters per pixel, which is also the size of
+    /// one exported block
+    #[arg(long, value_parser = parse_resolution, default_value_t = 1.0)]
+    pub resolution: f64,
+
+    /// Planar frame of the output grid: `local` for the spherical local
+    /// projection, `tm` for an ellipsoidal Transverse Mercator centred on the
+    /// ROI, `utm` for the ROI's UTM zone, or `epsg:<code>` for any projected
+    /// CRS in metres
+    #[arg(long, value_parser = parse_projection, default_value = "local")]
+    pub projection: ProjectionKind,
+
+    /// How continuous layers are sampled where dataset tiles overlap
+    #[arg(long, value_enum, default_value_t = BlendMode::Feather)]
+    pub blend: BlendMode,
+
+    /// Process the grid in square tiles of this many pixels, a multiple of 512,
+    /// each written straight to its region files, to bound memory on large
+    /// ROIs
+    #[arg(long, value_parser = parse_tile_size)]
+    pub tile_size: Option<usize>,
+
+    /// Also write these terrain or physics layers, or `all` of them, as
+    /// GeoTIFFs in the grid's projection, e.g. `elevation,landcover,slope,twi`
+    #[arg(long, value_delimiter = ',')]
+    pub export_layers: Vec<String>,
+
+    /// Render these PNG previews of the grid, e.g. `hillshade,elevation,landcover`
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub preview: Vec<PreviewKind>,
+
+    /// Game grid drawn over the previews
+    #[arg(long, value_enum, default_value_t = GridOverlay::None)]
+    pub preview_grid: GridOverlay,
+
+    /// Longest side of a preview in pixels; larger grids are downsampled
+    #[arg(long, default_value_t = 2048)]
+    pub preview_size: usize,
+
+    /// Stop before writing the world, keeping only previews and layer exports
+    #[arg(long)]
+    pub preview_only: bool,
+
+    /// Save each stage's output under `checkpoints/` and resume from those an
+    /// earlier run with the same inputs and settings saved. Nothing there is
+    /// ever deleted, so clear the directory once a world is done
+    #[arg(long)]
+    pub checkpoints: bool,
+
+    /// Memory budget in MiB for raster blocks cached across all threads
+    #[arg(long, default_value_t = DEFAULT_BUDGET_MIB)]
+    pub cache_memory: usize,
+
+    /// TOML settings file, e.g. with per-layer `[resampling.<layer>]` tables
+    /// for elevation, hh, hv, inc, ls, landcover, landcover_confidence and
+    /// soil or a single scanned soil layer such as soil_clay_0-5
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub scan: ScanArgs,
+}
+
+impl BuildArgs {
+    pub fn scan_options(&self) -> ScanOptions {
+        self.scan.scan_options_with(match self.blend {
+            BlendMode::Feather => FootprintMode::ValidData,
+            BlendMode::Top => FootprintMode::Extent,
+        })
+    }
+
+    /// The ROI and the output grid laid over it.
+    pub fn spatial_context(&self) -> Result<(Roi, SpatialContext)> {
+        if let (Some(center), Some((width, height))) = (self.center, self.size) {
+            let ctx = SpatialContext::around(
+                center,
+                width,
+                height,
+                self.resolution,
+                self.snap,
+                self.projection,
+            )?;
+            return Ok((Roi::from_rect(ctx.bounds), ctx));
+        }
+
+        let ctx = SpatialContext::analyze(&self.roi, self.resolution, self.projection)?;
+        Ok((self.roi.clone(), ctx))
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct PreprocessArgs {
+    /// Directory holding raw downloads in the same provider layout as the datasets root
+    #[arg(long, default_value = "datasets/raw")]
+    pub raw: PathBuf,
+
+    /// Reprocess rasters even if their output is already up to date
+    #[arg(long)]
+    pub force: bool,
+
+    #[command(flatten)]
+    pub scan: ScanArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct PlanArgs {
+    #[command(flatten)]
+    pub build: BuildArgs,
+
+    /// Also write the plan to this file
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct ScanArgs {
+    /// How tile footprints are derived while scanning datasets; defaults to
+    /// `valid-data` when feathering, since tiles are blended from the edges
+    /// of their footprints, and to `extent` otherwise
+    #[arg(long, value_enum)]
+    pub footprint: Option<FootprintMode>,
+
+    /// SoilGrids properties to scan; those without data on disk are skipped.
+    /// Without this or `--soil-depths`, only sand, clay and pH at 0-5 and
+    /// 30-60 cm and SOC at 0-5 cm are scanned; with either, every listed
+    /// property is scanned at every listed depth, defaulting to all of them
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub soil_properties: Vec<SoilProperty>,
+
+    /// SoilGrids depth intervals in centimeters to scan, e.g. `0-5,30-60`
+    #[arg(long, value_delimiter = ',')]
+    pub soil_depths: Vec<SoilDepth>,
+
+    /// Dataset epoch to use: a year such as `2021`, `newest` for only the
+    /// newest epoch present, or `fallback` to fill gaps with older tiles
+    #[arg(long, value_parser = parse_epoch_policy, default_value = "fallback")]
+    pub epoch: EpochPolicy,
+}
+
+impl ScanArgs {
+    pub fn scan_options(&self) -> ScanOptions {
+        self.scan_options_with(FootprintMode::Extent)
+    }
+
+    /// Options with `footprint` used unless a footprint mode was given.
+    fn scan_options_with(&self, footprint: FootprintMode) -> ScanOptions {
+        let soil_layers = if self.soil_properties.is_empty() && self.soil_depths.is_empty() {
+            SoilLayerKey::DEFAULTS.to_vec()
+        } else {
+            let depths = or_all(&self.soil_depths, &SoilDepth::ALL);
+            or_all(&self.soil_properties, &SoilProperty::ALL)
+                .iter()
+                .flat_map(|&property| {
+                    depths
+                        .iter()
+                        .map(move |&depth| SoilLayerKey::new(property, depth))
+                })
+                .collect()
+        };
+
+        ScanOptions {
+            footprint_mode: self.footprint.unwrap_or(footprint),
+            soil_layers,
+            epoch_policy: self.epoch,
+        }
+    }
+}
+
+/// `chosen`, or `all` when nothing was chosen.
+fn or_all<'a, T>(chosen: &'a [T], all: &'a [T]) -> &'a [T] {
+    if chosen.is_empty() { all } else { chosen }
+}
+
+fn parse_roi(s: &str) -> Result<Roi> {
+    let path = Path::new(s);
+    if path.is_file() {
+        return Roi::load(path);
+    }
+
+    let values = s
+        .split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| anyhow!("Expected `west,south,east,north` or a polygon file, got {s:?}"))?;
+
+    let [west, south, east, north] = values[..] else {
+        return Err(anyhow!("Expected `west,south,east,north`, got {s:?}"));
+    };
+
+    let east = if east < west { east + 360.0 } else { east };
+
+    Ok(Roi::from_rect(Rect::new(
+        Coord { x: west, y: south },
+        Coord { x: east, y: north },
+    )))
+}
+
+fn parse_center(s: &str) -> Result<Coord<f64>> {
+    let values = s
+        .split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match values[..] {
+        [x, y] if x.is_finite() && (-90.0..=90.0).contains(&y) => Ok(Coord {
+            x: normalize_lon(x),
+            y,
+        }),
+        _ => Err(anyhow!("Expected `lon,lat`, got {s:?}")),
+    }
+}
+
+fn parse_size(s: &str) -> Result<(Length, Length)> {
+    let lowered = s.trim().to_lowercase();
+    match lowered.split_once('x') {
+        Some((width, height)) => Ok((parse_length(width)?, parse_length(height)?)),
+        None => parse_length(&lowered).map(|side| (side, side)),
+    }
+}
+
+fn parse_length(s: &str) -> Result<Length> {
+    let s = s.trim();
+    let length = if let Some(blocks) = s.strip_suffix("blocks") {
+        Length::Blocks(blocks.trim().parse()?)
+    } else if let Some(km) = s.strip_suffix("km") {
+        Length::Meters(km.trim().parse::<f64>()? * 1000.0)
+    } else {
+        Length::Meters(s.strip_suffix('m').unwrap_or(s).trim().parse()?)
+    };
+
+    match length {
+        Length::Meters(m) if !(m.is_finite() && m > 0.0) => {
+            Err(anyhow!("Size must be positive, got {s:?}"))
+        }
+        Length::Blocks(0) => Err(anyhow!("Size must be positive, got {s:?}")),
+        _ => Ok(length),
+    }
+}
+
+fn parse_resolution(s: &str) -> Result<f64> {
+    let resolution = s.trim().parse::<f64>()?;
+    if resolution.is_finite() && resolution > 0.0 {
+        Ok(resolution)
+    } else {
+        Err(anyhow!(
+            "Resolution must be a positive number of meters, got {s:?}"
+        ))
+    }
+}
+
+fn parse_projection(s: &str) -> Result<ProjectionKind> {
+    let s = s.trim().to_lowercase();
+    match s.as_str() {
+        "local" => Ok(ProjectionKind::Adaptive),
+        "tm" => Ok(ProjectionKind::TransverseMercator),
+        "utm" => Ok(ProjectionKind::Utm),
+        _ => s
+            .strip_prefix("epsg:")
+            .and_then(|code| code.parse::<u32>().ok())
+            .map(ProjectionKind::Epsg)
+            .ok_or_else(|| anyhow!("Expected `local`, `tm`, `utm` or `epsg:<code>`, got {s:?}")),
+    }
+}
+
+fn parse_tile_size(s: &str) -> Result<usize> {
+    let size = s.trim().parse::<usize>()?;
+    if size > 0 && size % TILE_ALIGN == 0 {
+        Ok(size)
+    } else {
+        Err(anyhow!(
+            "Tile size must be a positive multiple of {TILE_ALIGN}, got {s:?}"
+        ))
+    }
+}
+
+fn parse_epoch_policy(s: &str) -> Result<EpochPolicy> {
+    match s.trim() {
+        "newest" => Ok(EpochPolicy::Newest),
+        "fallback" => Ok(EpochPolicy::NewestWithFallback),
+        year => year
+            .parse::<u16>()
+            .map(EpochPolicy::Pinned)
+            .map_err(|_| anyhow!("Expected a year, `newest` or `fallback`, got {s:?}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_center_normalizes_longitude() {
+        let center = |s| parse_center(s).map(|c| (c.x, c.y)).unwrap();
+        assert_eq!(center("10, 45"), (10.0, 45.0));
+        assert_eq!(center("190,-30"), (-170.0, -30.0));
+        assert_eq!(center("-540,0"), (-180.0, 0.0));
+    }
+
+    #[test]
+    fn parse_center_rejects_bad_values() {
+        for s in ["NaN,0", "inf,0", "0,NaN", "0,-inf", "0,91", "0", "1,2,3"] {
+            assert!(parse_center(s).is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn parse_size_reads_units() {
+        assert_eq!(
+            parse_size("10km").unwrap(),
+            (Length::Meters(10_000.0), Length::Meters(10_000.0))
+        );
+        assert_eq!(
+            parse_size("2048 blocks").unwrap(),
+            (Length::Blocks(2048), Length::Blocks(2048))
+        );
+        assert_eq!(
+            parse_size("1500m").unwrap(),
+            (Length::Meters(1500.0), Length::Meters(1500.0))
+        );
+    }
+
+    #[test]
+    fn parse_size_reads_width_by_height() {
+        assert_eq!(
+            parse_size("12KM x 512blocks").unwrap(),
+            (Length::Meters(12_000.0), Length::Blocks(512))
+        );
+        assert_eq!(
+            parse_size("800x600").unwrap(),
+            (Length::Meters(800.0), Length::Meters(600.0))
+        );
+    }
+
+    #[test]
+    fn parse_size_rejects_empty_and_invalid_sizes() {
+        for size in ["0", "0blocks", "-5km", "nan", "x10km", "10 miles", ""] {
+            assert!(parse_size(size).is_err(), "{size:?} was accepted");
+        }
+    }
+}
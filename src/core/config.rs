@@ -0,0 +1,32 @@
+use crate::core::resampling::ResamplingOverride;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Optional settings file, e.g.
+///
+/// ```toml
+/// [resampling.elevation]
+/// interpolator = "bicubic"
+/// nodata = "strict"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Resampling overrides keyed by layer name
+    #[serde(default)]
+    pub resampling: BTreeMap<String, ResamplingOverride>,
+}
+
+impl Config {
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config {:?}", path))?;
+        toml::from_str(&text).with_context(|| format!("Invalid config {:?}", path))
+    }
+}
@@ -1,55 +1,345 @@
-use crate::core::projection::AdaptiveLtm;
-use geo::{Coord, Rect};
+use crate::core::lonlat::normalize_lon;
+use crate::core::projection::{LocalProjection, ProjectionKind, ScaleDistortion, scale_distortion};
+use crate::core::roi::{Roi, rasterize};
+use anyhow::Result;
+use clap::ValueEnum;
+use geo::{Coord, MultiPolygon, Rect};
 use std::fmt::Display;
+use std::sync::Arc;
+
+const CHUNK_BLOCKS: usize = 16;
+const REGION_BLOCKS: usize = 512;
+
+/// A distance given either in meters or in output blocks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Length {
+    Meters(f64),
+    Blocks(usize),
+}
+
+impl Length {
+    fn blocks(self, pixel_size: f64) -> usize {
+        match self {
+            Self::Meters(m) => (m / pixel_size).round() as usize,
+            Self::Blocks(b) => b,
+        }
+    }
+}
+
+/// Grid size a centred ROI is rounded up to, so the world fills whole chunks
+/// or region files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Snap {
+    None,
+    Chunk,
+    Region,
+}
+
+impl Snap {
+    fn round_up(self, blocks: usize) -> usize {
+        let unit = match self {
+            Self::None => 1,
+            Self::Chunk => CHUNK_BLOCKS,
+            Self::Region => REGION_BLOCKS,
+        };
+        blocks.max(1).div_ceil(unit) * unit
+    }
+}
 
 pub struct SpatialContext {
-    pub ltm: AdaptiveLtm,
+    pub projection: Arc<dyn LocalProjection>,
+    /// Geographic bounds of the grid; `max().x` may pass 180 when it crosses
+    /// the antimeridian
+    pub bounds: Rect<f64>,
+    /// Worst scale error of the projection within the ROI
+    pub distortion: ScaleDistortion,
     pub roi_meters: Rect<f64>,
     pub width: usize,
     pub height: usize,
     pub total_pixels: u64,
+    /// Output pixel size in meters
+    pub pixel_size: f64,
+    /// Polygon the ROI is clipped to; `None` when it is the whole rectangle
+    pub polygon: Option<Arc<MultiPolygon<f64>>>,
+    /// Position of the first pixel in the full ROI grid; non-zero only for
+    /// windows of it
+    pub offset: (usize, usize),
+}
+
+/// A rectangle of grid pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelWindow {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
 }
 
 impl SpatialContext {
-    pub fn analyze(roi_geo: Rect<f64>) -> Self {
-        let center = roi_geo.center();
-        let ltm = AdaptiveLtm::new(center);
+    /// `pixel_size` is the output grid resolution in meters.
+    pub fn analyze(roi: &Roi, pixel_size: f64, projection: ProjectionKind) -> Result<Self> {
+        let roi_geo = roi.bounds;
+        let projection = projection.build(roi_geo.center())?;
+        let roi_meters = project_bounds(projection.as_ref(), roi_geo);
 
-        let p_min = ltm.project(roi_geo.min().x, roi_geo.min().y);
-        let p_max = ltm.project(roi_geo.max().x, roi_geo.max().y);
-        let roi_meters = Rect::new(p_min, p_max);
+        let width = (roi_meters.width().abs() / pixel_size).round() as usize;
+        let height = (roi_meters.height().abs() / pixel_size).round() as usize;
 
-        let width = roi_meters.width().abs().round() as usize;
-        let height = roi_meters.height().abs().round() as usize;
-        let total_pixels = (width * height) as u64;
+        let mut ctx = Self::new(projection, roi_geo, roi_meters, width, height, pixel_size);
+        ctx.polygon = roi.polygon.clone().map(Arc::new);
+        Ok(ctx)
+    }
+
+    /// A grid of `width` by `height` centred on `center`, with both sides
+    /// rounded up to whole units of `snap`.
+    pub fn around(
+        center: Coord<f64>,
+        width: Length,
+        height: Length,
+        pixel_size: f64,
+        snap: Snap,
+        projection: ProjectionKind,
+    ) -> Result<Self> {
+        let projection = projection.build(center)?;
+        let width = snap.round_up(width.blocks(pixel_size));
+        let height = snap.round_up(height.blocks(pixel_size));
+
+        let c = projection.project(center.x, center.y);
+        let half = Coord {
+            x: width as f64 * pixel_size / 2.0,
+            y: height as f64 * pixel_size / 2.0,
+        };
+        let roi_meters = Rect::new(c - half, c + half);
+        let roi_geo = unproject_bounds(projection.as_ref(), roi_meters, center.x);
+
+        Ok(Self::new(
+            projection, roi_geo, roi_meters, width, height, pixel_size,
+        ))
+    }
 
+    fn new(
+        projection: Arc<dyn LocalProjection>,
+        bounds: Rect<f64>,
+        roi_meters: Rect<f64>,
+        width: usize,
+        height: usize,
+        pixel_size: f64,
+    ) -> Self {
         Self {
-            ltm,
+            distortion: scale_distortion(projection.as_ref(), bounds),
+            projection,
+            bounds,
             roi_meters,
             width,
             height,
-            total_pixels,
+            total_pixels: (width * height) as u64,
+            pixel_size,
+            polygon: None,
+            offset: (0, 0),
+        }
+    }
+
+    /// The part of the grid covered by `window`, sharing this grid's
+    /// projection and pixel positions.
+    pub fn window(&self, window: PixelWindow) -> Self {
+        let min = self.roi_meters.min();
+        let origin = Coord {
+            x: min.x + window.x as f64 * self.pixel_size,
+            y: min.y + window.y as f64 * self.pixel_size,
+        };
+        let roi_meters = Rect::new(
+            origin,
+            Coord {
+                x: origin.x + window.width as f64 * self.pixel_size,
+                y: origin.y + window.height as f64 * self.pixel_size,
+            },
+        );
+
+        Self {
+            projection: self.projection.clone(),
+            bounds: unproject_bounds(self.projection.as_ref(), roi_meters, self.bounds.center().x),
+            distortion: self.distortion,
+            roi_meters,
+            width: window.width,
+            height: window.height,
+            total_pixels: (window.width * window.height) as u64,
+            pixel_size: self.pixel_size,
+            polygon: self.polygon.clone(),
+            offset: (self.offset.0 + window.x, self.offset.1 + window.y),
         }
     }
 
+    /// The same grid at `factor` times the pixel size, its far edges grown to
+    /// whole cells.
+    pub fn coarsened(&self, factor: usize) -> Self {
+        let pixel_size = self.pixel_size * factor as f64;
+        let (width, height) = (self.width.div_ceil(factor), self.height.div_ceil(factor));
+        let min = self.roi_meters.min();
+        let roi_meters = Rect::new(
+            min,
+            Coord {
+                x: min.x + width as f64 * pixel_size,
+                y: min.y + height as f64 * pixel_size,
+            },
+        );
+
+        Self {
+            projection: self.projection.clone(),
+            bounds: unproject_bounds(self.projection.as_ref(), roi_meters, self.bounds.center().x),
+            distortion: self.distortion,
+            roi_meters,
+            width,
+            height,
+            total_pixels: (width * height) as u64,
+            pixel_size,
+            polygon: self.polygon.clone(),
+            offset: (self.offset.0 / factor, self.offset.1 / factor),
+        }
+    }
+
+    /// Pixels whose centres fall inside the ROI polygon; `None` when the ROI
+    /// is a rectangle.
+    pub fn inside_mask(&self) -> Option<Vec<bool>> {
+        self.polygon
+            .as_ref()
+            .map(|polygon| rasterize(polygon, self))
+    }
+
     #[inline]
     pub fn get_geo_coord(&self, x: usize, y: usize) -> Coord<f64> {
-        let mx = self.roi_meters.min().x + x as f64 + 0.5;
-        let my = self.roi_meters.min().y + y as f64 + 0.5;
-        self.ltm.unproject(mx, my)
+        let mx = self.roi_meters.min().x + (x as f64 + 0.5) * self.pixel_size;
+        let my = self.roi_meters.min().y + (y as f64 + 0.5) * self.pixel_size;
+        self.projection.unproject(mx, my)
+    }
+}
+
+/// Bounds of the projected ROI, sampled along its edges since meridians and
+/// parallels are curved in the high-latitude projection.
+fn project_bounds(projection: &dyn LocalProjection, roi_geo: Rect<f64>) -> Rect<f64> {
+    const EDGE_SAMPLES: usize = 32;
+
+    let (min, max) = (roi_geo.min(), roi_geo.max());
+    let mut min_p = Coord {
+        x: f64::MAX,
+        y: f64::MAX,
+    };
+    let mut max_p = Coord {
+        x: f64::MIN,
+        y: f64::MIN,
+    };
+
+    for i in 0..=EDGE_SAMPLES {
+        let t = i as f64 / EDGE_SAMPLES as f64;
+        let lon = min.x + (max.x - min.x) * t;
+        let lat = min.y + (max.y - min.y) * t;
+
+        for (x, y) in [(lon, min.y), (lon, max.y), (min.x, lat), (max.x, lat)] {
+            let p = projection.project(x, y);
+            min_p.x = min_p.x.min(p.x);
+            min_p.y = min_p.y.min(p.y);
+            max_p.x = max_p.x.max(p.x);
+            max_p.y = max_p.y.max(p.y);
+        }
     }
+
+    Rect::new(min_p, max_p)
+}
+
+/// Geographic bounds of a projected rectangle, with longitudes kept within
+/// 180° of `center_lon` so a grid across the antimeridian stays contiguous.
+fn unproject_bounds(
+    projection: &dyn LocalProjection,
+    rect: Rect<f64>,
+    center_lon: f64,
+) -> Rect<f64> {
+    const EDGE_SAMPLES: usize = 32;
+
+    let (min, max) = (rect.min(), rect.max());
+    let mut min_g = Coord {
+        x: f64::MAX,
+        y: f64::MAX,
+    };
+    let mut max_g = Coord {
+        x: f64::MIN,
+        y: f64::MIN,
+    };
+
+    for i in 0..=EDGE_SAMPLES {
+        let t = i as f64 / EDGE_SAMPLES as f64;
+        let x = min.x + (max.x - min.x) * t;
+        let y = min.y + (max.y - min.y) * t;
+
+        for (x, y) in [(x, min.y), (x, max.y), (min.x, y), (max.x, y)] {
+            let g = projection.unproject(x, y);
+            let lon = center_lon + normalize_lon(g.x - center_lon);
+            min_g.x = min_g.x.min(lon);
+            min_g.y = min_g.y.min(g.y);
+            max_g.x = max_g.x.max(lon);
+            max_g.y = max_g.y.max(g.y);
+        }
+    }
+
+    if min_g.x < -180.0 {
+        min_g.x += 360.0;
+        max_g.x += 360.0;
+    }
+    Rect::new(min_g, max_g)
 }
 
 impl Display for SpatialContext {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
+        writeln!(f, "Projection: {}", self.projection)?;
+        writeln!(
+            f,
+            "Bounds: {:.5},{:.5},{:.5},{:.5}",
+            normalize_lon(self.bounds.min().x),
+            self.bounds.min().y,
+            normalize_lon(self.bounds.max().x),
+            self.bounds.max().y
+        )?;
+        writeln!(f, "{}", self.distortion)?;
+        writeln!(
             f,
             "Physical Dimensions: {:.2}m x {:.2}m",
             self.roi_meters.width().abs(),
             self.roi_meters.height().abs()
         )?;
-        write!(f, "Grid Resolution: {} x {}", self.width, self.height)?;
+        writeln!(f, "Pixel Size: {}m", self.pixel_size)?;
+        writeln!(f, "Grid Resolution: {} x {}", self.width, self.height)?;
+        if let Some(polygon) = &self.polygon {
+            writeln!(f, "ROI Polygon: {} part(s)", polygon.0.len())?;
+        }
         write!(f, "Total Voxels: {}", self.total_pixels)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snap_rounds_up_to_whole_units() {
+        assert_eq!(Snap::None.round_up(37), 37);
+        assert_eq!(Snap::Chunk.round_up(16), 16);
+        assert_eq!(Snap::Chunk.round_up(17), 32);
+        assert_eq!(Snap::Region.round_up(513), 1024);
+        assert_eq!(Snap::None.round_up(0), 1);
+        assert_eq!(Snap::Chunk.round_up(0), CHUNK_BLOCKS);
+    }
+
+    #[test]
+    fn around_snaps_both_sides() {
+        let ctx = SpatialContext::around(
+            Coord { x: 10.0, y: 45.0 },
+            Length::Meters(10_000.0),
+            Length::Blocks(300),
+            30.0,
+            Snap::Region,
+            ProjectionKind::Adaptive,
+        )
+        .unwrap();
+        assert_eq!((ctx.width, ctx.height), (512, 512));
+        assert!((ctx.roi_meters.width() - 512.0 * 30.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,121 @@
+use crate::core::soil::SoilLayerKey;
+use serde::{Deserialize, Serialize};
+
+/// Storage type of a grid layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LayerKind {
+    /// f32 values, NaN where missing
+    Continuous,
+    /// u8 class codes, `None` where missing
+    Categorical,
+    /// Per-pixel flags, always complete
+    Mask,
+}
+
+/// Describes a grid layer independently of where its values come from.
+#[derive(Debug, Clone)]
+pub struct LayerMeta {
+    /// Stable identifier used in configuration and file names
+    pub name: String,
+    /// Human-readable name for progress and error messages
+    pub label: String,
+    pub kind: LayerKind,
+    pub units: String,
+    /// Median-filtered after void filling
+    pub median: bool,
+    pub soil: Option<SoilLayerKey>,
+}
+
+impl LayerMeta {
+    pub fn new(
+        name: impl Into<String>,
+        label: impl Into<String>,
+        kind: LayerKind,
+        units: impl Into<String>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            label: label.into(),
+            kind,
+            units: units.into(),
+            median: false,
+            soil: None,
+        }
+    }
+
+    pub fn soil(key: SoilLayerKey, units: impl Into<String>) -> Self {
+        Self {
+            // Topsoil is smoothed so texture changes do not speckle the surface.
+            median: key.smoothed(),
+            soil: Some(key),
+            ..Self::new(
+                format!("soil_{}_{}", key.property.dir_name(), key.depth.dir_name()),
+                key.to_string(),
+                LayerKind::Continuous,
+                units,
+            )
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LayerData {
+    Continuous(Vec<f32>),
+    Categorical(Vec<Option<u8>>),
+    Mask(Vec<bool>),
+}
+
+impl LayerData {
+    /// A layer of `len` missing values.
+    pub fn empty(kind: LayerKind, len: usize) -> Self {
+        match kind {
+            LayerKind::Continuous => Self::Continuous(vec![f32::NAN; len]),
+            LayerKind::Categorical => Self::Categorical(vec![None; len]),
+            LayerKind::Mask => Self::Mask(vec![false; len]),
+        }
+    }
+}
+
+/// A layer's values, borrowed.
+#[derive(Clone, Copy)]
+pub enum LayerSlice<'a> {
+    Continuous(&'a [f32]),
+    Categorical(&'a [Option<u8>]),
+    Mask(&'a [bool]),
+}
+
+impl LayerData {
+    pub fn as_slice(&self) -> LayerSlice<'_> {
+        match self {
+            Self::Continuous(v) => LayerSlice::Continuous(v),
+            Self::Categorical(v) => LayerSlice::Categorical(v),
+            Self::Mask(v) => LayerSlice::Mask(v),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Layer {
+    pub meta: LayerMeta,
+    pub data: LayerData,
+}
+
+/// One row of one layer, borrowed mutably for parallel writes.
+pub enum LayerRowMut<'a> {
+    Continuous(&'a mut [f32]),
+    Categorical(&'a mut [Option<u8>]),
+    Mask(&'a mut [bool]),
+}
+
+impl LayerRowMut<'_> {
+    /// Stores a sampled value, converted to the layer's storage type.
+    #[inline]
+    pub fn set(&mut self, x: usize, value: Option<f32>) {
+        match self {
+            Self::Continuous(row) => row[x] = value.unwrap_or(f32::NAN),
+            Self::Categorical(row) => row[x] = value.map(|v| v as u8),
+            Self::Mask(row) => row[x] = value.is_some_and(|v| v != 0.0),
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use geo::{BooleanOps, Coord, Intersects, MapCoords, MultiPolygon, Rect};
+
+const POLE_CLOSURE_TOLERANCE: f64 = 1.0;
+
+/// Wraps a longitude into `[-180, 180)`.
+#[inline]
+pub fn normalize_lon(lon: f64) -> f64 {
+    (lon + 180.0).rem_euclid(360.0) - 180.0
+}
+
+/// Checks a normalized coordinate against a rect that may extend past ±180.
+#[inline]
+pub fn wrapped_intersects(rect: &Rect<f64>, coord: &Coord<f64>) -> bool {
+    [0.0, 360.0, -360.0].iter().any(|shift| {
+        rect.intersects(&Coord {
+            x: coord.x + shift,
+            y: coord.y,
+        })
+    })
+}
+
+/// Removes ±360° jumps so consecutive vertices never differ by more than 180°
+/// in longitude. A ring that winds around a pole is closed through that pole.
+pub fn unwrap_ring(coords: Vec<Coord<f64>>) -> Vec<Coord<f64>> {
+    let Some(first) = coords.first().copied() else {
+        return coords;
+    };
+
+    let mut prev = first.x;
+    let mut ring: Vec<Coord<f64>> = coords
+        .into_iter()
+        .map(|c| {
+            let mut x = c.x;
+            while x - prev > 180.0 {
+                x -= 360.0;
+            }
+            while x - prev < -180.0 {
+                x += 360.0;
+            }
+            prev = x;
+            Coord { x, y: c.y }
+        })
+        .collect();
+
+    let last = ring[ring.len() - 1];
+    if (last.x - first.x).abs() > POLE_CLOSURE_TOLERANCE {
+        let mean_lat = ring.iter().map(|c| c.y).sum::<f64>() / ring.len() as f64;
+        let pole = 90.0_f64.copysign(mean_lat);
+        ring.push(Coord { x: last.x, y: pole });
+        ring.push(Coord {
+            x: first.x,
+            y: pole,
+        });
+        ring.push(first);
+    }
+
+    ring
+}
+
+/// Cuts geometry expressed in unwrapped longitudes at ±180° and shifts every
+/// piece back into `[-180, 180]`.
+pub fn split_antimeridian(geometry: &MultiPolygon<f64>) -> MultiPolygon<f64> {
+    let mut parts = Vec::new();
+
+    for shift in [-360.0, 0.0, 360.0] {
+        let window = Rect::new(
+            Coord {
+                x: -180.0 - shift,
+                y: -90.0,
+            },
+            Coord {
+                x: 180.0 - shift,
+                y: 90.0,
+            },
+        );
+        let piece = geometry.intersection(&MultiPolygon::from(window.to_polygon()));
+        parts.extend(piece.0.into_iter().map(|p| {
+            p.map_coords(|c| Coord {
+                x: c.x + shift,
+                y: c.y,
+            })
+        }));
+    }
+
+    MultiPolygon::new(parts)
+}
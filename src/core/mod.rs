@@ -1,6 +1,13 @@
+pub mod config;
 pub mod context;
+pub mod layer;
+pub mod lonlat;
 pub mod projection;
 pub mod raster;
+pub mod resampling;
+pub mod roi;
+pub mod soil;
 pub mod spatial;
 pub mod terrain;
+pub mod units;
 pub mod validator;
@@ -1,48 +1,599 @@
-use geo::Coord;
+use crate::core::lonlat::normalize_lon;
+use anyhow::{Result, anyhow};
+use gdal::spatial_ref::{AxisMappingStrategy, CoordTransform, SpatialRef};
+use geo::{Coord, Rect};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
 use std::f64::consts::PI;
+use std::fmt;
+use std::sync::Arc;
 
 const EARTH_RADIUS: f64 = 6378137.0;
+const WGS84_FLATTENING: f64 = 1.0 / 298.257223563;
+const HIGH_LATITUDE: f64 = 60.0;
+const CONVERGENCE_STEP: f64 = 1e-4;
+const UTM_SCALE: f64 = 0.9996;
+const UTM_FALSE_EASTING: f64 = 500_000.0;
+const UTM_FALSE_NORTHING_SOUTH: f64 = 10_000_000.0;
+
+/// Maps geographic coordinates to a planar frame in meters around the ROI.
+pub trait LocalProjection: fmt::Display + Send + Sync {
+    fn project(&self, lon: f64, lat: f64) -> Coord<f64>;
+
+    fn unproject(&self, x: f64, y: f64) -> Coord<f64>;
+
+    /// PROJ string or `EPSG:` code of the planar frame, for georeferencing
+    /// exported rasters.
+    fn crs(&self) -> String;
+
+    /// Angle in radians from true north to grid north at the point.
+    fn convergence_angle(&self, lon: f64, lat: f64) -> f64 {
+        numeric_convergence(self, lon, lat)
+    }
+}
+
+/// Convergence from the projected direction of a short step along the meridian.
+fn numeric_convergence<P: LocalProjection + ?Sized>(projection: &P, lon: f64, lat: f64) -> f64 {
+    let (lat_a, lat_b) = if lat + CONVERGENCE_STEP > 90.0 {
+        (lat - CONVERGENCE_STEP, lat)
+    } else {
+        (lat, lat + CONVERGENCE_STEP)
+    };
+    let a = projection.project(lon, lat_a);
+    let b = projection.project(lon, lat_b);
+
+    (b.x - a.x).atan2(b.y - a.y)
+}
+
+/// Which projection the terrain grid is laid out in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProjectionKind {
+    /// Spherical equirectangular, or azimuthal equidistant at high latitudes
+    Adaptive,
+    /// Ellipsoidal Transverse Mercator centred on the ROI
+    TransverseMercator,
+    /// The UTM zone containing the ROI centre
+    Utm,
+    /// Any projected CRS with metre units, through GDAL
+    Epsg(u32),
+}
+
+impl ProjectionKind {
+    pub fn build(self, center: Coord<f64>) -> Result<Arc<dyn LocalProjection>> {
+        Ok(match self {
+            Self::Adaptive => Arc::new(AdaptiveLtm::new(center)),
+            Self::TransverseMercator => Arc::new(TransverseMercator::local(center)),
+            Self::Utm => Arc::new(TransverseMercator::utm(center)),
+            Self::Epsg(code) => Arc::new(GdalProjection::new(code)?),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LtmKind {
+    Equirectangular,
+    /// Oblique azimuthal equidistant, which stays valid up to and across the poles
+    Azimuthal,
+}
 
 pub struct AdaptiveLtm {
     center_lon: f64,
     center_lat: f64,
     cos_lat: f64,
+    sin_lat: f64,
+    kind: LtmKind,
 }
 
 impl AdaptiveLtm {
     pub fn new(center: Coord<f64>) -> Self {
+        let phi = center.y * PI / 180.0;
+        let kind = if center.y.abs() > HIGH_LATITUDE {
+            LtmKind::Azimuthal
+        } else {
+            LtmKind::Equirectangular
+        };
+
         Self {
-            center_lon: center.x,
+            center_lon: normalize_lon(center.x),
             center_lat: center.y,
-            cos_lat: (center.y * PI / 180.0).cos(),
+            cos_lat: phi.cos(),
+            sin_lat: phi.sin(),
+            kind,
         }
     }
+}
+
+impl LocalProjection for AdaptiveLtm {
+    fn project(&self, lon: f64, lat: f64) -> Coord<f64> {
+        let d_lon = normalize_lon(lon - self.center_lon).to_radians();
+
+        match self.kind {
+            LtmKind::Equirectangular => {
+                let d_lat = (lat - self.center_lat).to_radians();
 
-    pub fn project(&self, lon: f64, lat: f64) -> Coord<f64> {
-        let d_lon = (lon - self.center_lon).to_radians();
-        let d_lat = (lat - self.center_lat).to_radians();
+                let x = d_lon * EARTH_RADIUS * self.cos_lat;
+                let y = d_lat * EARTH_RADIUS;
 
-        let x = d_lon * EARTH_RADIUS * self.cos_lat;
-        let y = d_lat * EARTH_RADIUS;
+                Coord { x, y }
+            }
+            LtmKind::Azimuthal => {
+                let phi = lat.to_radians();
+                let cos_c = (self.sin_lat * phi.sin() + self.cos_lat * phi.cos() * d_lon.cos())
+                    .clamp(-1.0, 1.0);
+                let c = cos_c.acos();
+                let k = if c < 1e-12 { 1.0 } else { c / c.sin() };
 
-        Coord { x, y }
+                let x = EARTH_RADIUS * k * phi.cos() * d_lon.sin();
+                let y = EARTH_RADIUS
+                    * k
+                    * (self.cos_lat * phi.sin() - self.sin_lat * phi.cos() * d_lon.cos());
+
+                Coord { x, y }
+            }
+        }
     }
 
-    pub fn unproject(&self, x: f64, y: f64) -> Coord<f64> {
-        let x_raw = x / self.cos_lat;
-        let d_lon = (x_raw / EARTH_RADIUS).to_degrees();
-        let d_lat = (y / EARTH_RADIUS).to_degrees();
+    fn unproject(&self, x: f64, y: f64) -> Coord<f64> {
+        match self.kind {
+            LtmKind::Equirectangular => {
+                let x_raw = x / self.cos_lat;
+                let d_lon = (x_raw / EARTH_RADIUS).to_degrees();
+                let d_lat = (y / EARTH_RADIUS).to_degrees();
+
+                Coord {
+                    x: normalize_lon(self.center_lon + d_lon),
+                    y: self.center_lat + d_lat,
+                }
+            }
+            LtmKind::Azimuthal => {
+                let rho = (x * x + y * y).sqrt();
+                if rho < 1e-9 {
+                    return Coord {
+                        x: self.center_lon,
+                        y: self.center_lat,
+                    };
+                }
+                let c = rho / EARTH_RADIUS;
+                let (sin_c, cos_c) = c.sin_cos();
+
+                let phi = (cos_c * self.sin_lat + y * sin_c * self.cos_lat / rho)
+                    .clamp(-1.0, 1.0)
+                    .asin();
+                let d_lon =
+                    (x * sin_c).atan2(rho * self.cos_lat * cos_c - y * self.sin_lat * sin_c);
+
+                Coord {
+                    x: normalize_lon(self.center_lon + d_lon.to_degrees()),
+                    y: phi.to_degrees(),
+                }
+            }
+        }
+    }
+
+    fn crs(&self) -> String {
+        match self.kind {
+            // Equidistant cylindrical stays spherical on the WGS84 datum, scaled
+            // by the semi-major axis like the formulas above.
+            LtmKind::Equirectangular => format!(
+                "+proj=eqc +lat_ts={lat} +lat_0={lat} +lon_0={lon} +x_0=0 +y_0=0 \
+                 +ellps=WGS84 +units=m +no_defs",
+                lat = self.center_lat,
+                lon = self.center_lon
+            ),
+            LtmKind::Azimuthal => format!(
+                "+proj=aeqd +lat_0={} +lon_0={} +x_0=0 +y_0=0 +R={} +units=m +no_defs",
+                self.center_lat, self.center_lon, EARTH_RADIUS
+            ),
+        }
+    }
+
+    fn convergence_angle(&self, lon: f64, lat: f64) -> f64 {
+        match self.kind {
+            LtmKind::Equirectangular => {
+                let d_lon = normalize_lon(lon - self.center_lon).to_radians();
+                let phi = lat.to_radians();
+
+                d_lon * phi.sin()
+            }
+            LtmKind::Azimuthal => numeric_convergence(self, lon, lat),
+        }
+    }
+}
+
+impl fmt::Display for AdaptiveLtm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            LtmKind::Equirectangular => write!(f, "Local equirectangular (spherical)"),
+            LtmKind::Azimuthal => write!(f, "Local azimuthal equidistant (spherical)"),
+        }
+    }
+}
+
+/// Ellipsoidal Transverse Mercator on WGS84, using the Krüger series to
+/// fourth order in the third flattening, accurate to well below a millimetre
+/// within a few thousand kilometres of the central meridian.
+pub struct TransverseMercator {
+    center_lon: f64,
+    scale: f64,
+    false_easting: f64,
+    false_northing: f64,
+    /// Rectifying radius, the meridian arc length per radian of rectifying latitude
+    radius: f64,
+    /// `2 * sqrt(n) / (1 + n)` for the conformal latitude
+    e_conformal: f64,
+    alpha: [f64; 4],
+    beta: [f64; 4],
+    delta: [f64; 4],
+    label: String,
+    /// Set for the standard UTM zones
+    epsg: Option<u32>,
+}
+
+impl TransverseMercator {
+    fn new(
+        center_lon: f64,
+        scale: f64,
+        false_easting: f64,
+        false_northing: f64,
+        label: String,
+    ) -> Self {
+        let n = WGS84_FLATTENING / (2.0 - WGS84_FLATTENING);
+        let (n2, n3, n4) = (n * n, n * n * n, n * n * n * n);
+
+        Self {
+            center_lon: normalize_lon(center_lon),
+            scale,
+            false_easting,
+            false_northing,
+            radius: EARTH_RADIUS / (1.0 + n) * (1.0 + n2 / 4.0 + n4 / 64.0),
+            e_conformal: 2.0 * n.sqrt() / (1.0 + n),
+            alpha: [
+                n / 2.0 - 2.0 / 3.0 * n2 + 5.0 / 16.0 * n3 + 41.0 / 180.0 * n4,
+                13.0 / 48.0 * n2 - 3.0 / 5.0 * n3 + 557.0 / 1440.0 * n4,
+                61.0 / 240.0 * n3 - 103.0 / 140.0 * n4,
+                49561.0 / 161280.0 * n4,
+            ],
+            beta: [
+                n / 2.0 - 2.0 / 3.0 * n2 + 37.0 / 96.0 * n3 - 1.0 / 360.0 * n4,
+                1.0 / 48.0 * n2 + 1.0 / 15.0 * n3 - 437.0 / 1440.0 * n4,
+                17.0 / 480.0 * n3 - 37.0 / 840.0 * n4,
+                4397.0 / 161280.0 * n4,
+            ],
+            delta: [
+                2.0 * n - 2.0 / 3.0 * n2 - 2.0 * n3 + 116.0 / 45.0 * n4,
+                7.0 / 3.0 * n2 - 8.0 / 5.0 * n3 - 227.0 / 45.0 * n4,
+                56.0 / 15.0 * n3 - 136.0 / 35.0 * n4,
+                4279.0 / 630.0 * n4,
+            ],
+            label,
+            epsg: None,
+        }
+    }
+
+    /// True scale along the meridian through the ROI centre, with the origin
+    /// at the centre.
+    pub fn local(center: Coord<f64>) -> Self {
+        let lon_0 = normalize_lon(center.x);
+        let label = format!("Transverse Mercator (lon_0 = {lon_0:.4})");
+        let mut tm = Self::new(lon_0, 1.0, 0.0, 0.0, label);
+        tm.false_northing = -tm.project(lon_0, center.y).y;
+        tm
+    }
+
+    /// The standard UTM zone containing the ROI centre.
+    pub fn utm(center: Coord<f64>) -> Self {
+        let zone = utm_zone(center.x);
+        let north = center.y >= 0.0;
+        let false_northing = if north { 0.0 } else { UTM_FALSE_NORTHING_SOUTH };
+        let label = format!("UTM zone {}{}", zone, if north { 'N' } else { 'S' });
+
+        Self {
+            epsg: Some(if north { 32600 } else { 32700 } + zone),
+            ..Self::new(
+                zone as f64 * 6.0 - 183.0,
+                UTM_SCALE,
+                UTM_FALSE_EASTING,
+                false_northing,
+                label,
+            )
+        }
+    }
+}
+
+impl LocalProjection for TransverseMercator {
+    fn project(&self, lon: f64, lat: f64) -> Coord<f64> {
+        let lambda = normalize_lon(lon - self.center_lon).to_radians();
+        let sin_phi = lat.to_radians().sin();
+
+        let t = (sin_phi.atanh() - self.e_conformal * (self.e_conformal * sin_phi).atanh()).sinh();
+        let xi_p = t.atan2(lambda.cos());
+        let eta_p = (lambda.sin() / (1.0 + t * t).sqrt()).atanh();
+
+        let mut xi = xi_p;
+        let mut eta = eta_p;
+        for (j, a) in self.alpha.iter().enumerate() {
+            let k = 2.0 * (j + 1) as f64;
+            xi += a * (k * xi_p).sin() * (k * eta_p).cosh();
+            eta += a * (k * xi_p).cos() * (k * eta_p).sinh();
+        }
+
+        Coord {
+            x: self.false_easting + self.scale * self.radius * eta,
+            y: self.false_northing + self.scale * self.radius * xi,
+        }
+    }
+
+    fn unproject(&self, x: f64, y: f64) -> Coord<f64> {
+        let xi = (y - self.false_northing) / (self.scale * self.radius);
+        let eta = (x - self.false_easting) / (self.scale * self.radius);
+
+        let mut xi_p = xi;
+        let mut eta_p = eta;
+        for (j, b) in self.beta.iter().enumerate() {
+            let k = 2.0 * (j + 1) as f64;
+            xi_p -= b * (k * xi).sin() * (k * eta).cosh();
+            eta_p -= b * (k * xi).cos() * (k * eta).sinh();
+        }
+
+        let chi = (xi_p.sin() / eta_p.cosh()).clamp(-1.0, 1.0).asin();
+        let mut phi = chi;
+        for (j, d) in self.delta.iter().enumerate() {
+            phi += d * (2.0 * (j + 1) as f64 * chi).sin();
+        }
+        let lambda = eta_p.sinh().atan2(xi_p.cos());
 
         Coord {
-            x: self.center_lon + d_lon,
-            y: self.center_lat + d_lat,
+            x: normalize_lon(self.center_lon + lambda.to_degrees()),
+            y: phi.to_degrees(),
+        }
+    }
+
+    fn crs(&self) -> String {
+        match self.epsg {
+            Some(code) => format!("EPSG:{code}"),
+            None => format!(
+                "+proj=tmerc +lat_0=0 +lon_0={} +k_0={} +x_0={} +y_0={} \
+                 +ellps=WGS84 +units=m +no_defs",
+                self.center_lon, self.scale, self.false_easting, self.false_northing
+            ),
+        }
+    }
+}
+
+impl fmt::Display for TransverseMercator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.label)
+    }
+}
+
+fn utm_zone(lon: f64) -> u32 {
+    (((normalize_lon(lon) + 180.0) / 6.0).floor() as u32).min(59) + 1
+}
+
+thread_local! {
+    /// GDAL transformations cannot be shared across threads, so each thread
+    /// keeps its own forward and inverse pair per EPSG code.
+    static GDAL_TRANSFORMS: RefCell<HashMap<u32, (CoordTransform, CoordTransform)>> =
+        RefCell::new(HashMap::new());
+}
+
+/// A projected CRS from the GDAL/PROJ database.
+pub struct GdalProjection {
+    epsg: u32,
+    name: String,
+}
+
+impl GdalProjection {
+    pub fn new(epsg: u32) -> Result<Self> {
+        let srs = SpatialRef::from_epsg(epsg)?;
+        if !srs.is_projected() {
+            return Err(anyhow!("EPSG:{epsg} is not a projected coordinate system"));
         }
+        if (srs.linear_units() - 1.0).abs() > 1e-9 {
+            return Err(anyhow!(
+                "EPSG:{epsg} uses {} units, only metres are supported",
+                srs.linear_units_name().unwrap_or_default()
+            ));
+        }
+        gdal_transforms(epsg)?;
+
+        Ok(Self {
+            epsg,
+            name: srs.name().unwrap_or_default(),
+        })
     }
 
-    pub fn convergence_angle(&self, lon: f64, lat: f64) -> f64 {
-        let d_lon = (lon - self.center_lon).to_radians();
-        let phi = lat.to_radians();
+    fn transform(&self, inverse: bool, x: f64, y: f64) -> Coord<f64> {
+        GDAL_TRANSFORMS.with(|cache| {
+            let mut cache = cache.borrow_mut();
+            let (forward, backward) = match cache.entry(self.epsg) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => e.insert(
+                    gdal_transforms(self.epsg).expect("Transform was validated on construction"),
+                ),
+            };
+            let transform = if inverse { backward } else { forward };
+
+            let (mut xs, mut ys) = ([x], [y]);
+            match transform.transform_coords(&mut xs, &mut ys, &mut []) {
+                Ok(()) => Coord { x: xs[0], y: ys[0] },
+                Err(_) => Coord {
+                    x: f64::NAN,
+                    y: f64::NAN,
+                },
+            }
+        })
+    }
+}
 
-        d_lon * phi.sin()
+fn gdal_transforms(epsg: u32) -> Result<(CoordTransform, CoordTransform)> {
+    let mut wgs84 = SpatialRef::from_epsg(4326)?;
+    wgs84.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+    let mut target = SpatialRef::from_epsg(epsg)?;
+    target.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+
+    Ok((
+        CoordTransform::new(&wgs84, &target)?,
+        CoordTransform::new(&target, &wgs84)?,
+    ))
+}
+
+impl LocalProjection for GdalProjection {
+    fn project(&self, lon: f64, lat: f64) -> Coord<f64> {
+        self.transform(false, normalize_lon(lon), lat)
+    }
+
+    fn unproject(&self, x: f64, y: f64) -> Coord<f64> {
+        self.transform(true, x, y)
+    }
+
+    fn crs(&self) -> String {
+        format!("EPSG:{}", self.epsg)
+    }
+}
+
+impl fmt::Display for GdalProjection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EPSG:{} ({})", self.epsg, self.name)
+    }
+}
+
+/// Largest deviation of the point scale from 1 across the ROI.
+#[derive(Clone, Copy)]
+pub struct ScaleDistortion {
+    /// Relative length error, e.g. `0.001` for 1 mm per metre
+    pub max_error: f64,
+    pub at: Coord<f64>,
+}
+
+/// Samples the point scale along meridians and parallels on a grid over the
+/// ROI, comparing projected lengths of short steps to their lengths on the
+/// WGS84 ellipsoid.
+pub fn scale_distortion(projection: &dyn LocalProjection, roi: Rect<f64>) -> ScaleDistortion {
+    const SAMPLES: usize = 16;
+    const STEP_DEG: f64 = 1e-5;
+
+    let e2 = WGS84_FLATTENING * (2.0 - WGS84_FLATTENING);
+    let mut worst = ScaleDistortion {
+        max_error: 0.0,
+        at: roi.center(),
+    };
+
+    for i in 0..=SAMPLES {
+        for j in 0..=SAMPLES {
+            let lon = roi.min().x + roi.width() * i as f64 / SAMPLES as f64;
+            let lat = (roi.min().y + roi.height() * j as f64 / SAMPLES as f64)
+                .clamp(-90.0 + STEP_DEG, 90.0 - STEP_DEG);
+
+            let phi = lat.to_radians();
+            let w = (1.0 - e2 * phi.sin().powi(2)).sqrt();
+            let meridian_radius = EARTH_RADIUS * (1.0 - e2) / (w * w * w);
+            let parallel_radius = EARTH_RADIUS / w * phi.cos();
+
+            let origin = projection.project(lon, lat);
+            let north = projection.project(lon, lat + STEP_DEG);
+            let east = projection.project(lon + STEP_DEG, lat);
+            let step = STEP_DEG.to_radians();
+
+            let h = distance(origin, north) / (meridian_radius * step);
+            let mut error = (h - 1.0).abs();
+            if parallel_radius > 1.0 {
+                let k = distance(origin, east) / (parallel_radius * step);
+                error = error.max((k - 1.0).abs());
+            }
+
+            if error > worst.max_error {
+                worst = ScaleDistortion {
+                    max_error: error,
+                    at: Coord { x: lon, y: lat },
+                };
+            }
+        }
+    }
+
+    worst
+}
+
+fn distance(a: Coord<f64>, b: Coord<f64>) -> f64 {
+    (b.x - a.x).hypot(b.y - a.y)
+}
+
+impl fmt::Display for ScaleDistortion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Max Scale Distortion: {:.4}% ({:.0} ppm) at {:.5}, {:.5}",
+            self.max_error * 100.0,
+            self.max_error * 1e6,
+            normalize_lon(self.at.x),
+            self.at.y
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_round_trip(projection: &dyn LocalProjection, lon: f64, lat: f64) {
+        let p = projection.project(lon, lat);
+        let back = projection.unproject(p.x, p.y);
+        assert!(
+            (back.x - lon).abs() < 1e-9 && (back.y - lat).abs() < 1e-9,
+            "{projection}: ({lon}, {lat}) came back as ({}, {})",
+            back.x,
+            back.y
+        );
+    }
+
+    #[test]
+    fn local_tm_round_trips_around_its_centre() {
+        let center = Coord { x: 116.4, y: 39.9 };
+        let tm = TransverseMercator::local(center);
+        let origin = tm.project(center.x, center.y);
+        assert!(origin.x.abs() < 1e-6 && origin.y.abs() < 1e-6);
+
+        for (dlon, dlat) in [(0.0, 0.0), (2.5, 1.0), (-3.0, -2.0), (1.0, 10.0)] {
+            assert_round_trip(&tm, center.x + dlon, center.y + dlat);
+        }
+    }
+
+    #[test]
+    fn local_tm_round_trips_across_the_antimeridian() {
+        let tm = TransverseMercator::local(Coord { x: 179.5, y: -17.0 });
+        assert_round_trip(&tm, -179.5, -16.0);
+        assert!(tm.project(-179.5, -17.0).x > 0.0);
+    }
+
+    #[test]
+    fn local_tm_normalizes_its_meridian() {
+        let tm = TransverseMercator::local(Coord { x: 190.0, y: 10.0 });
+        assert_eq!(tm.to_string(), "Transverse Mercator (lon_0 = -170.0000)");
+        assert!(tm.crs().contains("+lon_0=-170 "));
+        let origin = tm.project(-170.0, 10.0);
+        assert!(origin.x.abs() < 1e-6 && origin.y.abs() < 1e-6);
+    }
+
+    #[test]
+    fn utm_matches_zone_origin() {
+        let utm = TransverseMercator::utm(Coord { x: 4.0, y: 1.0 });
+        assert_eq!(utm.crs(), "EPSG:32631");
+        let p = utm.project(3.0, 0.0);
+        assert!((p.x - UTM_FALSE_EASTING).abs() < 1e-6 && p.y.abs() < 1e-6);
+    }
+
+    #[test]
+    fn utm_round_trips_in_both_hemispheres() {
+        for center in [Coord { x: 9.2, y: 48.1 }, Coord { x: 151.2, y: -33.9 }] {
+            let utm = TransverseMercator::utm(center);
+            for (dlon, dlat) in [(0.0, 0.0), (2.9, 0.5), (-2.9, -0.5)] {
+                assert_round_trip(&utm, center.x + dlon, center.y + dlat);
+            }
+        }
+        assert_eq!(
+            TransverseMercator::utm(Coord { x: 151.2, y: -33.9 }).crs(),
+            "EPSG:32756"
+        );
     }
 }
@@ -1,7 +1,10 @@
 pub trait PixelSource {
     fn read_at(&self, col: isize, row: isize) -> Option<f32>;
-    fn width(&self) -> usize;
-    fn height(&self) -> usize;
+
+    /// Size of one output pixel measured in source pixels.
+    fn footprint(&self) -> f64 {
+        1.0
+    }
 }
 
 pub trait Interpolator: Send + Sync {
@@ -42,50 +45,221 @@ impl Interpolator for Bilinear {
     }
 }
 
-pub struct Bicubic;
+/// Box filter over the output pixel footprint when it spans more than one
+/// source pixel, averaging only valid taps; otherwise defers to the inner
+/// interpolator.
+pub struct AreaAverage<T>(pub T);
 
-impl Interpolator for Bicubic {
+impl<T: Interpolator> Interpolator for AreaAverage<T> {
     fn sample(&self, source: &dyn PixelSource, u: f64, v: f64) -> Option<f32> {
-        let x = u.floor();
-        let y = v.floor();
-        let dx = (u - x) as f32;
-        let dy = (v - y) as f32;
-        let col = x as isize;
-        let row = y as isize;
+        const MAX_FOOTPRINT: f64 = 64.0;
+
+        let footprint = source.footprint().min(MAX_FOOTPRINT);
+        if footprint <= 1.0 {
+            return self.0.sample(source, u, v);
+        }
 
-        if is_boundary(col, row, source.width(), source.height()) {
-            return Bilinear.sample(source, u, v);
+        let half = footprint / 2.0;
+        let mut sum = 0.0;
+        let mut count = 0;
+        for row in (v - half).ceil() as isize..=(v + half).floor() as isize {
+            for col in (u - half).ceil() as isize..=(u + half).floor() as isize {
+                if let Some(val) = source.read_at(col, row) {
+                    sum += val;
+                    count += 1;
+                }
+            }
         }
 
-        let mut window = [[0.0; 4]; 4];
+        (count > 0).then(|| sum / count as f32)
+    }
+}
+
+/// Returns nodata wherever the nearest source pixel is nodata, so voids keep
+/// their exact extent instead of being bridged by the inner interpolator.
+pub struct Strict<T>(pub T);
+
+impl<T: Interpolator> Interpolator for Strict<T> {
+    fn sample(&self, source: &dyn PixelSource, u: f64, v: f64) -> Option<f32> {
+        NearestNeighbor.sample(source, u, v)?;
+        self.0.sample(source, u, v)
+    }
+}
+
+/// Falls back to the nearest pixel where the inner interpolator finds too few
+/// valid taps, e.g. bilinear next to a void or a tile edge.
+pub struct NearestFallback<T>(pub T);
+
+impl<T: Interpolator> Interpolator for NearestFallback<T> {
+    fn sample(&self, source: &dyn PixelSource, u: f64, v: f64) -> Option<f32> {
+        self.0
+            .sample(source, u, v)
+            .or_else(|| NearestNeighbor.sample(source, u, v))
+    }
+}
+
+/// Box-filters the source over `radius` pixels before the inner interpolator
+/// sees it, e.g. to suppress speckle in radar backscatter.
+pub struct PreSmooth<T> {
+    pub inner: T,
+    pub radius: isize,
+}
+
+impl<T: Interpolator> Interpolator for PreSmooth<T> {
+    fn sample(&self, source: &dyn PixelSource, u: f64, v: f64) -> Option<f32> {
+        let smoothed = BoxFiltered {
+            source,
+            radius: self.radius,
+        };
+        self.inner.sample(&smoothed, u, v)
+    }
+}
+
+struct BoxFiltered<'a> {
+    source: &'a dyn PixelSource,
+    radius: isize,
+}
 
-        for j in 0..4 {
-            for i in 0..4 {
-                if let Some(val) = source.read_at(col - 1 + i, row - 1 + j) {
-                    window[j as usize][i as usize] = val;
-                } else {
-                    return None;
+impl PixelSource for BoxFiltered<'_> {
+    fn read_at(&self, col: isize, row: isize) -> Option<f32> {
+        self.source.read_at(col, row)?;
+
+        let mut sum = 0.0;
+        let mut count = 0;
+        for r in row - self.radius..=row + self.radius {
+            for c in col - self.radius..=col + self.radius {
+                if let Some(val) = self.source.read_at(c, r) {
+                    sum += val;
+                    count += 1;
                 }
             }
         }
+        Some(sum / count as f32)
+    }
 
-        let mut col_results = [0.0; 4];
-        for j in 0..4 {
-            col_results[j] = cubic_hermite(window[j], dx);
+    fn footprint(&self) -> f64 {
+        self.source.footprint()
+    }
+}
+
+impl<T: Interpolator + ?Sized> Interpolator for Box<T> {
+    fn sample(&self, source: &dyn PixelSource, u: f64, v: f64) -> Option<f32> {
+        (**self).sample(source, u, v)
+    }
+}
+
+/// Catmull-Rom bicubic that skips nodata taps and renormalizes the remaining
+/// weights, so voids do not grow by the kernel radius.
+pub struct Bicubic;
+
+impl Interpolator for Bicubic {
+    fn sample(&self, source: &dyn PixelSource, u: f64, v: f64) -> Option<f32> {
+        convolve(source, u, v, 2, catmull_rom)
+    }
+}
+
+/// Lanczos-3 windowed sinc over a 6x6 neighbourhood, renormalized over valid taps.
+pub struct Lanczos3;
+
+impl Interpolator for Lanczos3 {
+    fn sample(&self, source: &dyn PixelSource, u: f64, v: f64) -> Option<f32> {
+        convolve(source, u, v, 3, lanczos3)
+    }
+}
+
+/// Majority class weighted by how much of the output pixel footprint each
+/// source pixel covers; for categorical layers.
+pub struct Mode;
+
+impl Interpolator for Mode {
+    fn sample(&self, source: &dyn PixelSource, u: f64, v: f64) -> Option<f32> {
+        const MAX_FOOTPRINT: f64 = 64.0;
+
+        let half = source.footprint().clamp(1.0, MAX_FOOTPRINT) / 2.0;
+        let overlap = |center: isize, lo: f64, hi: f64| {
+            let c = center as f64;
+            ((c + 0.5).min(hi) - (c - 0.5).max(lo)).max(0.0)
+        };
+
+        let mut votes: Vec<(f32, f64)> = Vec::new();
+        for row in (v - half).round() as isize..=(v + half).round() as isize {
+            let wy = overlap(row, v - half, v + half);
+            if wy <= 0.0 {
+                continue;
+            }
+            for col in (u - half).round() as isize..=(u + half).round() as isize {
+                let wx = overlap(col, u - half, u + half);
+                if wx <= 0.0 {
+                    continue;
+                }
+                let Some(class) = source.read_at(col, row) else {
+                    continue;
+                };
+                match votes.iter_mut().find(|(c, _)| *c == class) {
+                    Some((_, w)) => *w += wx * wy,
+                    None => votes.push((class, wx * wy)),
+                }
+            }
         }
-        Some(cubic_hermite(col_results, dy))
+
+        votes
+            .into_iter()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(class, _)| class)
     }
 }
 
-fn is_boundary(col: isize, row: isize, w: usize, h: usize) -> bool {
-    col < 1 || row < 1 || col >= (w as isize - 2) || row >= (h as isize - 2)
+/// Separable convolution with taps `1 - radius..=radius` around the sample
+/// point, dropping nodata taps and renormalizing over the rest.
+fn convolve(
+    source: &dyn PixelSource,
+    u: f64,
+    v: f64,
+    radius: isize,
+    kernel: fn(f64) -> f64,
+) -> Option<f32> {
+    const MIN_WEIGHT: f64 = 1e-3;
+
+    let col0 = u.floor() as isize;
+    let row0 = v.floor() as isize;
+
+    let mut sum = 0.0;
+    let mut weight_sum = 0.0;
+    for j in 1 - radius..=radius {
+        let row = row0 + j;
+        let wy = kernel(v - row as f64);
+        for i in 1 - radius..=radius {
+            let col = col0 + i;
+            if let Some(val) = source.read_at(col, row) {
+                let w = kernel(u - col as f64) * wy;
+                sum += w * val as f64;
+                weight_sum += w;
+            }
+        }
+    }
+
+    (weight_sum > MIN_WEIGHT).then(|| (sum / weight_sum) as f32)
 }
 
-fn cubic_hermite(p: [f32; 4], t: f32) -> f32 {
-    let a = -0.5 * p[0] + 1.5 * p[1] - 1.5 * p[2] + 0.5 * p[3];
-    let b = p[0] - 2.5 * p[1] + 2.0 * p[2] - 0.5 * p[3];
-    let c = -0.5 * p[0] + 0.5 * p[2];
-    let d = p[1];
+fn catmull_rom(x: f64) -> f64 {
+    let x = x.abs();
+    if x < 1.0 {
+        1.5 * x * x * x - 2.5 * x * x + 1.0
+    } else if x < 2.0 {
+        -0.5 * x * x * x + 2.5 * x * x - 4.0 * x + 2.0
+    } else {
+        0.0
+    }
+}
 
-    a * t * t * t + b * t * t + c * t + d
+fn lanczos3(x: f64) -> f64 {
+    const A: f64 = 3.0;
+    if x == 0.0 {
+        1.0
+    } else if x.abs() >= A {
+        0.0
+    } else {
+        let px = std::f64::consts::PI * x;
+        A * px.sin() * (px / A).sin() / (px * px)
+    }
 }
@@ -0,0 +1,122 @@
+use crate::core::raster::{
+    AreaAverage, Bicubic, Bilinear, Interpolator, Lanczos3, Mode, NearestFallback, NearestNeighbor,
+    PreSmooth, Strict,
+};
+use serde::Deserialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InterpolatorKind {
+    Nearest,
+    Bilinear,
+    Bicubic,
+    Lanczos3,
+    /// Footprint-weighted majority, for class codes
+    Mode,
+}
+
+/// What a sample does when some of the source pixels it reads are nodata.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodataPolicy {
+    /// Nodata wherever the nearest source pixel is nodata
+    Strict,
+    /// Whatever the interpolator makes of the valid pixels it reads
+    Tolerant,
+    /// As `Tolerant`, taking the nearest pixel where interpolation yields nothing
+    Fallback,
+}
+
+/// How a layer is resampled onto the output grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResamplingPolicy {
+    pub interpolator: InterpolatorKind,
+    pub nodata: NodataPolicy,
+    /// Box filter radius in source pixels applied before interpolating; 0 disables it
+    pub smoothing: u32,
+    /// Average over the output pixel where it spans several source pixels
+    pub area_average: bool,
+}
+
+impl ResamplingPolicy {
+    pub const fn new(interpolator: InterpolatorKind) -> Self {
+        Self {
+            interpolator,
+            nodata: NodataPolicy::Tolerant,
+            smoothing: 0,
+            area_average: false,
+        }
+    }
+
+    pub const fn area_averaged(self) -> Self {
+        Self {
+            area_average: true,
+            ..self
+        }
+    }
+
+    pub fn with_override(self, config: &ResamplingOverride) -> Self {
+        Self {
+            interpolator: config.interpolator.unwrap_or(self.interpolator),
+            nodata: config.nodata.unwrap_or(self.nodata),
+            smoothing: config.smoothing.unwrap_or(self.smoothing),
+            area_average: config.area_average.unwrap_or(self.area_average),
+        }
+    }
+
+    pub fn interpolator(&self) -> Box<dyn Interpolator> {
+        let base: Box<dyn Interpolator> = match self.interpolator {
+            InterpolatorKind::Nearest => Box::new(NearestNeighbor),
+            InterpolatorKind::Bilinear => Box::new(Bilinear),
+            InterpolatorKind::Bicubic => Box::new(Bicubic),
+            InterpolatorKind::Lanczos3 => Box::new(Lanczos3),
+            InterpolatorKind::Mode => Box::new(Mode),
+        };
+
+        let averaged: Box<dyn Interpolator> = if self.area_average {
+            Box::new(AreaAverage(base))
+        } else {
+            base
+        };
+
+        let smoothed: Box<dyn Interpolator> = if self.smoothing > 0 {
+            Box::new(PreSmooth {
+                inner: averaged,
+                radius: self.smoothing as isize,
+            })
+        } else {
+            averaged
+        };
+
+        match self.nodata {
+            NodataPolicy::Strict => Box::new(Strict(smoothed)),
+            NodataPolicy::Tolerant => smoothed,
+            NodataPolicy::Fallback => Box::new(NearestFallback(smoothed)),
+        }
+    }
+}
+
+impl fmt::Display for ResamplingPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}, {:?} nodata", self.interpolator, self.nodata)?;
+        if self.smoothing > 0 {
+            write!(f, ", smoothed over {} px", self.smoothing)?;
+        }
+        if self.area_average {
+            write!(f, ", area averaged")?;
+        }
+        Ok(())
+    }
+}
+
+/// Per-layer overrides read from the configuration file; unset fields keep
+/// the layer's default.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResamplingOverride {
+    pub interpolator: Option<InterpolatorKind>,
+    pub nodata: Option<NodataPolicy>,
+    pub smoothing: Option<u32>,
+    pub area_average: Option<bool>,
+}
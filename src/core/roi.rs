@@ -0,0 +1,354 @@
+use crate::core::context::SpatialContext;
+use crate::core::lonlat::{split_antimeridian, unwrap_ring};
+use anyhow::{Context, Result, anyhow};
+use gdal::Dataset;
+use gdal::spatial_ref::{AxisMappingStrategy, SpatialRef};
+use gdal::vector::{Geometry as OgrGeometry, LayerAccess};
+use geo::{
+    BoundingRect, Coord, Densify, Euclidean, Geometry, LineString, MapCoords, MultiPolygon,
+    Polygon, Rect,
+};
+use rayon::prelude::*;
+use std::fs;
+use std::path::Path;
+
+/// Longest polygon edge in degrees before projecting, so edges that are
+/// straight in degrees stay close to their true course in the grid.
+const MAX_EDGE_DEG: f64 = 0.01;
+
+/// Distance in pixels beyond the ROI polygon up to which the datasets are
+/// still sampled, so terrain sloping away from the edge starts from real data.
+pub const OUTSIDE_MARGIN: f32 = 96.0;
+
+/// Region of interest in degrees.
+#[derive(Debug, Clone)]
+pub struct Roi {
+    /// Rectangle the grid covers; `max().x` may pass 180 when the ROI crosses
+    /// the antimeridian
+    pub bounds: Rect<f64>,
+    /// Area the world is built for, in the same longitude range as `bounds`;
+    /// `None` when it is the whole rectangle
+    pub polygon: Option<MultiPolygon<f64>>,
+}
+
+impl Roi {
+    pub fn from_rect(bounds: Rect<f64>) -> Self {
+        Self {
+            bounds,
+            polygon: None,
+        }
+    }
+
+    /// Loads every polygon in a GeoJSON, KML or other OGR vector file, or the
+    /// single geometry in a `.wkt` file, which is taken to be in WGS84.
+    pub fn load(path: &Path) -> Result<Self> {
+        let geometries = if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("wkt"))
+        {
+            let text = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read ROI {:?}", path))?;
+            let geometry = OgrGeometry::from_wkt(text.trim())
+                .with_context(|| format!("Invalid WKT in ROI {:?}", path))?;
+            vec![geometry.to_geo()?]
+        } else {
+            read_vector(path)?
+        };
+
+        let polygons: Vec<Polygon<f64>> = geometries
+            .into_iter()
+            .flat_map(polygons_of)
+            .map(unwrap_polygon)
+            .collect();
+        if polygons.is_empty() {
+            return Err(anyhow!("No polygons in ROI {:?}", path));
+        }
+
+        let polygon = align_longitudes(polygons);
+        let bounds = polygon
+            .bounding_rect()
+            .with_context(|| format!("Empty polygon in ROI {:?}", path))?;
+
+        Ok(Self {
+            bounds,
+            polygon: Some(polygon),
+        })
+    }
+
+    /// The ROI as polygons split at the antimeridian, in the same longitude
+    /// range as tile footprints.
+    pub fn target(&self) -> MultiPolygon<f64> {
+        match &self.polygon {
+            Some(polygon) => split_antimeridian(polygon),
+            None => split_antimeridian(&MultiPolygon::from(self.bounds.to_polygon())),
+        }
+    }
+}
+
+/// Marks the pixels of the grid whose centres fall inside `polygon`.
+pub fn rasterize(polygon: &MultiPolygon<f64>, ctx: &SpatialContext) -> Vec<bool> {
+    let origin = ctx.roi_meters.min();
+
+    // Rings in pixel units, scanned with the even-odd rule so holes and
+    // separate parts need no special casing.
+    let edges: Vec<(Coord<f64>, Coord<f64>)> = Euclidean
+        .densify(polygon, MAX_EDGE_DEG)
+        .iter()
+        .flat_map(|p| std::iter::once(p.exterior()).chain(p.interiors()))
+        .flat_map(|ring| {
+            let points: Vec<Coord<f64>> = ring
+                .coords()
+                .map(|c| {
+                    let m = ctx.projection.project(c.x, c.y);
+                    Coord {
+                        x: (m.x - origin.x) / ctx.pixel_size,
+                        y: (m.y - origin.y) / ctx.pixel_size,
+                    }
+                })
+                .collect();
+            points.windows(2).map(|w| (w[0], w[1])).collect::<Vec<_>>()
+        })
+        .collect();
+
+    let mut inside = vec![false; ctx.width * ctx.height];
+    inside
+        .par_chunks_mut(ctx.width)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let yc = y as f64 + 0.5;
+            let mut crossings: Vec<f64> = edges
+                .iter()
+                .filter(|(a, b)| (a.y <= yc) != (b.y <= yc))
+                .map(|(a, b)| a.x + (yc - a.y) * (b.x - a.x) / (b.y - a.y))
+                .collect();
+            crossings.sort_by(f64::total_cmp);
+
+            for span in crossings.chunks_exact(2) {
+                let start = (span[0] - 0.5).ceil().clamp(0.0, row.len() as f64) as usize;
+                let end = (span[1] - 0.5).ceil().clamp(0.0, row.len() as f64) as usize;
+                row[start..end].fill(true);
+            }
+        });
+
+    inside
+}
+
+/// Two-pass chamfer distance in pixels from every pixel to the nearest one
+/// inside the ROI, `inside` being a mask from [`rasterize`].
+pub fn distance_to_inside(inside: &[bool], w: usize, h: usize) -> Vec<f32> {
+    const ORTHOGONAL: f32 = 1.0;
+    const DIAGONAL: f32 = std::f32::consts::SQRT_2;
+
+    let mut dist: Vec<f32> = inside
+        .iter()
+        .map(|&v| if v { 0.0 } else { f32::INFINITY })
+        .collect();
+
+    for y in 0..h {
+        for x in 0..w {
+            let mut d = dist[y * w + x];
+            if x > 0 {
+                d = d.min(dist[y * w + x - 1] + ORTHOGONAL);
+            }
+            if y > 0 {
+                d = d.min(dist[(y - 1) * w + x] + ORTHOGONAL);
+                if x > 0 {
+                    d = d.min(dist[(y - 1) * w + x - 1] + DIAGONAL);
+                }
+                if x + 1 < w {
+                    d = d.min(dist[(y - 1) * w + x + 1] + DIAGONAL);
+                }
+            }
+            dist[y * w + x] = d;
+        }
+    }
+
+    for y in (0..h).rev() {
+        for x in (0..w).rev() {
+            let mut d = dist[y * w + x];
+            if x + 1 < w {
+                d = d.min(dist[y * w + x + 1] + ORTHOGONAL);
+            }
+            if y + 1 < h {
+                d = d.min(dist[(y + 1) * w + x] + ORTHOGONAL);
+                if x + 1 < w {
+                    d = d.min(dist[(y + 1) * w + x + 1] + DIAGONAL);
+                }
+                if x > 0 {
+                    d = d.min(dist[(y + 1) * w + x - 1] + DIAGONAL);
+                }
+            }
+            dist[y * w + x] = d;
+        }
+    }
+
+    dist
+}
+
+fn read_vector(path: &Path) -> Result<Vec<Geometry<f64>>> {
+    let dataset = Dataset::open(path).with_context(|| format!("Failed to open ROI {:?}", path))?;
+
+    let mut wgs84 = SpatialRef::from_epsg(4326)?;
+    wgs84.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+
+    let mut geometries = Vec::new();
+    for mut layer in dataset.layers() {
+        for feature in layer.features() {
+            let Some(geometry) = feature.geometry() else {
+                continue;
+            };
+            let geometry = match geometry.spatial_ref() {
+                Some(_) => geometry.transform_to(&wgs84)?,
+                None => geometry.clone(),
+            };
+            geometries.push(geometry.to_geo()?);
+        }
+    }
+    Ok(geometries)
+}
+
+fn polygons_of(geometry: Geometry<f64>) -> Vec<Polygon<f64>> {
+    match geometry {
+        Geometry::Polygon(p) => vec![p],
+        Geometry::MultiPolygon(mp) => mp.0,
+        Geometry::GeometryCollection(gc) => gc.into_iter().flat_map(polygons_of).collect(),
+        _ => vec![],
+    }
+}
+
+fn unwrap_polygon(polygon: Polygon<f64>) -> Polygon<f64> {
+    let (exterior, interiors) = polygon.into_inner();
+    Polygon::new(
+        LineString(unwrap_ring(exterior.0)),
+        interiors
+            .into_iter()
+            .map(|ring| LineString(unwrap_ring(ring.0)))
+            .collect(),
+    )
+}
+
+/// Shifts polygons by whole turns so they sit next to the first one, with the
+/// western bound in `[-180, 180)`.
+fn align_longitudes(polygons: Vec<Polygon<f64>>) -> MultiPolygon<f64> {
+    let anchor = polygons[0].exterior().0.first().map_or(0.0, |c| c.x);
+    let shift_by = |dx: f64| {
+        move |c: Coord<f64>| Coord {
+            x: c.x + dx,
+            y: c.y,
+        }
+    };
+
+    let aligned = MultiPolygon::new(
+        polygons
+            .into_iter()
+            .map(|p| {
+                let x = p.exterior().0.first().map_or(anchor, |c| c.x);
+                p.map_coords(shift_by(((anchor - x) / 360.0).round() * 360.0))
+            })
+            .collect(),
+    );
+
+    match aligned.bounding_rect() {
+        Some(rect) => {
+            let turns = ((rect.min().x + 180.0) / 360.0).floor();
+            aligned.map_coords(shift_by(-turns * 360.0))
+        }
+        None => aligned,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::context::{Length, Snap};
+    use crate::core::projection::ProjectionKind;
+
+    const SIZE: usize = 20;
+
+    fn grid() -> SpatialContext {
+        SpatialContext::around(
+            Coord { x: 10.0, y: 45.0 },
+            Length::Blocks(SIZE),
+            Length::Blocks(SIZE),
+            100.0,
+            Snap::None,
+            ProjectionKind::Adaptive,
+        )
+        .unwrap()
+    }
+
+    /// Ring through the given grid pixel corners, in degrees.
+    fn ring(ctx: &SpatialContext, corners: &[(f64, f64)]) -> LineString<f64> {
+        let origin = ctx.roi_meters.min();
+        let mut coords: Vec<Coord<f64>> = corners
+            .iter()
+            .map(|&(x, y)| {
+                ctx.projection
+                    .unproject(origin.x + x * ctx.pixel_size, origin.y + y * ctx.pixel_size)
+            })
+            .collect();
+        coords.push(coords[0]);
+        LineString(coords)
+    }
+
+    fn rect(x0: f64, y0: f64, x1: f64, y1: f64) -> [(f64, f64); 4] {
+        [(x0, y0), (x1, y0), (x1, y1), (x0, y1)]
+    }
+
+    #[test]
+    fn rasterize_marks_pixel_centres_inside_and_leaves_holes() {
+        let ctx = grid();
+        let polygon = Polygon::new(
+            ring(&ctx, &rect(2.0, 3.0, 12.0, 9.0)),
+            vec![ring(&ctx, &rect(5.0, 5.0, 7.0, 6.0))],
+        );
+        let inside = rasterize(&MultiPolygon::new(vec![polygon]), &ctx);
+
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let in_outer = (2..12).contains(&x) && (3..9).contains(&y);
+                let in_hole = (5..7).contains(&x) && y == 5;
+                assert_eq!(inside[y * SIZE + x], in_outer && !in_hole, "pixel {x}, {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn rasterize_triangle_follows_its_diagonal() {
+        let ctx = grid();
+        let triangle = Polygon::new(ring(&ctx, &[(0.0, 0.0), (20.0, 0.0), (0.0, 20.0)]), vec![]);
+        let inside = rasterize(&MultiPolygon::new(vec![triangle]), &ctx);
+
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                // Pixel centres on the diagonal itself are left out.
+                if x + y != SIZE - 1 {
+                    assert_eq!(inside[y * SIZE + x], x + y < SIZE - 1, "pixel {x}, {y}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn distance_to_inside_uses_chamfer_steps() {
+        let (w, h) = (5, 4);
+        let mut inside = vec![false; w * h];
+        inside[w + 1] = true;
+        let dist = distance_to_inside(&inside, w, h);
+
+        let at = |x: usize, y: usize| dist[y * w + x];
+        let diagonal = std::f32::consts::SQRT_2;
+        assert_eq!(at(1, 1), 0.0);
+        assert_eq!(at(2, 1), 1.0);
+        assert_eq!(at(1, 3), 2.0);
+        assert_eq!(at(0, 0), diagonal);
+        assert_eq!(at(3, 2), 1.0 + diagonal);
+        assert_eq!(at(4, 3), 1.0 + 2.0 * diagonal);
+    }
+
+    #[test]
+    fn distance_to_inside_without_inside_pixels_is_infinite() {
+        let dist = distance_to_inside(&[false; 6], 3, 2);
+        assert!(dist.iter().all(|d| d.is_infinite()));
+    }
+}
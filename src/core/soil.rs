@@ -0,0 +1,175 @@
+use crate::core::units::{LayerUnits, UnitConversion};
+use anyhow::{Result, anyhow};
+use clap::ValueEnum;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, ValueEnum)]
+pub enum SoilProperty {
+    Bdod,
+    Cec,
+    Cfvo,
+    Clay,
+    Nitrogen,
+    Ocd,
+    Phh2o,
+    Sand,
+    Silt,
+    Soc,
+}
+
+impl SoilProperty {
+    pub const ALL: [SoilProperty; 10] = [
+        Self::Bdod,
+        Self::Cec,
+        Self::Cfvo,
+        Self::Clay,
+        Self::Nitrogen,
+        Self::Ocd,
+        Self::Phh2o,
+        Self::Sand,
+        Self::Silt,
+        Self::Soc,
+    ];
+
+    pub fn dir_name(self) -> &'static str {
+        match self {
+            Self::Bdod => "bdod",
+            Self::Cec => "cec",
+            Self::Cfvo => "cfvo",
+            Self::Clay => "clay",
+            Self::Nitrogen => "nitrogen",
+            Self::Ocd => "ocd",
+            Self::Phh2o => "phh2o",
+            Self::Sand => "sand",
+            Self::Silt => "silt",
+            Self::Soc => "soc",
+        }
+    }
+
+    /// SoilGrids stores integers in mapped units; loaded values are converted
+    /// to conventional units.
+    pub fn units(self) -> LayerUnits {
+        use UnitConversion::Scale;
+        match self {
+            Self::Bdod => LayerUnits::convert("cg/cm³", "kg/dm³", Scale(0.01)),
+            Self::Cec => LayerUnits::convert("mmol(c)/kg", "cmol(c)/kg", Scale(0.1)),
+            Self::Cfvo => LayerUnits::convert("cm³/dm³", "%", Scale(0.1)),
+            Self::Clay | Self::Sand | Self::Silt => LayerUnits::convert("g/kg", "%", Scale(0.1)),
+            Self::Nitrogen => LayerUnits::convert("cg/kg", "g/kg", Scale(0.01)),
+            Self::Ocd => LayerUnits::convert("hg/m³", "kg/m³", Scale(0.1)),
+            Self::Phh2o => LayerUnits::convert("pH×10", "pH", Scale(0.1)),
+            Self::Soc => LayerUnits::convert("dg/kg", "g/kg", Scale(0.1)),
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Bdod => "Bulk Density",
+            Self::Cec => "CEC",
+            Self::Cfvo => "Coarse Fragments",
+            Self::Clay => "Clay",
+            Self::Nitrogen => "Nitrogen",
+            Self::Ocd => "OC Density",
+            Self::Phh2o => "pH",
+            Self::Sand => "Sand",
+            Self::Silt => "Silt",
+            Self::Soc => "SOC",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SoilDepth {
+    pub top_cm: u16,
+    pub bottom_cm: u16,
+}
+
+impl SoilDepth {
+    pub const ALL: [SoilDepth; 6] = [
+        Self::new(0, 5),
+        Self::new(5, 15),
+        Self::new(15, 30),
+        Self::new(30, 60),
+        Self::new(60, 100),
+        Self::new(100, 200),
+    ];
+
+    pub const fn new(top_cm: u16, bottom_cm: u16) -> Self {
+        Self { top_cm, bottom_cm }
+    }
+
+    pub fn dir_name(self) -> String {
+        format!("{}-{}", self.top_cm, self.bottom_cm)
+    }
+
+    #[inline]
+    pub fn contains_cm(self, depth_cm: f32) -> bool {
+        depth_cm >= self.top_cm as f32 && depth_cm < self.bottom_cm as f32
+    }
+}
+
+impl FromStr for SoilDepth {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let range = s.trim().trim_end_matches("cm");
+        let (top, bottom) = range
+            .split_once('-')
+            .ok_or_else(|| anyhow!("Expected a depth interval like `0-5`, got {s:?}"))?;
+        let depth = Self::new(top.parse()?, bottom.parse()?);
+
+        if depth.top_cm >= depth.bottom_cm {
+            return Err(anyhow!("Empty depth interval {s:?}"));
+        }
+        Ok(depth)
+    }
+}
+
+impl fmt::Display for SoilDepth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}cm", self.top_cm, self.bottom_cm)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SoilLayerKey {
+    pub property: SoilProperty,
+    pub depth: SoilDepth,
+}
+
+impl SoilLayerKey {
+    /// Layers scanned unless properties or depths are chosen explicitly: the
+    /// topsoil and subsoil texture and pH the exporter draws strata from,
+    /// plus topsoil organic carbon.
+    pub const DEFAULTS: [SoilLayerKey; 7] = [
+        Self::new(SoilProperty::Sand, SoilDepth::new(0, 5)),
+        Self::new(SoilProperty::Sand, SoilDepth::new(30, 60)),
+        Self::new(SoilProperty::Clay, SoilDepth::new(0, 5)),
+        Self::new(SoilProperty::Clay, SoilDepth::new(30, 60)),
+        Self::new(SoilProperty::Soc, SoilDepth::new(0, 5)),
+        Self::new(SoilProperty::Phh2o, SoilDepth::new(0, 5)),
+        Self::new(SoilProperty::Phh2o, SoilDepth::new(30, 60)),
+    ];
+
+    pub const fn new(property: SoilProperty, depth: SoilDepth) -> Self {
+        Self { property, depth }
+    }
+
+    /// Whether the layer is median-filtered after void filling. Only the
+    /// topsoil layers of the default set are, so enabling more properties
+    /// does not change how the surface is smoothed.
+    pub fn smoothed(self) -> bool {
+        self.depth.top_cm == 0
+            && matches!(
+                self.property,
+                SoilProperty::Sand | SoilProperty::Clay | SoilProperty::Soc | SoilProperty::Phh2o
+            )
+    }
+}
+
+impl fmt::Display for SoilLayerKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.property.label(), self.depth)
+    }
+}
@@ -1,6 +1,9 @@
 use anyhow::{Result, anyhow};
 use geo::Coord;
 
+/// Length of one degree of latitude, and of longitude at the equator.
+pub const METERS_PER_DEGREE: f64 = 111_320.0;
+
 #[derive(Debug, Clone, Copy)]
 pub struct GeoTransform {
     forward_matrix: [f64; 6],
@@ -32,6 +35,28 @@ impl GeoTransform {
         })
     }
 
+    /// Transform of the same raster resampled so one new pixel spans
+    /// `sx` by `sy` original pixels.
+    pub fn scaled(&self, sx: f64, sy: f64) -> Result<Self> {
+        let gt = self.forward_matrix;
+        Self::from_gdal([gt[0], gt[1] * sx, gt[2] * sy, gt[3], gt[4] * sx, gt[5] * sy])
+    }
+
+    /// Approximate ground size of one pixel in meters near the given latitude,
+    /// for a raster in degrees.
+    pub fn pixel_size_m(&self, lat: f64) -> f64 {
+        let gt = self.forward_matrix;
+        let dx = gt[1].hypot(gt[4]) * METERS_PER_DEGREE * lat.to_radians().cos();
+        let dy = gt[2].hypot(gt[5]) * METERS_PER_DEGREE;
+        (dx * dy).sqrt()
+    }
+
+    /// Size of one pixel in the raster's own units.
+    pub fn pixel_size(&self) -> f64 {
+        let gt = self.forward_matrix;
+        (gt[1].hypot(gt[4]) * gt[2].hypot(gt[5])).sqrt()
+    }
+
     #[inline]
     pub fn geo_to_pixel(&self, x: f64, y: f64) -> Coord<f64> {
         let inv = self.inverse_matrix;
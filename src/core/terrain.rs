@@ -1,58 +1,26 @@
+use crate::core::layer::{Layer, LayerData, LayerMeta, LayerRowMut};
+use crate::core::soil::{SoilLayerKey, SoilProperty};
 use rayon::iter::IndexedParallelIterator;
 use rayon::prelude::*;
 
-#[derive(Debug, Clone)]
-pub struct TerrainPixel {
-    pub elevation: f32,
-
-    pub hh: f32,
-    pub hv: f32,
-    pub inc: f32,
-    pub ls: f32,
-
-    pub landcover: Option<u8>,
-
-    pub sand: f32,
-    pub sand_sub: f32,
-    pub clay: f32,
-    pub clay_sub: f32,
-    pub ph: f32,
-    pub ph_sub: f32,
-    pub soc: f32,
-}
+pub const ELEVATION: &str = "elevation";
+pub const LANDCOVER: &str = "landcover";
+pub const LANDCOVER_CONFIDENCE: &str = "landcover_confidence";
+/// Pixels inside the ROI polygon, present only for polygon ROIs
+pub const ROI_MASK: &str = "roi_mask";
 
+/// One row of every layer, in registry order.
 pub struct RowViewMut<'a> {
-    elevation: &'a mut [f32],
-    hh: &'a mut [f32],
-    hv: &'a mut [f32],
-    inc: &'a mut [f32],
-    ls: &'a mut [f32],
-    landcover: &'a mut [Option<u8>],
-    sand: &'a mut [f32],
-    sand_sub: &'a mut [f32],
-    clay: &'a mut [f32],
-    clay_sub: &'a mut [f32],
-    ph: &'a mut [f32],
-    ph_sub: &'a mut [f32],
-    soc: &'a mut [f32],
+    layers: Vec<LayerRowMut<'a>>,
 }
 
 impl<'a> RowViewMut<'a> {
+    /// Stores one sampled value per layer, in registry order.
     #[inline]
-    pub fn set(&mut self, x: usize, pixel: TerrainPixel) {
-        self.elevation[x] = pixel.elevation;
-        self.hh[x] = pixel.hh;
-        self.hv[x] = pixel.hv;
-        self.inc[x] = pixel.inc;
-        self.ls[x] = pixel.ls;
-        self.landcover[x] = pixel.landcover;
-        self.sand[x] = pixel.sand;
-        self.sand_sub[x] = pixel.sand_sub;
-        self.clay[x] = pixel.clay;
-        self.clay_sub[x] = pixel.clay_sub;
-        self.ph[x] = pixel.ph;
-        self.ph_sub[x] = pixel.ph_sub;
-        self.soc[x] = pixel.soc;
+    pub fn set(&mut self, x: usize, values: &[Option<f32>]) {
+        for (layer, &value) in self.layers.iter_mut().zip(values) {
+            layer.set(x, value);
+        }
     }
 }
 
@@ -60,89 +28,139 @@ impl<'a> RowViewMut<'a> {
 pub struct TerrainGrid {
     pub width: usize,
     pub height: usize,
+    /// Pixel size in meters
+    pub pixel_size: f64,
+    /// Position of the first pixel in the full ROI grid; non-zero only when
+    /// this grid is one tile of it
+    pub offset: (usize, usize),
 
     pub min_elevation: f32,
     pub max_elevation: f32,
 
-    pub elevation: Vec<f32>,
-    pub hh: Vec<f32>,
-    pub hv: Vec<f32>,
-    pub inc: Vec<f32>,
-    pub ls: Vec<f32>,
-
-    pub landcover: Vec<Option<u8>>,
-
-    pub sand: Vec<f32>,
-    pub sand_sub: Vec<f32>,
-    pub clay: Vec<f32>,
-    pub clay_sub: Vec<f32>,
-    pub ph: Vec<f32>,
-    pub ph_sub: Vec<f32>,
-    pub soc: Vec<f32>,
+    /// Layer registry; every layer holds `width * height` values
+    pub layers: Vec<Layer>,
 }
 
 impl TerrainGrid {
-    pub fn new(width: usize, height: usize) -> Self {
+    pub fn new(width: usize, height: usize, pixel_size: f64, layers: Vec<LayerMeta>) -> Self {
         let len = width * height;
         Self {
             width,
             height,
+            pixel_size,
+            offset: (0, 0),
             min_elevation: f32::MAX,
             max_elevation: f32::MIN,
-            elevation: vec![f32::NAN; len],
-            hh: vec![f32::NAN; len],
-            hv: vec![f32::NAN; len],
-            inc: vec![f32::NAN; len],
-            ls: vec![f32::NAN; len],
-            landcover: vec![None; len],
-            sand: vec![f32::NAN; len],
-            sand_sub: vec![f32::NAN; len],
-            clay: vec![f32::NAN; len],
-            clay_sub: vec![f32::NAN; len],
-            ph: vec![f32::NAN; len],
-            ph_sub: vec![f32::NAN; len],
-            soc: vec![f32::NAN; len],
+            layers: layers
+                .into_iter()
+                .map(|meta| Layer {
+                    data: LayerData::empty(meta.kind, len),
+                    meta,
+                })
+                .collect(),
         }
     }
 
+    pub fn add_layer(&mut self, meta: LayerMeta, data: LayerData) {
+        self.layers.push(Layer { meta, data });
+    }
+
+    pub fn layer(&self, name: &str) -> Option<&Layer> {
+        self.layers.iter().find(|l| l.meta.name == name)
+    }
+
+    fn layer_mut(&mut self, name: &str) -> Option<&mut Layer> {
+        self.layers.iter_mut().find(|l| l.meta.name == name)
+    }
+
+    pub fn continuous(&self, name: &str) -> Option<&[f32]> {
+        match &self.layer(name)?.data {
+            LayerData::Continuous(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    pub fn continuous_mut(&mut self, name: &str) -> Option<&mut Vec<f32>> {
+        match &mut self.layer_mut(name)?.data {
+            LayerData::Continuous(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    pub fn categorical(&self, name: &str) -> Option<&[Option<u8>]> {
+        match &self.layer(name)?.data {
+            LayerData::Categorical(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    pub fn categorical_mut(&mut self, name: &str) -> Option<&mut Vec<Option<u8>>> {
+        match &mut self.layer_mut(name)?.data {
+            LayerData::Categorical(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    pub fn mask(&self, name: &str) -> Option<&[bool]> {
+        match &self.layer(name)?.data {
+            LayerData::Mask(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    pub fn elevation(&self) -> &[f32] {
+        self.continuous(ELEVATION)
+            .expect("Elevation layer is always registered")
+    }
+
+    pub fn elevation_mut(&mut self) -> &mut Vec<f32> {
+        self.continuous_mut(ELEVATION)
+            .expect("Elevation layer is always registered")
+    }
+
+    pub fn soil_layers(&self) -> impl Iterator<Item = (SoilLayerKey, &[f32])> {
+        self.layers
+            .iter()
+            .filter_map(|l| match (&l.meta.soil, &l.data) {
+                (Some(key), LayerData::Continuous(data)) => Some((*key, data.as_slice())),
+                _ => None,
+            })
+    }
+
+    /// Layer of a soil property whose depth interval contains `depth_cm`
+    /// below the surface.
+    pub fn soil_layer(&self, property: SoilProperty, depth_cm: f32) -> Option<&[f32]> {
+        self.soil_layers()
+            .find(|(key, _)| key.property == property && key.depth.contains_cm(depth_cm))
+            .map(|(_, data)| data)
+    }
+
     pub fn par_rows_mut(&mut self) -> impl IndexedParallelIterator<Item = RowViewMut<'_>> {
         let w = self.width;
-        (
-            (
-                self.elevation.par_chunks_mut(w),
-                self.hh.par_chunks_mut(w),
-                self.hv.par_chunks_mut(w),
-                self.inc.par_chunks_mut(w),
-                self.ls.par_chunks_mut(w),
-                self.landcover.par_chunks_mut(w),
-            ),
-            (
-                self.sand.par_chunks_mut(w),
-                self.sand_sub.par_chunks_mut(w),
-                self.clay.par_chunks_mut(w),
-                self.clay_sub.par_chunks_mut(w),
-                self.ph.par_chunks_mut(w),
-                self.ph_sub.par_chunks_mut(w),
-                self.soc.par_chunks_mut(w),
-            ),
-        )
-            .into_par_iter()
-            .map(
-                |((e, hh, hv, inc, ls, lc), (s, ss, c, cs, p, ps, soc))| RowViewMut {
-                    elevation: e,
-                    hh,
-                    hv,
-                    inc,
-                    ls,
-                    landcover: lc,
-                    sand: s,
-                    sand_sub: ss,
-                    clay: c,
-                    clay_sub: cs,
-                    ph: p,
-                    ph_sub: ps,
-                    soc,
-                },
-            )
+
+        let mut rows: Vec<Vec<LayerRowMut>> = (0..self.height)
+            .map(|_| Vec::with_capacity(self.layers.len()))
+            .collect();
+        for layer in &mut self.layers {
+            match &mut layer.data {
+                LayerData::Continuous(data) => {
+                    for (row, chunk) in rows.iter_mut().zip(data.chunks_mut(w)) {
+                        row.push(LayerRowMut::Continuous(chunk));
+                    }
+                }
+                LayerData::Categorical(data) => {
+                    for (row, chunk) in rows.iter_mut().zip(data.chunks_mut(w)) {
+                        row.push(LayerRowMut::Categorical(chunk));
+                    }
+                }
+                LayerData::Mask(data) => {
+                    for (row, chunk) in rows.iter_mut().zip(data.chunks_mut(w)) {
+                        row.push(LayerRowMut::Mask(chunk));
+                    }
+                }
+            }
+        }
+
+        rows.into_par_iter().map(|layers| RowViewMut { layers })
     }
 }
@@ -0,0 +1,100 @@
+use std::fmt;
+
+/// Weakest linear power converted to decibels, -40 dB, below the noise floor
+/// of the radar products; weaker returns, including zero, are clamped to it.
+const MIN_POWER: f32 = 1e-4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnitConversion {
+    Identity,
+    Scale(f32),
+    /// Linear power to decibels, `10 * log10(v)`, clamped at [`MIN_POWER`];
+    /// negative values become nodata
+    PowerToDb,
+}
+
+impl UnitConversion {
+    #[inline]
+    pub fn apply(self, value: f32) -> f32 {
+        match self {
+            Self::Identity => value,
+            Self::Scale(factor) => value * factor,
+            Self::PowerToDb => {
+                if value >= 0.0 {
+                    10.0 * value.max(MIN_POWER).log10()
+                } else {
+                    f32::NAN
+                }
+            }
+        }
+    }
+}
+
+/// Units a layer is stored in on disk and the units it is normalized to on load.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LayerUnits {
+    pub source: &'static str,
+    pub target: &'static str,
+    pub conversion: UnitConversion,
+}
+
+impl LayerUnits {
+    pub const fn identity(unit: &'static str) -> Self {
+        Self {
+            source: unit,
+            target: unit,
+            conversion: UnitConversion::Identity,
+        }
+    }
+
+    pub const fn convert(
+        source: &'static str,
+        target: &'static str,
+        conversion: UnitConversion,
+    ) -> Self {
+        Self {
+            source,
+            target,
+            conversion,
+        }
+    }
+}
+
+impl fmt::Display for LayerUnits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.conversion == UnitConversion::Identity {
+            write!(f, "{}", self.target)
+        } else {
+            write!(f, "{} -> {}", self.source, self.target)
+        }
+    }
+}
+
+/// Per-band transform from raw stored values to normalized layer values: the
+/// band's own scale/offset first, then the layer's declared unit conversion.
+#[derive(Debug, Clone, Copy)]
+pub struct ValueTransform {
+    pub no_data: Option<f64>,
+    pub scale: f64,
+    pub offset: f64,
+    pub conversion: UnitConversion,
+}
+
+impl ValueTransform {
+    #[inline]
+    pub fn apply(&self, raw: f32) -> f32 {
+        if let Some(no_data) = self.no_data
+            && (raw - no_data as f32).abs() < 1e-6
+        {
+            return f32::NAN;
+        }
+        let physical = (raw as f64 * self.scale + self.offset) as f32;
+        self.conversion.apply(physical)
+    }
+
+    pub fn apply_all(&self, data: &mut [f32]) {
+        for v in data {
+            *v = self.apply(*v);
+        }
+    }
+}
@@ -1,16 +1,25 @@
-use crate::core::terrain::TerrainGrid;
+use crate::core::layer::LayerData;
+use crate::core::roi::Roi;
+use crate::core::terrain::{ROI_MASK, TerrainGrid};
 use crate::scanner::types::DataCatalog;
 use crate::utils::float::FloatEx;
 use crate::utils::progress::create_progress_bar;
 use anyhow::{Result, anyhow};
-use geo::Rect;
 use indicatif::ProgressBar;
 use rayon::prelude::*;
+use std::path::Path;
 
-pub fn validate_data_catalog(data_catalog: &DataCatalog, roi: Rect<f64>) -> Result<()> {
+pub fn validate_data_catalog(
+    data_catalog: &DataCatalog,
+    roi: &Roi,
+    output_dir: &Path,
+) -> Result<()> {
     let coverage = data_catalog.check_coverage(roi);
     if !coverage.is_full() {
         println!("{coverage}");
+        let gaps_path = output_dir.join("coverage_gaps.geojson");
+        coverage.write_missing_geojson(&gaps_path)?;
+        println!("Missing coverage written to {}", gaps_path.display());
         Err(anyhow!("Coverage check failed"))
     } else {
         Ok(())
@@ -20,42 +29,39 @@ pub fn validate_data_catalog(data_catalog: &DataCatalog, roi: Rect<f64>) -> Resu
 pub fn validate_terrain_grid(terrain: &TerrainGrid) -> Result<()> {
     const MAX_WORLD_HEIGHT: usize = 4064;
     let world_height = terrain.max_elevation - terrain.min_elevation;
-    if world_height > MAX_WORLD_HEIGHT as f32 {
+    let world_blocks = world_height / terrain.pixel_size as f32;
+    if world_blocks > MAX_WORLD_HEIGHT as f32 {
         return Err(anyhow!(
-            "World height is too large ({world_height}m) to put into Minecraft worlds, please use another ROI"
+            "World height is too large ({world_height}m, {world_blocks:.0} blocks) to put into Minecraft worlds, please use another ROI or a coarser resolution"
         ));
     }
 
-    let f32_layers = [
-        ("Elevation", &terrain.elevation),
-        ("HH", &terrain.hh),
-        ("HV", &terrain.hv),
-        ("Incidence", &terrain.inc),
-        ("Layover/Shadow", &terrain.ls),
-        ("Sand", &terrain.sand),
-        ("Clay", &terrain.clay),
-        ("pH", &terrain.ph),
-        ("SOC", &terrain.soc),
-        ("Sand Sub", &terrain.sand_sub),
-        ("Clay Sub", &terrain.clay_sub),
-        ("pH Sub", &terrain.ph_sub),
-    ];
+    let total = terrain.width * terrain.height;
+    let total_work = total * terrain.layers.len();
 
-    let u8_layers = [("Landcover", &terrain.landcover)];
-
-    let total = terrain.elevation.len();
-    let num_layers = u8_layers.len() + f32_layers.len();
-    let total_work = total * num_layers;
+    // Only pixels inside the ROI polygon have to be populated.
+    let inside = terrain.mask(ROI_MASK);
+    let expected = inside.map_or(total, |inside| inside.iter().filter(|&&v| v).count());
 
     let bar = create_progress_bar(total_work as u64, "Validate Terrain Integrity");
     let chunk_size = 10_000.max(total / 100);
 
-    for (name, data) in f32_layers {
-        verify_layer(name, data, total, chunk_size, &bar, |v| v.is_not_nan())?;
-    }
-
-    for (name, data) in u8_layers {
-        verify_layer(name, data, total, chunk_size, &bar, |v| v.is_some())?;
+    for layer in &terrain.layers {
+        let name = &layer.meta.label;
+        match &layer.data {
+            LayerData::Continuous(data) => {
+                verify_layer(name, data, inside, expected, chunk_size, &bar, |v| {
+                    v.is_not_nan()
+                })?
+            }
+            LayerData::Categorical(data) => {
+                verify_layer(name, data, inside, expected, chunk_size, &bar, |v| {
+                    v.is_some()
+                })?
+            }
+            // Masks hold a flag for every pixel and cannot be incomplete.
+            LayerData::Mask(data) => bar.inc(data.len() as u64),
+        }
     }
 
     bar.finish();
@@ -65,6 +71,7 @@ pub fn validate_terrain_grid(terrain: &TerrainGrid) -> Result<()> {
 fn verify_layer<T, F>(
     name: &str,
     data: &[T],
+    inside: Option<&[bool]>,
     total_expected: usize,
     chunk_size: usize,
     bar: &ProgressBar,
@@ -76,9 +83,15 @@ where
 {
     let valid_count: usize = data
         .par_chunks(chunk_size)
-        .map(|chunk| {
+        .enumerate()
+        .map(|(i, chunk)| {
             bar.inc(chunk.len() as u64);
-            chunk.iter().filter(|v| predicate(v)).count()
+            let offset = i * chunk_size;
+            chunk
+                .iter()
+                .enumerate()
+                .filter(|(j, v)| inside.is_none_or(|inside| inside[offset + j]) && predicate(v))
+                .count()
         })
         .sum();
 
@@ -0,0 +1,250 @@
+use crate::core::context::{PixelWindow, SpatialContext};
+use crate::core::layer::{LayerMeta, LayerSlice};
+use crate::core::terrain::{ROI_MASK, TerrainGrid};
+use crate::physics::PhysicsMap;
+use anyhow::{Context, Result, anyhow};
+use gdal::cpl::CslStringList;
+use gdal::raster::{Buffer, GdalType};
+use gdal::spatial_ref::SpatialRef;
+use gdal::{Dataset, DriverManager, Metadata};
+use std::ffi::CString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Selects every terrain and physics layer.
+const ALL_LAYERS: &str = "all";
+
+/// Stored where a categorical pixel has no class.
+const CATEGORICAL_NO_DATA: u8 = 255;
+const BLOCK_SIZE: &str = "256";
+
+/// Grid layers written as single-band GeoTIFFs in the grid's projection, one
+/// file per layer. The files cover the full ROI grid and can be filled one
+/// tile at a time.
+pub struct LayerRasters {
+    dir: PathBuf,
+    names: Vec<String>,
+    width: usize,
+    height: usize,
+    geo_transform: [f64; 6],
+    srs: SpatialRef,
+    /// Opened on the first write, once the grid's layers are known
+    rasters: Vec<(PathBuf, Dataset)>,
+}
+
+impl LayerRasters {
+    /// `names` are terrain or physics layer names, or [`ALL_LAYERS`]; the
+    /// terrain layers are those in `layers`, plus the ROI mask for polygon
+    /// ROIs.
+    pub fn new(
+        dir: &Path,
+        ctx: &SpatialContext,
+        layers: &[LayerMeta],
+        names: &[String],
+    ) -> Result<Self> {
+        let available: Vec<&str> = layers
+            .iter()
+            .map(|meta| meta.name.as_str())
+            .chain(ctx.polygon.as_ref().map(|_| ROI_MASK))
+            .chain(PhysicsMap::LAYERS.iter().map(|&(name, ..)| name))
+            .collect();
+        if let Some(unknown) = names
+            .iter()
+            .find(|n| *n != ALL_LAYERS && !available.contains(&n.as_str()))
+        {
+            return Err(anyhow!(
+                "Unknown layer {unknown:?}, expected `{ALL_LAYERS}` or one of: {}",
+                available.join(", ")
+            ));
+        }
+
+        let crs = ctx.projection.crs();
+        let srs = SpatialRef::from_definition(&crs)
+            .with_context(|| format!("Unsupported CRS for layer export: {crs}"))?;
+
+        // Row 0 of the grid is its southern edge, while GeoTIFF rows run from
+        // the north.
+        let min = ctx.roi_meters.min();
+        let geo_transform = [
+            min.x,
+            ctx.pixel_size,
+            0.0,
+            min.y + ctx.height as f64 * ctx.pixel_size,
+            0.0,
+            -ctx.pixel_size,
+        ];
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            names: names.to_vec(),
+            width: ctx.width,
+            height: ctx.height,
+            geo_transform,
+            srs,
+            rasters: Vec::new(),
+        })
+    }
+
+    /// Writes `area` of the full ROI grid from `grid` and `physics_map`, which
+    /// may be a tile of the ROI as long as it covers `area`.
+    pub fn write(
+        &mut self,
+        grid: &TerrainGrid,
+        physics_map: &PhysicsMap,
+        area: PixelWindow,
+    ) -> Result<()> {
+        let layers = self.select(grid, physics_map);
+        if self.rasters.is_empty() {
+            fs::create_dir_all(&self.dir)?;
+            println!(
+                "Layer rasters: {:?} ({})",
+                self.dir,
+                layers
+                    .iter()
+                    .map(|(name, ..)| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            self.rasters = layers
+                .iter()
+                .map(|(name, label, units, values)| self.create(name, label, units, *values))
+                .collect::<Result<_>>()?;
+        }
+
+        let (x0, y0) = (area.x - grid.offset.0, area.y - grid.offset.1);
+        let window = (
+            area.x as isize,
+            (self.height - area.y - area.height) as isize,
+        );
+        let size = (area.width, area.height);
+        // Rows of the area from north to south, as GeoTIFF stores them.
+        let rows = || {
+            (0..area.height).rev().map(move |y| {
+                let start = (y0 + y) * grid.width + x0;
+                start..start + area.width
+            })
+        };
+
+        for ((_, dataset), (.., values)) in self.rasters.iter_mut().zip(&layers) {
+            match *values {
+                LayerSlice::Continuous(v) => {
+                    let data = rows().flat_map(|r| &v[r]).copied().collect();
+                    write_band(dataset, window, size, data)?
+                }
+                LayerSlice::Categorical(v) => {
+                    let data = rows()
+                        .flat_map(|r| &v[r])
+                        .map(|c| c.unwrap_or(CATEGORICAL_NO_DATA))
+                        .collect();
+                    write_band(dataset, window, size, data)?
+                }
+                LayerSlice::Mask(v) => {
+                    let data = rows().flat_map(|r| &v[r]).map(|&m| m as u8).collect();
+                    write_band(dataset, window, size, data)?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Closes every file and moves it into place, so an interrupted run never
+    /// leaves a partly written raster behind.
+    pub fn finish(self) -> Result<()> {
+        for (path, dataset) in self.rasters {
+            dataset.close()?;
+            fs::rename(&path, path.with_extension(""))?;
+        }
+        Ok(())
+    }
+
+    /// Name, label, units and values of the requested layers, terrain layers
+    /// before physics layers.
+    fn select<'a>(
+        &self,
+        grid: &'a TerrainGrid,
+        physics_map: &'a PhysicsMap,
+    ) -> Vec<(String, &'a str, &'a str, LayerSlice<'a>)> {
+        let terrain = grid.layers.iter().map(|l| {
+            let (meta, values) = (&l.meta, l.data.as_slice());
+            (
+                meta.name.clone(),
+                meta.label.as_str(),
+                meta.units.as_str(),
+                values,
+            )
+        });
+        let physics = PhysicsMap::LAYERS
+            .iter()
+            .filter_map(|&(name, label, units)| {
+                let values = LayerSlice::Continuous(physics_map.layer(name)?);
+                Some((name.to_string(), label, units, values))
+            });
+        let all = self.names.iter().any(|n| n == ALL_LAYERS);
+        terrain
+            .chain(physics)
+            .filter(|(name, ..)| all || self.names.contains(name))
+            .collect()
+    }
+
+    fn create(
+        &self,
+        name: &str,
+        label: &str,
+        units: &str,
+        values: LayerSlice,
+    ) -> Result<(PathBuf, Dataset)> {
+        let mut options = CslStringList::new();
+        options.set_name_value("COMPRESS", "ZSTD")?;
+        options.set_name_value(
+            "PREDICTOR",
+            match values {
+                LayerSlice::Continuous(_) => "3",
+                _ => "2",
+            },
+        )?;
+        options.set_name_value("TILED", "YES")?;
+        options.set_name_value("BLOCKXSIZE", BLOCK_SIZE)?;
+        options.set_name_value("BLOCKYSIZE", BLOCK_SIZE)?;
+        options.set_name_value("BIGTIFF", "IF_SAFER")?;
+
+        let path = self.dir.join(format!("{name}.tif.part"));
+        let driver = DriverManager::get_driver_by_name("GTiff")?;
+        let (w, h) = (self.width, self.height);
+        let mut dataset = match values {
+            LayerSlice::Continuous(_) => {
+                driver.create_with_band_type_with_options::<f32, _>(&path, w, h, 1, &options)?
+            }
+            _ => driver.create_with_band_type_with_options::<u8, _>(&path, w, h, 1, &options)?,
+        };
+        dataset.set_geo_transform(&self.geo_transform)?;
+        dataset.set_spatial_ref(&self.srs)?;
+
+        let mut band = dataset.rasterband(1)?;
+        band.set_description(label)?;
+        match values {
+            LayerSlice::Continuous(_) => band.set_no_data_value(Some(f64::NAN))?,
+            LayerSlice::Categorical(_) => {
+                band.set_no_data_value(Some(CATEGORICAL_NO_DATA as f64))?
+            }
+            LayerSlice::Mask(_) => {}
+        }
+        let units = CString::new(units)?;
+        let err = unsafe { gdal_sys::GDALSetRasterUnitType(band.c_rasterband(), units.as_ptr()) };
+        if err != gdal_sys::CPLErr::CE_None {
+            return Err(anyhow!("Failed to set units of {}", path.display()));
+        }
+
+        Ok((path, dataset))
+    }
+}
+
+fn write_band<T: GdalType + Copy>(
+    dataset: &mut Dataset,
+    window: (isize, isize),
+    size: (usize, usize),
+    data: Vec<T>,
+) -> Result<()> {
+    let mut buffer = Buffer::new(size, data);
+    dataset.rasterband(1)?.write(window, size, &mut buffer)?;
+    Ok(())
+}
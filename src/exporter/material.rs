@@ -0,0 +1,107 @@
+use super::{CM_PER_M, ExportConfig};
+use crate::core::soil::SoilProperty;
+use crate::core::terrain::{LANDCOVER, LANDCOVER_CONFIDENCE, TerrainGrid};
+use crate::post_process::landcover::LOW_CONFIDENCE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Block {
+    Air,
+    Stone,
+    Dirt,
+    Sand,
+    Clay,
+    Gravel,
+    Grass,
+    Snow,
+    Moss,
+    Water,
+}
+
+impl Block {
+    pub const COUNT: usize = 10;
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Air => "minecraft:air",
+            Self::Stone => "minecraft:stone",
+            Self::Dirt => "minecraft:dirt",
+            Self::Sand => "minecraft:sand",
+            Self::Clay => "minecraft:clay",
+            Self::Gravel => "minecraft:gravel",
+            Self::Grass => "minecraft:grass_block",
+            Self::Snow => "minecraft:snow_block",
+            Self::Moss => "minecraft:moss_block",
+            Self::Water => "minecraft:water",
+        }
+    }
+}
+
+const CFVO_GRAVEL: f32 = 40.0;
+const SAND_DOMINANT: f32 = 70.0;
+const CLAY_DOMINANT: f32 = 45.0;
+
+/// Soil texture layers of one soil block depth.
+struct Texture<'a> {
+    cfvo: Option<&'a [f32]>,
+    sand: Option<&'a [f32]>,
+    clay: Option<&'a [f32]>,
+}
+
+/// Soil texture layers looked up once per grid for every soil block depth,
+/// so picking a block indexes them directly.
+pub(super) struct SoilTexture<'a> {
+    /// One entry per soil block from the surface down, `None` below the
+    /// deepest scanned soil layer where everything is bedrock stone
+    depths: Vec<Option<Texture<'a>>>,
+}
+
+impl<'a> SoilTexture<'a> {
+    pub fn new(grid: &'a TerrainGrid, config: &ExportConfig) -> Self {
+        let depths = (0..config.soil_blocks)
+            .map(|d| {
+                let depth_cm = (d as f32 + 0.5) * config.block_size * CM_PER_M;
+                (depth_cm < config.soil_bottom_cm).then(|| Texture {
+                    cfvo: grid.soil_layer(SoilProperty::Cfvo, depth_cm),
+                    sand: grid.soil_layer(SoilProperty::Sand, depth_cm),
+                    clay: grid.soil_layer(SoilProperty::Clay, depth_cm),
+                })
+            })
+            .collect();
+        Self { depths }
+    }
+
+    /// Block for the soil voxel `depth` blocks below the surface of column `idx`.
+    pub fn block(&self, idx: usize, depth: usize) -> Block {
+        let Some(texture) = &self.depths[depth] else {
+            return Block::Stone;
+        };
+        let exceeds = |layer: Option<&[f32]>, limit| layer.is_some_and(|v| v[idx] > limit);
+
+        if exceeds(texture.cfvo, CFVO_GRAVEL) {
+            Block::Gravel
+        } else if exceeds(texture.sand, SAND_DOMINANT) {
+            Block::Sand
+        } else if exceeds(texture.clay, CLAY_DOMINANT) {
+            Block::Clay
+        } else {
+            Block::Dirt
+        }
+    }
+}
+
+/// Surface block implied by the WorldCover class, if the class is confident
+/// enough to override the soil texture underneath.
+pub fn surface_block(grid: &TerrainGrid, idx: usize) -> Option<Block> {
+    let confidence = grid.continuous(LANDCOVER_CONFIDENCE)?[idx];
+    if confidence.is_nan() || confidence < LOW_CONFIDENCE {
+        return None;
+    }
+
+    match grid.categorical(LANDCOVER)?[idx]? {
+        10 | 20 | 30 | 40 | 90 | 95 => Some(Block::Grass),
+        50 => Some(Block::Stone),
+        70 => Some(Block::Snow),
+        100 => Some(Block::Moss),
+        _ => None,
+    }
+}
@@ -1,15 +1,28 @@
+mod geotiff;
+mod material;
+mod outside;
+
+use crate::core::context::PixelWindow;
+use crate::core::layer::LayerMeta;
 use crate::core::terrain::TerrainGrid;
+use crate::physics::PhysicsMap;
+use crate::preview::Previews;
 use anyhow::Result;
+pub use geotiff::LayerRasters;
 use lz4_java_wrc::Lz4BlockOutput;
+use material::{Block, SoilTexture, surface_block};
+use outside::Outside;
+pub use outside::OutsideFill;
 use serde::Serialize;
 use std::fs;
 use std::fs::File;
 use std::io::{Seek, SeekFrom, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 const COMPRESSION_LZ4: u8 = 4;
 const SECTOR_SIZE: u64 = 4096;
 const DATA_VERSION: i32 = 4671;
+const CM_PER_M: f32 = 100.0;
 
 #[derive(Serialize)]
 #[serde(rename_all = "PascalCase")]
@@ -57,30 +70,136 @@ struct Biomes {
     palette: Vec<String>,
 }
 
-struct ExportConfig {
+/// Vertical layout shared by every region file of a world.
+pub struct ExportConfig {
     world_min_y: i32,
     world_height: i32,
+    /// Edge length of a block in meters, equal to the grid pixel size so
+    /// exported terrain keeps its proportions
+    block_size: f32,
+    /// Offset in blocks from scaled elevation to world Y
     vertical_offset: f32,
+    /// Lowest elevation in meters inside the ROI
+    min_elevation: f32,
+    soil_bottom_cm: f32,
+    soil_blocks: usize,
 }
 
-pub fn generate_world(output_dir: &Path, grid: &TerrainGrid) -> Result<()> {
-    if !output_dir.exists() {
-        fs::create_dir_all(output_dir)?;
+/// Everything written from the processed grid, which arrives either whole
+/// or one tile of the ROI at a time.
+pub struct Outputs {
+    /// Directory of the region files; `None` skips the world
+    world_dir: Option<PathBuf>,
+    outside_fill: OutsideFill,
+    /// Set by [`Outputs::plan_world`] when the world is written
+    config: Option<ExportConfig>,
+    layer_rasters: Option<LayerRasters>,
+    previews: Option<Previews>,
+}
+
+impl Outputs {
+    pub fn new(
+        world_dir: Option<PathBuf>,
+        outside_fill: OutsideFill,
+        layer_rasters: Option<LayerRasters>,
+        previews: Option<Previews>,
+    ) -> Self {
+        Self {
+            world_dir,
+            outside_fill,
+            config: None,
+            layer_rasters,
+            previews,
+        }
     }
 
-    let config = calculate_export_config(grid.min_elevation, grid.max_elevation);
+    pub fn writes_world(&self) -> bool {
+        self.world_dir.is_some()
+    }
+
+    /// Fixes the world's vertical layout before any region is written.
+    pub fn plan_world(
+        &mut self,
+        min_elevation: f32,
+        max_elevation: f32,
+        soil_bottom_cm: f32,
+        pixel_size: f64,
+    ) {
+        if self.writes_world() {
+            self.config = Some(plan_export(
+                min_elevation,
+                max_elevation,
+                soil_bottom_cm,
+                pixel_size,
+            ));
+        }
+    }
+
+    /// Writes `area` of the full ROI grid. `grid` and `physics_map` may be a
+    /// tile of the ROI, as long as they cover `area`.
+    pub fn write(
+        &mut self,
+        grid: &TerrainGrid,
+        physics_map: &PhysicsMap,
+        area: PixelWindow,
+    ) -> Result<()> {
+        if let Some(rasters) = &mut self.layer_rasters {
+            rasters.write(grid, physics_map, area)?;
+        }
+        if let Some(previews) = &mut self.previews {
+            previews.draw(grid, physics_map, area);
+        }
+        if let (Some(dir), Some(config)) = (&self.world_dir, &self.config) {
+            write_regions(dir, grid, config, self.outside_fill, area)?;
+        }
+        Ok(())
+    }
+
+    /// Completes the files that are only written once every area is in.
+    pub fn finish(self) -> Result<()> {
+        if let Some(rasters) = self.layer_rasters {
+            rasters.finish()?;
+        }
+        if let Some(previews) = self.previews {
+            previews.finish()?;
+        }
+        Ok(())
+    }
+}
+
+/// Bottom of the deepest soil layer in centimeters.
+pub fn soil_bottom_cm<'a>(layers: impl Iterator<Item = &'a LayerMeta>) -> f32 {
+    layers
+        .filter_map(|meta| meta.soil)
+        .map(|key| key.depth.bottom_cm)
+        .max()
+        .unwrap_or(0) as f32
+}
+
+/// Fits an elevation range in meters into the world's build height.
+fn plan_export(
+    min_elevation: f32,
+    max_elevation: f32,
+    soil_bottom_cm: f32,
+    pixel_size: f64,
+) -> ExportConfig {
+    let block_size = pixel_size as f32;
+    let min_blocks = min_elevation / block_size;
+    let max_blocks = max_elevation / block_size;
+    let config = calculate_export_config(min_blocks, max_blocks, soil_bottom_cm, block_size);
 
     println!(
         "Origin Height: {:.2}m ~ {:.2}m (diff: {:.2}m)",
-        grid.min_elevation,
-        grid.max_elevation,
-        grid.max_elevation - grid.min_elevation
+        min_elevation,
+        max_elevation,
+        max_elevation - min_elevation
     );
-    println!("Offset: -{:.2}m", config.vertical_offset);
+    println!("Block Size: {}m", block_size);
+    println!("Offset: -{:.2} blocks", config.vertical_offset);
     println!(
         "Mapped Height: {:.2} ~ {:.2}",
-        grid.min_elevation - config.vertical_offset,
-        grid.max_elevation - config.vertical_offset
+        min_blocks - config.vertical_offset,
+        max_blocks - config.vertical_offset
     );
     println!(
         "Min Y = {}, Height = {}, Top Y = {}",
@@ -90,30 +209,66 @@ pub fn generate_world(output_dir: &Path, grid: &TerrainGrid) -> Result<()> {
     );
 
     let limit_max = (config.world_min_y + config.world_height - 1) as f32;
-    let mapped_max = grid.max_elevation - config.vertical_offset;
+    let mapped_max = max_blocks - config.vertical_offset;
     if mapped_max > limit_max {
         panic!("Map height is bigger than max height");
     } else if config.world_height > 384 || config.world_min_y < -64 {
         println!("Warn: Map height is bigger than 384, please install Higher Heights Datapack");
     }
 
+    config
+}
+
+/// Writes the region files covering `area` of the full ROI grid. `grid` may be
+/// a tile of the ROI, as long as it covers `area`.
+fn write_regions(
+    output_dir: &Path,
+    grid: &TerrainGrid,
+    config: &ExportConfig,
+    outside_fill: OutsideFill,
+    area: PixelWindow,
+) -> Result<()> {
+    if !output_dir.exists() {
+        fs::create_dir_all(output_dir)?;
+    }
+
+    let outside = Outside::new(grid, outside_fill, config);
+    let soil = SoilTexture::new(grid, config);
+
     const REGION_BLOCK_SIZE: usize = 512;
-    let regions_x = grid.width.div_ceil(REGION_BLOCK_SIZE);
-    let regions_z = grid.height.div_ceil(REGION_BLOCK_SIZE);
+    let regions_x = area.x / REGION_BLOCK_SIZE..(area.x + area.width).div_ceil(REGION_BLOCK_SIZE);
+    let regions_z = area.y / REGION_BLOCK_SIZE..(area.y + area.height).div_ceil(REGION_BLOCK_SIZE);
 
-    println!("Will Gen Region: X[0..{}] Z[0..{}]", regions_x, regions_z);
+    println!(
+        "Will Gen Region: X[{}..{}] Z[{}..{}]",
+        regions_x.start, regions_x.end, regions_z.start, regions_z.end
+    );
 
-    for rx in 0..regions_x as i32 {
-        for rz in 0..regions_z as i32 {
+    for rx in regions_x {
+        for rz in regions_z.clone() {
             println!("Writing: r.{}.{}.mca", rx, rz);
-            write_mc_lz4_region(output_dir, rx, rz, grid, &config)?;
+            write_mc_lz4_region(
+                output_dir,
+                rx as i32,
+                rz as i32,
+                grid,
+                config,
+                &soil,
+                outside.as_ref(),
+            )?;
         }
     }
 
     Ok(())
 }
 
-fn calculate_export_config(min_ele: f32, max_ele: f32) -> ExportConfig {
+/// `min_ele` and `max_ele` are in blocks.
+fn calculate_export_config(
+    min_ele: f32,
+    max_ele: f32,
+    soil_bottom_cm: f32,
+    block_size: f32,
+) -> ExportConfig {
     const ABS_MIN_Y: i32 = -2032;
     const MAX_CAPACITY: i32 = 4064;
     const ABS_MAX_Y: i32 = ABS_MIN_Y + MAX_CAPACITY;
@@ -143,10 +298,16 @@ fn calculate_export_config(min_ele: f32, max_ele: f32) -> ExportConfig {
 
     height = height.clamp(384, MAX_CAPACITY);
 
+    let block_height_cm = block_size * CM_PER_M;
+
     ExportConfig {
         world_min_y: target_min_y,
         world_height: height,
+        block_size,
         vertical_offset,
+        min_elevation: min_ele * block_size,
+        soil_bottom_cm,
+        soil_blocks: ((soil_bottom_cm / block_height_cm).ceil() as usize).max(1),
     }
 }
 
@@ -156,6 +317,8 @@ fn write_mc_lz4_region(
     rz: i32,
     grid: &TerrainGrid,
     config: &ExportConfig,
+    soil: &SoilTexture,
+    outside: Option<&Outside>,
 ) -> Result<()> {
     let path = dir.join(format!("r.{}.{}.mca", rx, rz));
     let mut file = File::create(path)?;
@@ -170,7 +333,7 @@ fn write_mc_lz4_region(
             let global_x = (rx * 512 + cx * 16) as usize;
             let global_z = (rz * 512 + cz * 16) as usize;
 
-            let chunk_data = build_chunk_struct(grid, global_x, global_z, config);
+            let chunk_data = build_chunk_struct(grid, global_x, global_z, config, soil, outside);
 
             let mut uncompressed_bytes = Vec::with_capacity(4096);
             na_nbt::to_writer_be(&mut uncompressed_bytes, &chunk_data)?;
@@ -214,27 +377,52 @@ fn build_chunk_struct(
     gx: usize,
     gz: usize,
     config: &ExportConfig,
+    soil: &SoilTexture,
+    outside: Option<&Outside>,
 ) -> ChunkRoot {
     let min_y = config.world_min_y;
     let max_y = min_y + config.world_height;
 
+    let soil_blocks = config.soil_blocks;
+    let elevation = grid.elevation();
     let mut height_map = [min_y; 256];
+    let mut water_map = [min_y; 256];
+    let mut soil_columns = vec![Block::Stone; 256 * soil_blocks];
     let mut chunk_min_h = i32::MAX;
     let mut chunk_max_h = i32::MIN;
 
     for z in 0..16 {
         for x in 0..16 {
-            let cur_gx = gx + x;
-            let cur_gz = gz + z;
+            // Grid-local; wraps past the grid size left of or below a tile.
+            let cur_gx = (gx + x).wrapping_sub(grid.offset.0);
+            let cur_gz = (gz + z).wrapping_sub(grid.offset.1);
+            let col = z * 16 + x;
 
             let h = if cur_gx < grid.width && cur_gz < grid.height {
                 let idx = cur_gz * grid.width + cur_gx;
-                if idx < grid.elevation.len() {
-                    let val = grid.elevation[idx];
+                if idx < elevation.len() {
+                    let val = elevation[idx];
                     if val.is_nan() {
                         min_y
                     } else {
-                        (val - config.vertical_offset).floor() as i32
+                        for d in 0..soil_blocks {
+                            soil_columns[col * soil_blocks + d] = soil.block(idx, d);
+                        }
+                        if let Some(surface) = surface_block(grid, idx) {
+                            soil_columns[col * soil_blocks] = surface;
+                        }
+                        let h = (val / config.block_size - config.vertical_offset).floor() as i32;
+                        match outside.filter(|o| o.contains(idx)) {
+                            Some(outside) => {
+                                let (h, water) = outside.column(idx, h);
+                                if water > h {
+                                    soil_columns[col * soil_blocks] = Block::Sand;
+                                }
+                                water_map[col] = water;
+                                h
+                            }
+                            None => h,
+                        }
                     }
                 } else {
                     min_y
@@ -243,12 +431,13 @@ fn build_chunk_struct(
                 min_y
             };
 
-            height_map[z * 16 + x] = h;
+            height_map[col] = h;
+            water_map[col] = water_map[col].max(h);
             if h < chunk_min_h {
                 chunk_min_h = h;
             }
-            if h > chunk_max_h {
-                chunk_max_h = h;
+            if water_map[col] > chunk_max_h {
+                chunk_max_h = water_map[col];
             }
         }
     }
@@ -265,9 +454,9 @@ fn build_chunk_struct(
             continue;
         }
 
-        if top_y <= chunk_min_h {
+        if top_y <= chunk_min_h - soil_blocks as i32 {
             let palette = vec![BlockStatePalette {
-                name: "minecraft:stone".to_string(),
+                name: Block::Stone.name().to_string(),
             }];
             sections.push(Section {
                 y: sy as i8,
@@ -282,39 +471,51 @@ fn build_chunk_struct(
             continue;
         }
 
-        let palette = vec![
-            BlockStatePalette {
-                name: "minecraft:air".to_string(),
-            },
-            BlockStatePalette {
-                name: "minecraft:stone".to_string(),
-            },
-        ];
-
+        let mut palette_index = [usize::MAX; Block::COUNT];
+        let mut palette = Vec::new();
         let mut block_indices = Vec::with_capacity(4096);
 
         for y in 0..16 {
             let abs_y = base_y + y;
             for z in 0..16 {
                 for x in 0..16 {
-                    let h = height_map[z * 16 + x];
-                    if abs_y <= h {
-                        block_indices.push(1); // Stone
+                    let col = z * 16 + x;
+                    let h = height_map[col];
+                    let block = if abs_y > water_map[col] {
+                        Block::Air
+                    } else if abs_y > h {
+                        Block::Water
                     } else {
-                        block_indices.push(0); // Air
+                        let depth = (h - abs_y) as usize;
+                        if depth < soil_blocks {
+                            soil_columns[col * soil_blocks + depth]
+                        } else {
+                            Block::Stone
+                        }
+                    };
+
+                    let slot = &mut palette_index[block as usize];
+                    if *slot == usize::MAX {
+                        *slot = palette.len();
+                        palette.push(BlockStatePalette {
+                            name: block.name().to_string(),
+                        });
                     }
+                    block_indices.push(*slot);
                 }
             }
         }
 
-        let packed_data = pack_states(&block_indices, 4);
+        let data = if palette.len() > 1 {
+            let bits = (usize::BITS - (palette.len() - 1).leading_zeros()).max(4) as usize;
+            pack_states(&block_indices, bits)
+        } else {
+            vec![]
+        };
 
         sections.push(Section {
             y: sy as i8,
-            block_states: BlockStates {
-                palette,
-                data: packed_data,
-            },
+            block_states: BlockStates { palette, data },
             biomes: Biomes {
                 palette: vec!["minecraft:plains".to_string()],
             },
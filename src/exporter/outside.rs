@@ -0,0 +1,80 @@
+use super::ExportConfig;
+use crate::core::roi::{OUTSIDE_MARGIN, distance_to_inside};
+use crate::core::terrain::{ROI_MASK, TerrainGrid};
+use clap::ValueEnum;
+
+/// Width in blocks of the slope from the ROI edge down to the sea floor,
+/// which is as far as real terrain was sampled beyond the edge.
+const FADE_BLOCKS: f32 = OUTSIDE_MARGIN;
+const SEA_DEPTH_BLOCKS: i32 = 16;
+
+/// Terrain exported where the grid lies outside a polygon ROI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutsideFill {
+    /// Level ground at the lowest elevation inside the ROI
+    Flat,
+    /// Terrain sloping away from the ROI edge into a sea
+    FadeToSea,
+    /// No blocks at all
+    Void,
+}
+
+/// Surface and water level of the columns outside the ROI polygon.
+pub(super) struct Outside<'a> {
+    fill: OutsideFill,
+    inside: &'a [bool],
+    /// Distance in blocks to the nearest pixel inside the ROI
+    distance: Vec<f32>,
+    flat_y: i32,
+    sea_y: i32,
+    min_y: i32,
+}
+
+impl<'a> Outside<'a> {
+    /// `None` when the ROI is a rectangle and every pixel is inside.
+    pub fn new(grid: &'a TerrainGrid, fill: OutsideFill, config: &ExportConfig) -> Option<Self> {
+        let inside = grid.mask(ROI_MASK)?;
+        let to_y =
+            |meters: f32| (meters / config.block_size - config.vertical_offset).floor() as i32;
+
+        let min_y = config.world_min_y;
+        let distance = match fill {
+            OutsideFill::FadeToSea => distance_to_inside(inside, grid.width, grid.height),
+            _ => vec![],
+        };
+
+        Some(Self {
+            fill,
+            inside,
+            distance,
+            flat_y: to_y(config.min_elevation),
+            // Sea level, raised just enough above the world floor for the
+            // sea to have a bed when the whole ROI lies above sea level.
+            sea_y: to_y(config.min_elevation.max(0.0)).max(min_y + SEA_DEPTH_BLOCKS),
+            min_y,
+        })
+    }
+
+    #[inline]
+    pub fn contains(&self, idx: usize) -> bool {
+        !self.inside[idx]
+    }
+
+    /// Surface height and water level of an outside column whose terrain
+    /// would otherwise reach `terrain_y`. A surface below the world floor
+    /// leaves the column empty.
+    pub fn column(&self, idx: usize, terrain_y: i32) -> (i32, i32) {
+        match self.fill {
+            OutsideFill::Flat => (self.flat_y, self.flat_y),
+            OutsideFill::Void => (self.min_y - 1, self.min_y - 1),
+            OutsideFill::FadeToSea => {
+                let t = (self.distance[idx] / FADE_BLOCKS).min(1.0);
+                let s = t * t * (3.0 - 2.0 * t);
+                let floor_y = self.sea_y - SEA_DEPTH_BLOCKS;
+                let h = (terrain_y as f32 * (1.0 - s) + floor_y as f32 * s).round() as i32;
+                let h = h.max(self.min_y);
+                (h, h.max(self.sea_y))
+            }
+        }
+    }
+}
@@ -1,74 +1,295 @@
-use super::mosaic::MosaicSource;
+use super::mosaic::{BlendMode, MosaicItem, MosaicSource, MultiBandSource, SourceOptions};
+use crate::core::layer::{LayerKind, LayerMeta};
+use crate::core::resampling::{InterpolatorKind, ResamplingOverride, ResamplingPolicy};
+use crate::core::soil::SoilLayerKey;
+use crate::core::terrain::{ELEVATION, LANDCOVER, LANDCOVER_CONFIDENCE};
+use crate::core::units::{LayerUnits, UnitConversion};
+use crate::scanner::provider::RasterKind;
 use crate::scanner::types::{AlosTile, DataCatalog, EsaTile, SoilTile};
-use geo::Rect;
+use anyhow::{Result, anyhow};
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
 use std::path::PathBuf;
 
+/// Valid Sentinel-1 and Sentinel-2 observations per year at which a
+/// WorldCover pixel is considered fully confident.
+const FULL_CONFIDENCE_S1_OBS: f32 = 30.0;
+const FULL_CONFIDENCE_S2_OBS: f32 = 20.0;
+
+/// Resampling key applying to every soil layer.
+const SOIL_RESAMPLING: &str = "soil";
+
+/// Where a grid layer's values come from.
+#[derive(Clone)]
+pub enum LayerSource {
+    Mosaic(MosaicSource),
+    /// Several bands of the same tiles reduced to one value per pixel
+    Derived {
+        bands: MultiBandSource,
+        derive: fn(&[Option<f32>]) -> Option<f32>,
+    },
+}
+
+#[derive(Clone)]
+pub struct BundleLayer {
+    pub meta: LayerMeta,
+    pub source: LayerSource,
+}
+
+/// Every layer sampled onto the terrain grid, in registry order.
 pub struct LayerBundle {
-    pub elevation: MosaicSource,
-    pub hh: MosaicSource,
-    pub hv: MosaicSource,
-    pub inc: MosaicSource,
-    pub ls: MosaicSource,
-
-    pub landcover: MosaicSource,
-
-    pub sand: MosaicSource,
-    pub sand_sub: MosaicSource,
-    pub clay: MosaicSource,
-    pub clay_sub: MosaicSource,
-    pub ph: MosaicSource,
-    pub ph_sub: MosaicSource,
-    pub soc: MosaicSource,
+    pub layers: Vec<BundleLayer>,
 }
 
 impl LayerBundle {
-    pub fn from_catalog(catalog: &DataCatalog) -> Self {
+    /// `blend` applies to continuous layers; categorical layers always take
+    /// the top tile, since class codes cannot be averaged. `resolution_m` is
+    /// the output pixel size, used to pick raster overviews. `resampling`
+    /// overrides the per-layer resampling defaults below by layer name, or
+    /// for soil layers also by `soil`; any other key is an error.
+    pub fn from_catalog(
+        catalog: &DataCatalog,
+        blend: BlendMode,
+        resolution_m: f64,
+        resampling: &BTreeMap<String, ResamplingOverride>,
+    ) -> Result<Self> {
+        let band_options =
+            |name: &str, units, kind, band, policy: ResamplingPolicy| SourceOptions {
+                units,
+                blend: match kind {
+                    RasterKind::Continuous => blend,
+                    RasterKind::Categorical => BlendMode::Top,
+                },
+                resolution_m,
+                band,
+                resampling: resampling
+                    .get(name)
+                    .or_else(|| {
+                        name.strip_prefix("soil_")
+                            .and(resampling.get(SOIL_RESAMPLING))
+                    })
+                    .map_or(policy, |o| policy.with_override(o)),
+            };
+        let mosaic = |meta: LayerMeta, items, units: LayerUnits, kind, policy| BundleLayer {
+            source: LayerSource::Mosaic(MosaicSource::new(
+                items,
+                band_options(&meta.name, units, kind, 1, policy),
+            )),
+            meta,
+        };
+        let alos = |name,
+                    label,
+                    selector: fn(&AlosTile) -> &PathBuf,
+                    units: LayerUnits,
+                    kind: RasterKind,
+                    policy| {
+            let layer_kind = match kind {
+                RasterKind::Continuous => LayerKind::Continuous,
+                RasterKind::Categorical => LayerKind::Categorical,
+            };
+            mosaic(
+                LayerMeta::new(name, label, layer_kind, units.target),
+                map_alos(&catalog.alos, selector),
+                units,
+                kind,
+                policy,
+            )
+        };
+        let backscatter = || LayerUnits::convert("power", "dB", UnitConversion::PowerToDb);
+        let smooth = |kind| ResamplingPolicy::new(kind).area_averaged();
+        let quality = |band, units| {
+            band_options(
+                LANDCOVER_CONFIDENCE,
+                LayerUnits::identity(units),
+                RasterKind::Continuous,
+                band,
+                ResamplingPolicy::new(InterpolatorKind::Nearest),
+            )
+        };
+
+        let mut layers = vec![
+            alos(
+                ELEVATION,
+                "Elevation",
+                |t| &t.path_dem,
+                LayerUnits::identity("m"),
+                RasterKind::Continuous,
+                smooth(InterpolatorKind::Lanczos3),
+            ),
+            alos(
+                "hh",
+                "HH",
+                |t| &t.path_hh,
+                backscatter(),
+                RasterKind::Continuous,
+                smooth(InterpolatorKind::Bilinear),
+            ),
+            alos(
+                "hv",
+                "HV",
+                |t| &t.path_hv,
+                backscatter(),
+                RasterKind::Continuous,
+                smooth(InterpolatorKind::Bilinear),
+            ),
+            alos(
+                "inc",
+                "Incidence",
+                |t| &t.path_inc,
+                LayerUnits::identity("rad"),
+                RasterKind::Continuous,
+                smooth(InterpolatorKind::Bilinear),
+            ),
+            alos(
+                "ls",
+                "Layover/Shadow",
+                |t| &t.path_ls,
+                LayerUnits::identity("class"),
+                RasterKind::Categorical,
+                ResamplingPolicy::new(InterpolatorKind::Mode),
+            ),
+            mosaic(
+                LayerMeta::new(LANDCOVER, "Landcover", LayerKind::Categorical, "class"),
+                map_esa(&catalog.esa, |t| &t.path_map),
+                LayerUnits::identity("class"),
+                RasterKind::Categorical,
+                ResamplingPolicy::new(InterpolatorKind::Mode),
+            ),
+            // WorldCover InputQuality bands: Sentinel-1 observations, Sentinel-2
+            // observations and the percentage of invalid Sentinel-2 observations
+            BundleLayer {
+                meta: LayerMeta::new(
+                    LANDCOVER_CONFIDENCE,
+                    "Landcover Confidence",
+                    LayerKind::Continuous,
+                    "ratio",
+                ),
+                source: LayerSource::Derived {
+                    bands: MultiBandSource::new(
+                        map_esa(&catalog.esa, |t| &t.path_quality),
+                        &[quality(1, "obs"), quality(2, "obs"), quality(3, "%")],
+                    ),
+                    derive: landcover_confidence,
+                },
+            },
+        ];
+
+        layers.extend(catalog.soil_layers.iter().map(|key| {
+            let units = key.property.units();
+            mosaic(
+                LayerMeta::soil(*key, units.target),
+                map_soil(&catalog.soil, key),
+                units,
+                RasterKind::Continuous,
+                smooth(InterpolatorKind::Bilinear),
+            )
+        }));
+
+        let known =
+            |key: &String| key == SOIL_RESAMPLING || layers.iter().any(|l| &l.meta.name == key);
+        if let Some(unknown) = resampling.keys().find(|k| !known(k)) {
+            return Err(anyhow!(
+                "Unknown layer {unknown:?} in [resampling], expected `{SOIL_RESAMPLING}` or one of: {}",
+                layers
+                    .iter()
+                    .map(|l| l.meta.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+
+        Ok(Self { layers })
+    }
+
+    pub fn metas(&self) -> Vec<LayerMeta> {
+        self.layers.iter().map(|l| l.meta.clone()).collect()
+    }
+
+    /// Feeds every layer and the files it is read from into `state`, so the
+    /// hash changes whenever the sampled grid could.
+    pub fn hash_inputs<H: Hasher>(&self, state: &mut H) {
+        for layer in &self.layers {
+            layer.meta.name.hash(state);
+            match &layer.source {
+                LayerSource::Mosaic(source) => source.hash_inputs(state),
+                LayerSource::Derived { bands, .. } => bands.hash_inputs(state),
+            }
+        }
+    }
+
+    /// The named layers only, in registry order.
+    pub fn select(&self, names: &[&str]) -> Self {
         Self {
-            elevation: MosaicSource::new(map_alos(&catalog.alos, |t| &t.path_dem)),
-            hh: MosaicSource::new(map_alos(&catalog.alos, |t| &t.path_hh)),
-            hv: MosaicSource::new(map_alos(&catalog.alos, |t| &t.path_hv)),
-            inc: MosaicSource::new(map_alos(&catalog.alos, |t| &t.path_inc)),
-            ls: MosaicSource::new(map_alos(&catalog.alos, |t| &t.path_ls)),
-
-            landcover: MosaicSource::new(map_esa(&catalog.esa, |t| &t.path_map)),
-
-            sand: MosaicSource::new(map_soil(&catalog.soil, |t| &t.sand_top)),
-            sand_sub: MosaicSource::new(map_soil(&catalog.soil, |t| &t.sand_sub)),
-            clay: MosaicSource::new(map_soil(&catalog.soil, |t| &t.clay_top)),
-            clay_sub: MosaicSource::new(map_soil(&catalog.soil, |t| &t.clay_sub)),
-            ph: MosaicSource::new(map_soil(&catalog.soil, |t| &t.ph_top)),
-            ph_sub: MosaicSource::new(map_soil(&catalog.soil, |t| &t.ph_sub)),
-            soc: MosaicSource::new(map_soil(&catalog.soil, |t| &t.soc_top)),
+            layers: self
+                .layers
+                .iter()
+                .filter(|l| names.contains(&l.meta.name.as_str()))
+                .cloned()
+                .collect(),
         }
     }
 }
 
-fn map_alos<F>(tiles: &[AlosTile], selector: F) -> Vec<(String, Rect<f64>, PathBuf)>
+/// Landcover confidence in `[0, 1]` from the InputQuality bands, driven by
+/// whichever sensor had the better share of valid observations.
+fn landcover_confidence(quality: &[Option<f32>]) -> Option<f32> {
+    let [s1_obs, s2_obs, s2_invalid_pct] = quality else {
+        return None;
+    };
+
+    let s1 = s1_obs.map(|n| n / FULL_CONFIDENCE_S1_OBS);
+    let s2 = s2_obs.map(|n| {
+        let valid = n * (1.0 - s2_invalid_pct.unwrap_or(0.0) / 100.0);
+        valid / FULL_CONFIDENCE_S2_OBS
+    });
+
+    match (s1, s2) {
+        (None, None) => None,
+        (a, b) => Some(a.unwrap_or(0.0).max(b.unwrap_or(0.0)).min(1.0)),
+    }
+}
+
+fn map_alos<F>(tiles: &[AlosTile], selector: F) -> Vec<MosaicItem>
 where
     F: Fn(&AlosTile) -> &PathBuf,
 {
     tiles
         .iter()
-        .map(|t| (t.id.clone(), t.bounds, selector(t).clone()))
+        .map(|t| MosaicItem {
+            id: t.id.clone(),
+            epoch: None,
+            footprint: t.footprint.clone(),
+            path: selector(t).clone(),
+        })
         .collect()
 }
 
-fn map_esa<F>(tiles: &[EsaTile], selector: F) -> Vec<(String, Rect<f64>, PathBuf)>
+fn map_esa<F>(tiles: &[EsaTile], selector: F) -> Vec<MosaicItem>
 where
     F: Fn(&EsaTile) -> &PathBuf,
 {
     tiles
         .iter()
-        .map(|t| (t.id.clone(), t.bounds, selector(t).clone()))
+        .map(|t| MosaicItem {
+            id: t.id.clone(),
+            epoch: t.epoch,
+            footprint: t.footprint.clone(),
+            path: selector(t).clone(),
+        })
         .collect()
 }
 
-fn map_soil<F>(tiles: &[SoilTile], selector: F) -> Vec<(String, Rect<f64>, PathBuf)>
-where
-    F: Fn(&SoilTile) -> &PathBuf,
-{
+fn map_soil(tiles: &[SoilTile], key: &SoilLayerKey) -> Vec<MosaicItem> {
     tiles
         .iter()
-        .map(|t| (t.id.clone(), t.bounds, selector(t).clone()))
+        .filter_map(|t| {
+            let path = t.layers.get(key)?;
+            Some(MosaicItem {
+                id: t.id.clone(),
+                epoch: t.epoch,
+                footprint: t.footprint.clone(),
+                path: path.clone(),
+            })
+        })
         .collect()
 }
@@ -0,0 +1,192 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+const SHARDS: usize = 32;
+pub const DEFAULT_BUDGET_MIB: usize = 2048;
+const MIB: usize = 1024 * 1024;
+
+static BLOCK_CACHE: OnceLock<BlockCache> = OnceLock::new();
+
+/// Sets the memory budget of the process-wide block cache. Only the first call
+/// before the cache is used takes effect.
+pub fn configure_block_cache(budget_mib: usize) {
+    let _ = BLOCK_CACHE.set(BlockCache::new(budget_mib * MIB));
+}
+
+pub fn block_cache() -> &'static BlockCache {
+    BLOCK_CACHE.get_or_init(|| BlockCache::new(DEFAULT_BUDGET_MIB * MIB))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlockKey {
+    pub source: u64,
+    pub col: isize,
+    pub row: isize,
+}
+
+#[derive(Debug)]
+pub struct Block {
+    pub start_col: isize,
+    pub start_row: isize,
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<f32>,
+}
+
+impl Block {
+    #[inline]
+    pub fn contains(&self, col: isize, row: isize) -> bool {
+        col >= self.start_col
+            && col < self.start_col + self.width as isize
+            && row >= self.start_row
+            && row < self.start_row + self.height as isize
+    }
+
+    #[inline]
+    pub fn get(&self, col: isize, row: isize) -> f32 {
+        let local_col = (col - self.start_col) as usize;
+        let local_row = (row - self.start_row) as usize;
+        self.data[local_row * self.width + local_col]
+    }
+
+    fn bytes(&self) -> usize {
+        self.data.len() * size_of::<f32>()
+    }
+}
+
+#[derive(Default)]
+struct Shard {
+    blocks: HashMap<BlockKey, (Arc<Block>, u64)>,
+    /// Last-use tick to key, oldest first
+    recency: BTreeMap<u64, BlockKey>,
+    bytes: usize,
+}
+
+/// Raster blocks shared by every reader on every thread, evicted least
+/// recently used first once the memory budget is exceeded.
+pub struct BlockCache {
+    shards: Vec<Mutex<Shard>>,
+    shard_budget: usize,
+    budget: usize,
+    tick: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    resident: AtomicUsize,
+}
+
+impl BlockCache {
+    fn new(budget: usize) -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| Mutex::new(Shard::default())).collect(),
+            shard_budget: budget / SHARDS,
+            budget,
+            tick: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            resident: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the cached block, or loads it outside the shard lock and caches it.
+    pub fn get_or_load<F>(&self, key: BlockKey, load: F) -> Option<Arc<Block>>
+    where
+        F: FnOnce() -> Option<Block>,
+    {
+        let shard = &self.shards[Self::shard_of(&key)];
+
+        {
+            let mut guard = shard.lock().unwrap();
+            let tick = self.next_tick();
+            let Shard {
+                blocks, recency, ..
+            } = &mut *guard;
+            if let Some((block, used)) = blocks.get_mut(&key) {
+                recency.remove(used);
+                recency.insert(tick, key);
+                *used = tick;
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Some(block.clone());
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let block = Arc::new(load()?);
+
+        let mut guard = shard.lock().unwrap();
+        let tick = self.next_tick();
+        let size = block.bytes();
+        if let Some((_, used)) = guard.blocks.insert(key, (block.clone(), tick)) {
+            // Another thread loaded the same block meanwhile.
+            guard.recency.remove(&used);
+            guard.bytes -= size;
+            self.resident.fetch_sub(size, Ordering::Relaxed);
+        }
+        guard.recency.insert(tick, key);
+        guard.bytes += size;
+        self.resident.fetch_add(size, Ordering::Relaxed);
+
+        while guard.bytes > self.shard_budget && guard.blocks.len() > 1 {
+            let Some((_, oldest)) = guard.recency.pop_first() else {
+                break;
+            };
+            if let Some((evicted, _)) = guard.blocks.remove(&oldest) {
+                guard.bytes -= evicted.bytes();
+                self.resident.fetch_sub(evicted.bytes(), Ordering::Relaxed);
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        Some(block)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            resident: self.resident.load(Ordering::Relaxed),
+            budget: self.budget,
+        }
+    }
+
+    fn next_tick(&self) -> u64 {
+        self.tick.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn shard_of(key: &BlockKey) -> usize {
+        let mixed = key
+            .source
+            .wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            .wrapping_add((key.col as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F))
+            .wrapping_add((key.row as u64).wrapping_mul(0x1656_67B1_9E37_79F9));
+        (mixed >> 32) as usize % SHARDS
+    }
+}
+
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub resident: usize,
+    pub budget: usize,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lookups = (self.hits + self.misses).max(1);
+        write!(
+            f,
+            "Block cache: {} hits, {} misses ({:.1}% hit rate), {} evictions, {} / {} MiB resident",
+            self.hits,
+            self.misses,
+            self.hits as f64 / lookups as f64 * 100.0,
+            self.evictions,
+            self.resident / MIB,
+            self.budget / MIB
+        )
+    }
+}
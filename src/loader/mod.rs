@@ -1,12 +1,58 @@
 pub mod bundle;
+pub mod cache;
 pub mod mosaic;
 mod reader;
 
-use crate::loader::bundle::LayerBundle;
-use crate::scanner::types::DataCatalog;
+use crate::core::resampling::ResamplingOverride;
+use crate::loader::bundle::{LayerBundle, LayerSource};
+use crate::loader::mosaic::BlendMode;
+use crate::scanner::types::{DataCatalog, Epoch};
 use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet};
+
+pub fn load_layers(
+    catalog: &DataCatalog,
+    blend: BlendMode,
+    resolution_m: f64,
+    resampling: &BTreeMap<String, ResamplingOverride>,
+) -> Result<LayerBundle> {
+    let layers = LayerBundle::from_catalog(catalog, blend, resolution_m, resampling)?;
+
+    print_epochs("Esa WorldCover", catalog.esa.iter().map(|t| t.epoch));
+    print_epochs("Soil Grids", catalog.soil.iter().map(|t| t.epoch));
+
+    println!("Layer units and resampling:");
+    for layer in &layers.layers {
+        let label = &layer.meta.label;
+        match &layer.source {
+            LayerSource::Mosaic(source) => {
+                println!("  {}: {} ({})", label, source.units, source.resampling);
+            }
+            LayerSource::Derived { bands, .. } => {
+                for (i, source) in bands.bands.iter().enumerate() {
+                    println!(
+                        "  {} band {}: {} ({})",
+                        label,
+                        i + 1,
+                        source.units,
+                        source.resampling
+                    );
+                }
+            }
+        }
+    }
 
-pub fn load_layers(catalog: &DataCatalog) -> Result<LayerBundle> {
-    let layers = LayerBundle::from_catalog(catalog);
     Ok(layers)
 }
+
+fn print_epochs(name: &str, epochs: impl Iterator<Item = Option<Epoch>>) {
+    let epochs: BTreeSet<_> = epochs.collect();
+    let labels: Vec<_> = epochs
+        .iter()
+        .rev()
+        .map(|e| e.map_or("unknown".to_string(), |e| e.to_string()))
+        .collect();
+    if !labels.is_empty() {
+        println!("{name} epochs: {}", labels.join(", "));
+    }
+}
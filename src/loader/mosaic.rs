@@ -1,70 +1,252 @@
 use super::reader::{ReaderSession, ReaderSource};
-use crate::core::raster::{Bicubic, Bilinear, Interpolator, NearestNeighbor};
+use crate::core::raster::Interpolator;
+use crate::core::resampling::ResamplingPolicy;
+use crate::core::spatial::METERS_PER_DEGREE;
+use crate::core::units::LayerUnits;
+use crate::scanner::types::{Epoch, Footprint};
 use anyhow::Result;
-use geo::{Contains, Coord, Rect};
+use clap::ValueEnum;
+use geo::{Area, BooleanOps, BoundingRect, Coord};
+use rstar::primitives::{GeomWithData, Line, Rectangle};
+use rstar::{PointDistance, RTree, RTreeObject};
+use std::hash::{Hash, Hasher};
 use std::path::PathBuf;
 use std::sync::Arc;
 
+/// Tiles a session keeps readers open for, so sampling along a seam between
+/// neighbouring tiles does not reopen them on every switch.
+const RECENT_TILES: usize = 4;
+
+/// Distance in meters from a tile's footprint edge over which its weight
+/// ramps up to full when feathering overlaps.
+const FEATHER_WIDTH_M: f64 = 500.0;
+
+/// Share of the smaller footprint two tiles must have in common to count as
+/// overlapping, so neighbours that only meet along an edge, up to rounding
+/// in their reprojected outlines, do not.
+const MIN_OVERLAP_SHARE: f64 = 1e-6;
+
+type TileEnvelope = GeomWithData<Rectangle<[f64; 2]>, usize>;
+
+/// How samples are taken where tile footprints overlap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BlendMode {
+    /// Highest-priority tile with data at the point
+    Top,
+    /// Tiles of the top epoch weighted by distance to their footprint edges
+    Feather,
+}
+
 #[derive(Clone, Debug)]
 struct TileEntry {
     id: String,
-    bounds: Rect<f64>,
+    epoch: Option<Epoch>,
+    footprint: Footprint,
     reader_source: ReaderSource,
+    /// Whether another tile shares area with this one
+    overlapped: bool,
+    /// Set for overlapped tiles when feathering
+    edges: Option<FootprintEdges>,
+}
+
+#[derive(Clone)]
+pub struct MosaicItem {
+    pub id: String,
+    pub epoch: Option<Epoch>,
+    pub footprint: Footprint,
+    pub path: PathBuf,
+}
+
+/// How a layer's tiles are read and combined.
+#[derive(Debug, Clone, Copy)]
+pub struct SourceOptions {
+    pub units: LayerUnits,
+    pub blend: BlendMode,
+    /// Output pixel size in meters
+    pub resolution_m: f64,
+    /// 1-based band index read from each tile
+    pub band: usize,
+    pub resampling: ResamplingPolicy,
 }
 
 #[derive(Clone)]
 pub struct MosaicSource {
+    pub units: LayerUnits,
+    pub resampling: ResamplingPolicy,
+    blend: BlendMode,
+    interpolator: Arc<dyn Interpolator>,
     tiles: Arc<Vec<TileEntry>>,
+    index: Arc<RTree<TileEnvelope>>,
 }
 
 impl MosaicSource {
-    pub fn new(items: Vec<(String, Rect<f64>, PathBuf)>) -> Self {
+    pub fn new(items: Vec<MosaicItem>, options: SourceOptions) -> Self {
         let mut entries = Vec::new();
-        for (id, bounds, path) in items {
-            if let Ok(src) = ReaderSource::new(path) {
-                entries.push(TileEntry {
-                    id,
-                    bounds,
+        for item in items {
+            // A tile that cannot be read leaves a hole in the layer, which is
+            // worth a warning before void filling papers over it.
+            match ReaderSource::new(item.path, &options) {
+                Ok(src) => entries.push(TileEntry {
+                    id: item.id,
+                    epoch: item.epoch,
+                    footprint: item.footprint,
                     reader_source: src,
+                    overlapped: false,
+                    edges: None,
+                }),
+                Err(e) => eprintln!("Skipped tile {}: {e}", item.id),
+            }
+        }
+        // Later tiles take priority, so the newest epoch wins where tiles overlap.
+        entries.sort_by(|a, b| (a.epoch, &a.id).cmp(&(b.epoch, &b.id)));
+
+        let envelopes = entries
+            .iter()
+            .enumerate()
+            .flat_map(|(i, tile)| {
+                tile.footprint
+                    .polygon
+                    .iter()
+                    .filter_map(|p| p.bounding_rect())
+                    .map(move |r| {
+                        let rect =
+                            Rectangle::from_corners(r.min().x_y().into(), r.max().x_y().into());
+                        GeomWithData::new(rect, i)
+                    })
+            })
+            .collect::<Vec<_>>();
+        let index = RTree::bulk_load(envelopes);
+
+        for envelope in index.iter() {
+            let i = envelope.data;
+            if entries[i].overlapped {
+                continue;
+            }
+            let overlapped = index
+                .locate_in_envelope_intersecting(&envelope.envelope())
+                .filter(|other| other.data != i)
+                .any(|other| {
+                    footprints_overlap(&entries[i].footprint, &entries[other.data].footprint)
                 });
+            entries[i].overlapped = overlapped;
+        }
+        if options.blend == BlendMode::Feather {
+            for entry in entries.iter_mut().filter(|e| e.overlapped) {
+                entry.edges = Some(FootprintEdges::new(&entry.footprint));
             }
         }
-        entries.sort_by(|a, b| a.id.cmp(&b.id));
 
         Self {
+            units: options.units,
+            resampling: options.resampling,
+            blend: options.blend,
+            interpolator: Arc::from(options.resampling.interpolator()),
             tiles: Arc::new(entries),
+            index: Arc::new(index),
         }
     }
 
     pub fn open_session(&self) -> MosaicSession {
         MosaicSession {
             source: self.clone(),
-            active_session: None,
-            active_tile_id: None,
+            recent: Vec::with_capacity(RECENT_TILES),
         }
     }
+
+    /// Feeds every tile and the blending and resampling settings into `state`.
+    pub fn hash_inputs<H: Hasher>(&self, state: &mut H) {
+        format!("{:?} {:?} {:?}", self.units, self.resampling, self.blend).hash(state);
+        for tile in self.tiles.iter() {
+            tile.id.hash(state);
+            tile.reader_source.hash_inputs(state);
+        }
+    }
+
+    /// Tiles whose footprint contains the point, highest priority first.
+    fn candidates(&self, coord: &Coord<f64>) -> Vec<usize> {
+        let mut tiles: Vec<_> = self
+            .index
+            .locate_all_at_point(&[coord.x, coord.y])
+            .map(|e| e.data)
+            .filter(|&i| self.tiles[i].footprint.contains(coord))
+            .collect();
+        tiles.sort_unstable_by(|a, b| b.cmp(a));
+        tiles.dedup();
+        tiles
+    }
 }
 
-pub struct MosaicSession {
-    source: MosaicSource,
-    active_session: Option<ReaderSession>,
-    active_tile_id: Option<String>,
+/// Whether two footprints share area rather than just an edge, as
+/// neighbouring tiles of a gridded mosaic do.
+fn footprints_overlap(a: &Footprint, b: &Footprint) -> bool {
+    let shared = a.polygon.intersection(&b.polygon).unsigned_area();
+    let smaller = a.polygon.unsigned_area().min(b.polygon.unsigned_area());
+    shared > smaller * MIN_OVERLAP_SHARE
 }
 
-impl MosaicSession {
-    pub fn fetch_nearest(&mut self, lon: f64, lat: f64) -> Result<Option<f32>> {
-        self.fetch_impl(lon, lat, &NearestNeighbor)
+impl TileEntry {
+    /// Feathering weight in `(0, 1]`, ramping up from the footprint edge.
+    /// Tiles nothing overlaps always have full weight.
+    fn edge_weight(&self, coord: &Coord<f64>) -> f64 {
+        self.edges.as_ref().map_or(1.0, |edges| {
+            (edges.distance_m(coord) / FEATHER_WIDTH_M).clamp(f64::EPSILON, 1.0)
+        })
     }
+}
 
-    pub fn fetch_bilinear(&mut self, lon: f64, lat: f64) -> Result<Option<f32>> {
-        self.fetch_impl(lon, lat, &Bilinear)
+/// Outline of a tile's footprint in an R-tree, so the distance from a sample
+/// to the nearest edge is one lookup rather than a walk over every vertex.
+#[derive(Clone, Debug)]
+struct FootprintEdges {
+    /// Edges with longitudes scaled by the cosine of the tile's central
+    /// latitude, so distances are about equal in both directions
+    tree: RTree<Line<[f64; 2]>>,
+    lon_scale: f64,
+}
+
+impl FootprintEdges {
+    fn new(footprint: &Footprint) -> Self {
+        let lon_scale = footprint.bounds.center().y.to_radians().cos();
+        let scale = |c: Coord<f64>| [c.x * lon_scale, c.y];
+        let edges = footprint
+            .polygon
+            .iter()
+            .flat_map(|p| std::iter::once(p.exterior()).chain(p.interiors()))
+            .flat_map(|ring| ring.lines())
+            // Cuts at the antimeridian do not bound the tile's data.
+            .filter(|l| !(l.start.x.abs() == 180.0 && l.end.x.abs() == 180.0))
+            .map(|l| Line::new(scale(l.start), scale(l.end)))
+            .collect();
+        Self {
+            tree: RTree::bulk_load(edges),
+            lon_scale,
+        }
     }
 
-    pub fn fetch_bicubic(&mut self, lon: f64, lat: f64) -> Result<Option<f32>> {
-        self.fetch_impl(lon, lat, &Bicubic)
+    fn distance_m(&self, coord: &Coord<f64>) -> f64 {
+        let point = [coord.x * self.lon_scale, coord.y];
+        self.tree
+            .nearest_neighbor(&point)
+            .map_or(f64::INFINITY, |edge| {
+                edge.distance_2(&point).sqrt() * METERS_PER_DEGREE
+            })
     }
+}
+
+pub struct MosaicSession {
+    source: MosaicSource,
+    /// Most recently used tiles first
+    recent: Vec<(usize, ReaderSession)>,
+}
 
-    fn fetch_impl<T: Interpolator>(
+impl MosaicSession {
+    /// Samples the layer with its resampling policy.
+    pub fn fetch(&mut self, lon: f64, lat: f64) -> Result<Option<f32>> {
+        let interpolator = Arc::clone(&self.source.interpolator);
+        self.fetch_impl(lon, lat, interpolator.as_ref())
+    }
+
+    fn fetch_impl<T: Interpolator + ?Sized>(
         &mut self,
         lon: f64,
         lat: f64,
@@ -72,24 +254,154 @@ impl MosaicSession {
     ) -> Result<Option<f32>> {
         let coord = Coord { x: lon, y: lat };
 
-        let target = self
-            .source
-            .tiles
-            .iter()
-            .rev()
-            .find(|t| t.bounds.contains(&coord));
-
-        if let Some(tile) = target {
-            if self.active_tile_id.as_ref() != Some(&tile.id) {
-                let session = tile.reader_source.open_session()?;
-                self.active_session = Some(session);
-                self.active_tile_id = Some(tile.id.clone());
+        if let Some(tile) = self.recent_hit(&coord)
+            && let Some(v) = self.session_for(tile)?.sample(lon, lat, strategy)
+        {
+            return Ok(Some(v));
+        }
+
+        let candidates = self.source.candidates(&coord);
+        match self.source.blend {
+            BlendMode::Top => {
+                for tile in candidates {
+                    if let Some(v) = self.session_for(tile)?.sample(lon, lat, strategy) {
+                        return Ok(Some(v));
+                    }
+                }
+                Ok(None)
             }
+            BlendMode::Feather => self.feather(&coord, &candidates, strategy),
+        }
+    }
 
-            if let Some(reader) = &mut self.active_session {
-                return Ok(reader.sample(lon, lat, strategy));
+    /// Blends all tiles of the highest epoch that has data at the point; older
+    /// epochs are only reached when every newer tile returns nodata.
+    fn feather<T: Interpolator + ?Sized>(
+        &mut self,
+        coord: &Coord<f64>,
+        candidates: &[usize],
+        strategy: &T,
+    ) -> Result<Option<f32>> {
+        let mut blend_epoch = None;
+        let mut sum = 0.0;
+        let mut total_weight = 0.0;
+
+        for &tile in candidates {
+            let epoch = self.source.tiles[tile].epoch;
+            if blend_epoch.is_some_and(|e| e != epoch) {
+                break;
             }
+            let Some(v) = self.session_for(tile)?.sample(coord.x, coord.y, strategy) else {
+                continue;
+            };
+            blend_epoch = Some(epoch);
+
+            let weight = self.source.tiles[tile].edge_weight(coord);
+            sum += v as f64 * weight;
+            total_weight += weight;
+        }
+
+        Ok((total_weight > 0.0).then(|| (sum / total_weight) as f32))
+    }
+
+    /// A recently used tile containing the point, if no other tile can cover
+    /// it there.
+    fn recent_hit(&self, coord: &Coord<f64>) -> Option<usize> {
+        self.recent.iter().map(|(i, _)| *i).find(|&i| {
+            let tile = &self.source.tiles[i];
+            !tile.overlapped && tile.footprint.contains(coord)
+        })
+    }
+
+    fn session_for(&mut self, tile: usize) -> Result<&ReaderSession> {
+        match self.recent.iter().position(|(i, _)| *i == tile) {
+            Some(pos) => self.recent[..=pos].rotate_right(1),
+            None => {
+                let session = self.source.tiles[tile].reader_source.open_session()?;
+                self.recent.insert(0, (tile, session));
+                self.recent.truncate(RECENT_TILES);
+            }
+        }
+        Ok(&self.recent[0].1)
+    }
+}
+
+/// Vector-valued layer reading several bands of the same tiles, such as
+/// stacked polarizations or quality flags.
+#[derive(Clone)]
+pub struct MultiBandSource {
+    pub bands: Vec<MosaicSource>,
+}
+
+impl MultiBandSource {
+    /// One entry of `bands` per band to read, each naming its band index.
+    pub fn new(items: Vec<MosaicItem>, bands: &[SourceOptions]) -> Self {
+        let bands = bands
+            .iter()
+            .map(|options| MosaicSource::new(items.clone(), *options))
+            .collect();
+        Self { bands }
+    }
+
+    pub fn hash_inputs<H: Hasher>(&self, state: &mut H) {
+        for band in &self.bands {
+            band.hash_inputs(state);
         }
-        Ok(None)
+    }
+
+    pub fn open_session(&self) -> MultiBandSession {
+        MultiBandSession {
+            bands: self.bands.iter().map(|b| b.open_session()).collect(),
+        }
+    }
+}
+
+pub struct MultiBandSession {
+    bands: Vec<MosaicSession>,
+}
+
+impl MultiBandSession {
+    /// Values of every band at the point, `None` where a band has no data.
+    pub fn fetch(&mut self, lon: f64, lat: f64) -> Vec<Option<f32>> {
+        self.bands
+            .iter_mut()
+            .map(|b| b.fetch(lon, lat).ok().flatten())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo::{MultiPolygon, Rect};
+
+    fn footprint(west: f64, south: f64, east: f64, north: f64) -> Footprint {
+        let bounds = Rect::new((west, south), (east, north));
+        Footprint {
+            bounds,
+            polygon: MultiPolygon::from(bounds.to_polygon()),
+        }
+    }
+
+    #[test]
+    fn grid_neighbours_do_not_overlap() {
+        let tile = footprint(93.0, 30.0, 96.0, 33.0);
+        assert!(!footprints_overlap(
+            &tile,
+            &footprint(96.0, 30.0, 99.0, 33.0)
+        ));
+        assert!(!footprints_overlap(
+            &tile,
+            &footprint(96.0, 33.0, 99.0, 36.0)
+        ));
+    }
+
+    #[test]
+    fn shared_area_overlaps() {
+        let tile = footprint(93.0, 30.0, 96.0, 33.0);
+        assert!(footprints_overlap(
+            &tile,
+            &footprint(95.9, 30.0, 98.9, 33.0)
+        ));
     }
 }
@@ -1,100 +1,135 @@
+use super::cache::{Block, BlockKey, block_cache};
+use super::mosaic::SourceOptions;
 use crate::core::raster::{Interpolator, PixelSource};
 use crate::core::spatial::GeoTransform;
+use crate::core::units::{LayerUnits, UnitConversion, ValueTransform};
 use crate::utils::dataset::DatasetEx;
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow};
+use gdal::raster::RasterBand;
 use gdal::{Dataset, Metadata};
+use geo::Coord;
 use std::cell::RefCell;
+use std::fs;
+use std::hash::{Hash, Hasher};
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 #[derive(Clone, Debug)]
 pub struct ReaderSource {
     path: PathBuf,
     pub transform: GeoTransform,
     needs_half_pixel_shift: bool,
-    no_data_value: Option<f64>,
-    preloaded_data: Option<Arc<Vec<f32>>>,
+    /// 1-based index of the band read
+    band: usize,
+    values: ValueTransform,
+    /// Identifies this raster's blocks in the shared cache
+    cache_id: u64,
+    /// Overview level read instead of the full-resolution band
+    overview: Option<usize>,
+    /// Output pixel size in pixels of the level that is read
+    footprint: f64,
     pub width: usize,
     pub height: usize,
 }
 
 impl ReaderSource {
-    pub fn new(path: PathBuf) -> Result<Self> {
+    pub fn new(path: PathBuf, options: &SourceOptions) -> Result<Self> {
         let dataset = Dataset::open_dataset(&path)?;
 
         let gt_array = dataset
             .geo_transform()
             .context(format!("GeoTransform missing for {path:?}"))?;
 
-        let transform = GeoTransform::from_gdal(gt_array)?;
+        let full_transform = GeoTransform::from_gdal(gt_array)?;
 
         let needs_half_pixel_shift = dataset
             .metadata_item("AREA_OR_POINT", "")
             .map(|s| s == "Area")
             .unwrap_or(false);
 
-        let band = dataset.rasterband(1)?;
-        let no_data_value = band.no_data_value();
+        if options.band == 0 || options.band > dataset.raster_count() {
+            return Err(anyhow!(
+                "Band {} requested from {path:?}, which has {} bands",
+                options.band,
+                dataset.raster_count()
+            ));
+        }
+
+        let band = dataset.rasterband(options.band)?;
         let (w, h) = band.size();
+        let values = value_transform(&band, &options.units);
 
-        let mem_size = w * h * 4;
-        const PRELOAD_THRESHOLD: usize = 4 * 1024 * 1024 * 1024;
+        let center = full_transform.pixel_to_geo(w as f64 / 2.0, h as f64 / 2.0);
+        let pixel_size_m = match dataset.spatial_ref() {
+            Ok(srs) if srs.is_projected() => full_transform.pixel_size() * srs.linear_units(),
+            _ => full_transform.pixel_size_m(center.y),
+        };
+        let wanted = options.resolution_m / pixel_size_m;
 
-        let preloaded_data = if mem_size < PRELOAD_THRESHOLD {
-            match band.read_as::<f32>((0, 0), (w, h), (w, h), None) {
-                Ok(buffer) => Some(Arc::new(buffer.data().to_vec())),
-                Err(_) => None,
-            }
-        } else {
-            None
+        let overview = select_overview(&band, w, wanted)?;
+        let (level_w, level_h) = match overview {
+            Some(i) => band.overview(i)?.size(),
+            None => (w, h),
         };
+        let sx = w as f64 / level_w as f64;
+        let sy = h as f64 / level_h as f64;
 
         Ok(Self {
             path,
-            transform,
+            transform: full_transform.scaled(sx, sy)?,
             needs_half_pixel_shift,
-            no_data_value,
-            preloaded_data,
-            width: w,
-            height: h,
+            band: options.band,
+            values,
+            cache_id: NEXT_CACHE_ID.fetch_add(1, Ordering::Relaxed),
+            overview,
+            footprint: wanted / sx,
+            width: level_w,
+            height: level_h,
         })
     }
 
     pub fn open_session(&self) -> Result<ReaderSession> {
-        let dataset = if self.preloaded_data.is_none() {
-            Some(Dataset::open_dataset(&self.path)?)
-        } else {
-            None
-        };
-
         Ok(ReaderSession {
             source: self.clone(),
-            dataset,
-            cache: RefCell::new(Vec::with_capacity(8)),
+            dataset: Dataset::open_dataset(&self.path)?,
+            last_block: RefCell::new(None),
         })
     }
+
+    /// Feeds what the sampled values depend on into `state`; the file is
+    /// identified by its size and modification time rather than its contents.
+    pub fn hash_inputs<H: Hasher>(&self, state: &mut H) {
+        self.path.hash(state);
+        fs::metadata(&self.path)
+            .ok()
+            .map(|m| (m.len(), m.modified().ok()))
+            .hash(state);
+        self.band.hash(state);
+        self.overview.hash(state);
+        format!("{:?}", self.values).hash(state);
+    }
 }
 
 const CACHE_BLOCK_SIZE: isize = 512;
-const MAX_CACHE_ENTRIES: usize = 8;
-
-struct BlockCache {
-    start_col: isize,
-    start_row: isize,
-    width: usize,
-    height: usize,
-    data: Vec<f32>,
-}
+
+static NEXT_CACHE_ID: AtomicU64 = AtomicU64::new(0);
 
 pub struct ReaderSession {
     source: ReaderSource,
-    dataset: Option<Dataset>,
-    cache: RefCell<Vec<BlockCache>>,
+    dataset: Dataset,
+    /// Last block read, checked before going to the shared cache
+    last_block: RefCell<Option<Arc<Block>>>,
 }
 
 impl ReaderSession {
-    pub fn sample<T: Interpolator>(&self, lon: f64, lat: f64, strategy: &T) -> Option<f32> {
-        let mut px = self.source.transform.geo_to_pixel(lon, lat);
+    pub fn sample<T: Interpolator + ?Sized>(
+        &self,
+        lon: f64,
+        lat: f64,
+        strategy: &T,
+    ) -> Option<f32> {
+        let mut px = self.locate_pixel(lon, lat);
 
         if self.source.needs_half_pixel_shift {
             px.x -= 0.5;
@@ -104,63 +139,53 @@ impl ReaderSession {
         strategy.sample(self, px.x, px.y)
     }
 
-    fn read_from_disk(&self, col: isize, row: isize, w: isize, h: isize) -> Option<f32> {
-        if let Some(index) = self.find_cache_index(col, row) {
-            self.promote_cache_entry(index);
-            return self.read_from_cache_head(col, row);
-        }
+    /// Tiles may be stored in longitudes past ±180, so the normalized
+    /// longitude is retried one turn east and west.
+    fn locate_pixel(&self, lon: f64, lat: f64) -> Coord<f64> {
+        let (w, h) = (self.source.width as f64, self.source.height as f64);
 
-        if self.load_block_to_head(col, row, w, h).is_some() {
-            return self.read_from_cache_head(col, row);
-        }
-
-        None
-    }
-
-    fn find_cache_index(&self, col: isize, row: isize) -> Option<usize> {
-        self.cache.borrow().iter().position(|c| {
-            col >= c.start_col
-                && col < c.start_col + c.width as isize
-                && row >= c.start_row
-                && row < c.start_row + c.height as isize
-        })
+        [0.0, 360.0, -360.0]
+            .iter()
+            .map(|shift| self.source.transform.geo_to_pixel(lon + shift, lat))
+            .find(|p| p.x >= 0.0 && p.y >= 0.0 && p.x <= w && p.y <= h)
+            .unwrap_or_else(|| self.source.transform.geo_to_pixel(lon, lat))
     }
 
-    fn promote_cache_entry(&self, index: usize) {
-        if index == 0 {
-            return;
-        }
-        let mut cache = self.cache.borrow_mut();
-        let block = cache.remove(index);
-        cache.insert(0, block);
-    }
-
-    fn read_from_cache_head(&self, col: isize, row: isize) -> Option<f32> {
-        let cache = self.cache.borrow();
-        if cache.is_empty() {
-            return None;
+    fn read_block(&self, col: isize, row: isize, image_w: isize, image_h: isize) -> Option<f32> {
+        if let Some(block) = self.last_block.borrow().as_ref()
+            && block.contains(col, row)
+        {
+            return valid(block.get(col, row));
         }
 
-        let block = &cache[0];
-        let local_col = (col - block.start_col) as usize;
-        let local_row = (row - block.start_row) as usize;
-        let idx = local_row * block.width + local_col;
+        let start_col = (col / CACHE_BLOCK_SIZE) * CACHE_BLOCK_SIZE;
+        let start_row = (row / CACHE_BLOCK_SIZE) * CACHE_BLOCK_SIZE;
+        let key = BlockKey {
+            source: self.source.cache_id,
+            col: start_col,
+            row: start_row,
+        };
 
-        self.apply_nodata_check(block.data[idx])
+        let block = block_cache().get_or_load(key, || {
+            self.load_block(start_col, start_row, image_w, image_h)
+        })?;
+        let value = valid(block.get(col, row));
+        *self.last_block.borrow_mut() = Some(block);
+        value
     }
 
-    fn load_block_to_head(
+    fn load_block(
         &self,
-        target_col: isize,
-        target_row: isize,
+        start_col: isize,
+        start_row: isize,
         image_w: isize,
         image_h: isize,
-    ) -> Option<()> {
-        let ds = self.dataset.as_ref()?;
-        let band = ds.rasterband(1).ok()?;
-
-        let start_col = (target_col / CACHE_BLOCK_SIZE) * CACHE_BLOCK_SIZE;
-        let start_row = (target_row / CACHE_BLOCK_SIZE) * CACHE_BLOCK_SIZE;
+    ) -> Option<Block> {
+        let band = self.dataset.rasterband(self.source.band).ok()?;
+        let band = match self.source.overview {
+            Some(i) => band.overview(i).ok()?,
+            None => band,
+        };
 
         let block_w = CACHE_BLOCK_SIZE.min(image_w - start_col) as usize;
         let block_h = CACHE_BLOCK_SIZE.min(image_h - start_row) as usize;
@@ -178,33 +203,22 @@ impl ReaderSession {
             )
             .ok()?;
 
-        let new_block = BlockCache {
+        let mut data = buffer.data().to_vec();
+        self.source.values.apply_all(&mut data);
+
+        Some(Block {
             start_col,
             start_row,
             width: block_w,
             height: block_h,
-            data: buffer.data().to_vec(),
-        };
-
-        let mut cache = self.cache.borrow_mut();
-        cache.insert(0, new_block);
-
-        if cache.len() > MAX_CACHE_ENTRIES {
-            cache.pop();
-        }
-
-        Some(())
+            data,
+        })
     }
+}
 
-    #[inline(always)]
-    fn apply_nodata_check(&self, val: f32) -> Option<f32> {
-        if let Some(no_data) = self.source.no_data_value
-            && (val - no_data as f32).abs() < 1e-6
-        {
-            return None;
-        }
-        Some(val)
-    }
+#[inline(always)]
+fn valid(val: f32) -> Option<f32> {
+    if val.is_nan() { None } else { Some(val) }
 }
 
 impl PixelSource for ReaderSession {
@@ -216,19 +230,40 @@ impl PixelSource for ReaderSession {
             return None;
         }
 
-        if let Some(data) = &self.source.preloaded_data {
-            let idx = (row as usize) * self.source.width + (col as usize);
-            return self.apply_nodata_check(data[idx]);
-        }
+        self.read_block(col, row, w, h)
+    }
 
-        self.read_from_disk(col, row, w, h)
+    fn footprint(&self) -> f64 {
+        self.source.footprint
     }
+}
 
-    fn width(&self) -> usize {
-        self.source.width
+/// Maps raw band values to the layer's units. The band's scale and offset
+/// are always applied; the declared conversion is skipped only when the band
+/// says its values are already in the target unit.
+fn value_transform(band: &RasterBand, units: &LayerUnits) -> ValueTransform {
+    let band_unit = band.unit();
+    let conversion = if !band_unit.is_empty() && band_unit == units.target {
+        UnitConversion::Identity
+    } else {
+        units.conversion
+    };
+    ValueTransform {
+        no_data: band.no_data_value(),
+        scale: band.scale().unwrap_or(1.0),
+        offset: band.offset().unwrap_or(0.0),
+        conversion,
     }
+}
 
-    fn height(&self) -> usize {
-        self.source.height
+/// Overview with the coarsest resolution still at least as fine as the output.
+fn select_overview(band: &RasterBand, full_w: usize, wanted: f64) -> Result<Option<usize>> {
+    let mut best: Option<(usize, f64)> = None;
+    for i in 0..band.overview_count()? as usize {
+        let factor = full_w as f64 / band.overview(i)?.size().0 as f64;
+        if factor <= wanted && best.is_none_or(|(_, f)| factor > f) {
+            best = Some((i, factor));
+        }
     }
+    Ok(best.map(|(i, _)| i))
 }
@@ -1,65 +1,126 @@
 mod alignment;
+mod checkpoint;
+mod cli;
 mod core;
 mod exporter;
 mod loader;
 mod physics;
 mod post_process;
+mod preprocess;
+mod preview;
 mod scanner;
+mod tiling;
 mod utils;
 
-use crate::core::validator::{validate_data_catalog, validate_terrain_grid};
-use crate::exporter::generate_world;
-use crate::scanner::scan_datasets;
+use crate::checkpoint::{CHECKPOINTS_PATH, run_stages};
+use crate::cli::{BuildArgs, Cli, Command, PlanArgs};
+use crate::core::config::Config;
+use crate::core::context::PixelWindow;
+use crate::core::validator::validate_data_catalog;
+use crate::exporter::{LayerRasters, Outputs, soil_bottom_cm};
+use crate::loader::cache::{block_cache, configure_block_cache};
+use crate::preprocess::preprocess_datasets;
+use crate::preview::Previews;
+use crate::scanner::{DATASETS_PATH, plan_datasets, scan_datasets};
 use crate::utils::tap::{TryPipe, TryTap};
-use alignment::layers_align_and_resample;
 use anyhow::Result;
-use core::context::SpatialContext;
-use geo::{Coord, Rect};
+use clap::Parser;
 use loader::load_layers;
-use physics::physics_analyze;
-use post_process::terrain_post_process;
 use std::path::Path;
 use tap::Tap;
+use tiling::run_tiled;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    if let Err(e) = run_pipeline().await {
+    let cli = Cli::parse();
+    let result = match &cli.command {
+        Some(Command::Preprocess(args)) => {
+            preprocess_datasets(
+                &args.raw,
+                Path::new(DATASETS_PATH),
+                args.force,
+                &args.scan.scan_options(),
+            )
+            .await
+        }
+        Some(Command::Plan(args)) => run_plan(args).await,
+        None => run_pipeline(&cli.build).await,
+    };
+    if let Err(e) = result {
         eprintln!("Error: {e}");
     }
     Ok(())
 }
 
-async fn run_pipeline() -> Result<()> {
-    let roi = Rect::new(
-        Coord {
-            x: 94.02376,
-            y: 30.15698,
-        },
-        Coord {
-            x: 93.84993,
-            y: 29.97956,
-        },
-    );
+async fn run_plan(args: &PlanArgs) -> Result<()> {
+    let (roi, _) = args.build.spatial_context()?;
+    plan_datasets(&roi, &args.build.scan_options(), args.output.as_deref()).await
+}
 
-    let ctx = SpatialContext::analyze(roi).tap(|ctx| println!("{ctx}"));
+async fn run_pipeline(cli: &BuildArgs) -> Result<()> {
+    configure_block_cache(cli.cache_memory);
+    let config = Config::load(cli.config.as_deref())?;
+
+    let output_root = Path::new("output");
 
-    let terrain = scan_datasets()
+    let (roi, ctx) = cli.spatial_context()?.tap(|(_, ctx)| println!("{ctx}"));
+
+    let assets = scan_datasets(&cli.scan_options())
         .await?
-        .try_tap(|c| validate_data_catalog(c, roi))?
-        .try_pipe(|c| load_layers(&c))?
-        .try_pipe(|assets| layers_align_and_resample(&assets, &ctx))?
-        .try_tap_mut(terrain_post_process)?
-        .try_tap(validate_terrain_grid)?;
+        .try_tap(|c| validate_data_catalog(c, &roi, output_root))?
+        .try_pipe(|c| load_layers(&c, cli.blend, ctx.pixel_size, &config.resampling))?;
 
-    let physics_map = physics_analyze(&terrain, &ctx)?;
+    let checkpoints = cli.checkpoints.then_some(Path::new(CHECKPOINTS_PATH));
+    let layer_rasters = (!cli.export_layers.is_empty())
+        .then(|| {
+            LayerRasters::new(
+                &output_root.join("layers"),
+                &ctx,
+                &assets.metas(),
+                &cli.export_layers,
+            )
+        })
+        .transpose()?;
+    let previews = (!cli.preview.is_empty()).then(|| {
+        Previews::new(
+            &output_root.join("preview"),
+            &ctx,
+            &cli.preview,
+            cli.preview_grid,
+            cli.preview_size,
+        )
+    });
+    let mut outputs = Outputs::new(
+        (!cli.preview_only).then(|| output_root.to_path_buf()),
+        cli.outside,
+        layer_rasters,
+        previews,
+    );
+
+    if let Some(tile_size) = cli.tile_size {
+        return run_tiled(&assets, &ctx, tile_size, checkpoints, outputs);
+    }
+
+    let (terrain, physics_map) = run_stages(&assets, &ctx, None, checkpoints)?;
 
     let avg_slope: f32 = physics_map.slope.iter().sum::<f32>()
         / physics_map.slope.len() as f32
         / std::f32::consts::PI;
     println!("Average Slope: {:.4}π rad", avg_slope);
+    println!("{}", block_cache().stats());
 
-    let output_root = Path::new("output");
-    generate_world(output_root, &terrain)?;
-
-    Ok(())
+    outputs.plan_world(
+        terrain.min_elevation,
+        terrain.max_elevation,
+        soil_bottom_cm(terrain.layers.iter().map(|l| &l.meta)),
+        terrain.pixel_size,
+    );
+    let area = PixelWindow {
+        x: 0,
+        y: 0,
+        width: terrain.width,
+        height: terrain.height,
+    };
+    outputs.write(&terrain, &physics_map, area)?;
+    outputs.finish()
 }
@@ -35,7 +35,7 @@ pub fn calc_hli(
             let x = i % w;
             let y = i / w;
             let geo = ctx.get_geo_coord(x, y);
-            let gamma = ctx.ltm.convergence_angle(geo.x, geo.y) as f32;
+            let gamma = ctx.projection.convergence_angle(geo.x, geo.y) as f32;
 
             let corrected_aspect = a - gamma;
 
@@ -8,6 +8,8 @@ pub fn calc_geometry(grid: &TerrainGrid, bar: &ProgressBar) -> (Vec<f32>, Vec<f3
     let w = grid.width;
     let h = grid.height;
     let len = w * h;
+    let elevation = grid.elevation();
+    let cell_size = grid.pixel_size as f32;
 
     type GeometryDataRow = (usize, Vec<f32>, Vec<f32>, Vec<f32>);
 
@@ -26,15 +28,15 @@ pub fn calc_geometry(grid: &TerrainGrid, bar: &ProgressBar) -> (Vec<f32>, Vec<f3
             for x in 1..w - 1 {
                 let idx = y * w + x;
 
-                if grid.elevation[idx].is_nan() {
+                if elevation[idx].is_nan() {
                     continue;
                 }
 
-                let center_z = grid.elevation[idx];
+                let center_z = elevation[idx];
                 let get = |dx: isize, dy: isize| -> f32 {
                     let nx = (x as isize + dx) as usize;
                     let ny = (y as isize + dy) as usize;
-                    grid.elevation[ny * w + nx].pipe_when(|v| v.is_nan(), |_| center_z)
+                    elevation[ny * w + nx].pipe_when(|v| v.is_nan(), |_| center_z)
                 };
 
                 let z1 = get(-1, -1);
@@ -47,8 +49,6 @@ pub fn calc_geometry(grid: &TerrainGrid, bar: &ProgressBar) -> (Vec<f32>, Vec<f3
                 let z8 = get(0, 1);
                 let z9 = get(1, 1);
 
-                let cell_size = 1.0;
-
                 let dz_dx = ((z3 + 2.0 * z6 + z9) - (z1 + 2.0 * z4 + z7)) / (8.0 * cell_size);
                 let dz_dy = ((z7 + 2.0 * z8 + z9) - (z1 + 2.0 * z2 + z3)) / (8.0 * cell_size);
 
@@ -1,10 +1,47 @@
 use crate::core::terrain::TerrainGrid;
+use crate::post_process::backdrop::Backdrop;
 use crate::utils::float::FloatEx;
 use indicatif::ProgressBar;
 use rayon::prelude::*;
 use std::sync::atomic::{AtomicU8, Ordering};
 
-pub fn calc_flow_accumulation(grid: &TerrainGrid, bar: &ProgressBar) -> Vec<f32> {
+/// Longest upslope flow path in pixels counted from a tile itself. Pixels
+/// draining a longer path take their upslope area from the backdrop instead,
+/// so the result never depends on how far the tile extends past them.
+pub const FLOW_REACH: usize = 128;
+
+/// Upslope area of every pixel in pixels. Exact without a `backdrop`, and
+/// exact within `FLOW_REACH` and from `backdrop` beyond it otherwise.
+pub fn calc_flow_accumulation(
+    grid: &TerrainGrid,
+    backdrop: Option<&Backdrop>,
+    bar: &ProgressBar,
+) -> Vec<f32> {
+    let (accumulation, path_len) = calc_upslope_paths(grid, bar);
+    let Some(backdrop) = backdrop else {
+        return accumulation;
+    };
+
+    let width = grid.width;
+    let (x0, y0) = grid.offset;
+    accumulation
+        .into_par_iter()
+        .zip(path_len)
+        .enumerate()
+        .map(|(idx, (area, len))| {
+            if len as usize <= FLOW_REACH {
+                area
+            } else {
+                let (x, y) = (x0 + idx % width, y0 + idx / width);
+                backdrop.flow(x, y).max((FLOW_REACH + 1) as f32)
+            }
+        })
+        .collect()
+}
+
+/// Upslope area and longest upslope path of every pixel, however far they
+/// reach.
+pub fn calc_upslope_paths(grid: &TerrainGrid, bar: &ProgressBar) -> (Vec<f32>, Vec<u32>) {
     let width = grid.width;
     let height = grid.height;
     let total_pixels = width * height;
@@ -18,6 +55,7 @@ pub fn calc_flow_accumulation(grid: &TerrainGrid, bar: &ProgressBar) -> Vec<f32>
 fn compute_downstream_map(grid: &TerrainGrid, bar: &ProgressBar) -> Vec<Option<u32>> {
     let width = grid.width;
     let height = grid.height;
+    let elevation = grid.elevation();
 
     let rows: Vec<Option<u32>> = (0..height)
         .into_par_iter()
@@ -35,12 +73,14 @@ fn compute_downstream_map(grid: &TerrainGrid, bar: &ProgressBar) -> Vec<Option<u
 
             for x in 1..width - 1 {
                 let idx = y * width + x;
-                let current_z = grid.elevation[idx];
+                let current_z = elevation[idx];
 
                 if current_z.is_nan() {
                     row_result.push(None);
                 } else {
-                    row_result.push(find_lowest_neighbor_unrolled(grid, x, y, current_z));
+                    row_result.push(find_lowest_neighbor_unrolled(
+                        elevation, width, x, y, current_z,
+                    ));
                 }
             }
 
@@ -56,12 +96,12 @@ fn compute_downstream_map(grid: &TerrainGrid, bar: &ProgressBar) -> Vec<Option<u
 
 #[inline(always)]
 fn find_lowest_neighbor_unrolled(
-    grid: &TerrainGrid,
+    elevation: &[f32],
+    w: usize,
     x: usize,
     y: usize,
     current_z: f32,
 ) -> Option<u32> {
-    let w = grid.width;
     let idx = y * w + x;
 
     #[rustfmt::skip]
@@ -75,7 +115,7 @@ fn find_lowest_neighbor_unrolled(
     let mut min_idx = u32::MAX;
 
     for &ni in &n_idxes {
-        let nz = grid.elevation[ni];
+        let nz = elevation[ni];
 
         let mask = nz < min_z;
 
@@ -120,12 +160,14 @@ fn perform_topological_accumulation(
     grid: &TerrainGrid,
     count: usize,
     bar: &ProgressBar,
-) -> Vec<f32> {
+) -> (Vec<f32>, Vec<u32>) {
     let mut accumulation = vec![1.0; count];
+    let mut path_len = vec![0u32; count];
     let mut processing_stack = Vec::with_capacity(count / 10);
+    let elevation = grid.elevation();
 
     for (index, item) in in_degree_map.iter().enumerate().take(count) {
-        if item.load(Ordering::Relaxed) == 0 && grid.elevation[index].is_not_nan() {
+        if item.load(Ordering::Relaxed) == 0 && elevation[index].is_not_nan() {
             processing_stack.push(index);
         }
     }
@@ -142,6 +184,7 @@ fn perform_topological_accumulation(
         if let Some(target) = downstream_map[current_index] {
             let target_index = target as usize;
             accumulation[target_index] += accumulation[current_index];
+            path_len[target_index] = path_len[target_index].max(path_len[current_index] + 1);
 
             if in_degree_map[target_index].fetch_sub(1, Ordering::Relaxed) == 1 {
                 processing_stack.push(target_index);
@@ -153,11 +196,14 @@ fn perform_topological_accumulation(
         bar.inc((processed_count % UPDATE_BATCH) as u64);
     }
 
-    accumulation
+    (accumulation, path_len)
 }
 
-pub fn calc_twi_final(flow: &[f32], slope: &[f32], bar: &ProgressBar) -> Vec<f32> {
+/// `ln(a / tan(slope))`, where the specific catchment area `a` is the upslope
+/// cell count times the cell area per unit contour width.
+pub fn calc_twi_final(flow: &[f32], slope: &[f32], cell_size: f64, bar: &ProgressBar) -> Vec<f32> {
     let chunk_size = 10_000;
+    let cell_size = cell_size as f32;
 
     flow.par_iter()
         .zip(slope.par_iter())
@@ -168,7 +214,7 @@ pub fn calc_twi_final(flow: &[f32], slope: &[f32], bar: &ProgressBar) -> Vec<f32
             }
 
             let tan_slope = slope_val.tan().max(0.001);
-            (flow_val / tan_slope).ln().max(0.0)
+            (flow_val * cell_size / tan_slope).ln().max(0.0)
         })
         .collect()
 }
@@ -4,13 +4,19 @@ pub mod hydro;
 
 use crate::core::context::SpatialContext;
 use crate::core::terrain::TerrainGrid;
+use crate::post_process::backdrop::Backdrop;
 use crate::utils::progress::create_progress_bar;
 use anyhow::Result;
 use climate::calc_hli;
 use geometry::calc_geometry;
-use hydro::{calc_flow_accumulation, calc_twi_final};
+use hydro::{FLOW_REACH, calc_flow_accumulation, calc_twi_final};
 use indicatif::MultiProgress;
 
+/// Pixels around a tile that the stages below read from: flow paths are
+/// followed `FLOW_REACH` pixels upslope, plus one more to tell whether they
+/// go on, and the 3x3 terrain kernels need one.
+pub const HALO: usize = FLOW_REACH + 2;
+
 #[derive(Debug)]
 pub struct PhysicsMap {
     pub slope: Vec<f32>,
@@ -20,7 +26,33 @@ pub struct PhysicsMap {
     pub hli: Vec<f32>,
 }
 
-pub fn physics_analyze(grid: &TerrainGrid, ctx: &SpatialContext) -> Result<PhysicsMap> {
+impl PhysicsMap {
+    /// Name, label and units of every analysed layer, in field order.
+    pub const LAYERS: [(&'static str, &'static str, &'static str); 5] = [
+        ("slope", "Slope", "rad"),
+        ("aspect", "Aspect", "rad"),
+        ("tpi", "TPI", "m"),
+        ("twi", "TWI", "index"),
+        ("hli", "HLI", "index"),
+    ];
+
+    pub fn layer(&self, name: &str) -> Option<&[f32]> {
+        match name {
+            "slope" => Some(&self.slope),
+            "aspect" => Some(&self.aspect),
+            "tpi" => Some(&self.tpi),
+            "twi" => Some(&self.twi),
+            "hli" => Some(&self.hli),
+            _ => None,
+        }
+    }
+}
+
+pub fn physics_analyze(
+    grid: &TerrainGrid,
+    ctx: &SpatialContext,
+    backdrop: Option<&Backdrop>,
+) -> Result<PhysicsMap> {
     let multi_bar = MultiProgress::new();
     let total_pixels = (grid.width * grid.height) as u64;
 
@@ -43,10 +75,10 @@ pub fn physics_analyze(grid: &TerrainGrid, ctx: &SpatialContext) -> Result<Physi
 
             (s, a, t, h)
         },
-        || calc_flow_accumulation(grid, &bar_hydro),
+        || calc_flow_accumulation(grid, backdrop, &bar_hydro),
     );
 
-    let twi = calc_twi_final(&flow_acc, &slope, &bar_hydro);
+    let twi = calc_twi_final(&flow_acc, &slope, grid.pixel_size, &bar_hydro);
     bar_hydro.finish();
 
     Ok(PhysicsMap {
@@ -57,3 +89,72 @@ pub fn physics_analyze(grid: &TerrainGrid, ctx: &SpatialContext) -> Result<Physi
         hli,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::context::{Length, Snap};
+    use crate::core::layer::{LayerKind, LayerMeta};
+    use crate::core::projection::ProjectionKind;
+    use crate::core::terrain::ELEVATION;
+    use geo::Coord;
+    use hydro::calc_upslope_paths;
+    use indicatif::ProgressBar;
+
+    const PIXEL_SIZE: f64 = 30.0;
+
+    /// Upslope area of every pixel, counted by walking down from each pixel
+    /// to the strictly lowest of its neighbours until none is lower.
+    fn walked_upslope_area(elevation: &[f32], w: usize, h: usize) -> Vec<f32> {
+        let downstream = |i: usize| {
+            let (x, y) = (i % w, i / w);
+            if x == 0 || y == 0 || x == w - 1 || y == h - 1 {
+                return None;
+            }
+            (y - 1..=y + 1)
+                .flat_map(|ny| (x - 1..=x + 1).map(move |nx| ny * w + nx))
+                .filter(|&n| elevation[n] < elevation[i])
+                .min_by(|&a, &b| elevation[a].total_cmp(&elevation[b]))
+        };
+
+        let mut area = vec![0.0; w * h];
+        for i in 0..w * h {
+            let mut next = Some(i);
+            while let Some(j) = next {
+                area[j] += 1.0;
+                next = downstream(j);
+            }
+        }
+        area
+    }
+
+    #[test]
+    fn untiled_twi_follows_long_flow_paths() {
+        let (w, h) = (600, 40);
+        let ctx = SpatialContext::around(
+            Coord { x: 10.0, y: 45.0 },
+            Length::Blocks(w),
+            Length::Blocks(h),
+            PIXEL_SIZE,
+            Snap::None,
+            ProjectionKind::Adaptive,
+        )
+        .unwrap();
+        let meta = LayerMeta::new(ELEVATION, "Elevation", LayerKind::Continuous, "m");
+        let mut grid = TerrainGrid::new(w, h, PIXEL_SIZE, vec![meta]);
+        // A valley running the length of the grid, draining towards x = 0.
+        for (i, v) in grid.elevation_mut().iter_mut().enumerate() {
+            let (x, y) = ((i % w) as f32, (i / w) as f32);
+            *v = 100.0 + 0.5 * (y - 20.3).abs() + 0.0137 * x;
+        }
+
+        let bar = ProgressBar::hidden();
+        let (_, path_len) = calc_upslope_paths(&grid, &bar);
+        assert!(path_len.iter().any(|&len| len as usize > 4 * FLOW_REACH));
+
+        let physics_map = physics_analyze(&grid, &ctx, None).unwrap();
+        let area = walked_upslope_area(grid.elevation(), w, h);
+        let expected = calc_twi_final(&area, &physics_map.slope, PIXEL_SIZE, &bar);
+        assert_eq!(physics_map.twi, expected);
+    }
+}
@@ -0,0 +1,119 @@
+use super::fill::{FillBase, fill_voids_continuous, fill_voids_discrete};
+use super::{FILL_LEVELS, ITERS_SMOOTH, calc_fill_ticks};
+use crate::alignment::layers_align_and_resample;
+use crate::core::context::SpatialContext;
+use crate::core::layer::LayerData;
+use crate::core::terrain::TerrainGrid;
+use crate::loader::bundle::LayerBundle;
+use crate::physics::hydro::calc_flow_accumulation;
+use crate::utils::progress::create_progress_bar;
+use anyhow::Result;
+use indicatif::ProgressBar;
+use std::cell::OnceCell;
+
+/// Output pixels per backdrop cell along each axis.
+pub const CELL: usize = 1 << FILL_LEVELS;
+
+/// The whole ROI at [`CELL`] times the output pixel size, void filled without
+/// a distance limit. Tiles fall back to it for voids wider than the fill
+/// pyramid reaches and for upslope areas longer than their halo, so every
+/// tile of a tiled run agrees with its neighbours across the seams.
+pub struct Backdrop {
+    grid: TerrainGrid,
+    /// Upslope area of every cell in output pixels
+    flow: Vec<f32>,
+}
+
+impl Backdrop {
+    /// Samples `assets` over the full ROI grid `ctx` at the backdrop scale.
+    pub fn sample(assets: &LayerBundle, ctx: &SpatialContext) -> Result<Self> {
+        Ok(Self::new(layers_align_and_resample(
+            assets,
+            &ctx.coarsened(CELL),
+        )?))
+    }
+
+    /// A backdrop from the aligned grid of the full ROI at the backdrop scale.
+    pub fn new(mut grid: TerrainGrid) -> Self {
+        let (w, h) = (grid.width, grid.height);
+        let ticks = calc_fill_ticks(w, h, ITERS_SMOOTH, None);
+        let bar = create_progress_bar(grid.layers.len() as u64 * ticks, "Backdrop Fill");
+
+        let mut f32_aux_buffer = vec![f32::NAN; w * h];
+        let mut u8_aux_buffer = vec![None; w * h];
+        for layer in &mut grid.layers {
+            match &mut layer.data {
+                LayerData::Continuous(data) => {
+                    fill_voids_continuous(data, &mut f32_aux_buffer, w, h, ITERS_SMOOTH, None, &bar)
+                }
+                LayerData::Categorical(data) => {
+                    fill_voids_discrete(data, &mut u8_aux_buffer, w, h, ITERS_SMOOTH, None, &bar)
+                }
+                LayerData::Mask(_) => bar.inc(ticks),
+            }
+        }
+        bar.finish();
+
+        let cell_area = (CELL * CELL) as f32;
+        let flow = calc_flow_accumulation(&grid, None, &ProgressBar::hidden())
+            .into_iter()
+            .map(|cells| cells * cell_area)
+            .collect();
+
+        Self { grid, flow }
+    }
+
+    /// Base for filling the layer `values` picks, in a grid whose first pixel
+    /// is at `offset` in the full ROI grid.
+    pub fn fill_base<'a, T>(
+        &'a self,
+        offset: (usize, usize),
+        values: impl FnOnce(&'a TerrainGrid) -> Option<&'a [T]>,
+    ) -> Option<FillBase<'a, T>> {
+        Some(FillBase {
+            values: values(&self.grid)?,
+            width: self.grid.width,
+            origin: (offset.0 / CELL, offset.1 / CELL),
+            levels: FILL_LEVELS,
+        })
+    }
+
+    /// Upslope area in output pixels around pixel `(x, y)` of the full ROI grid.
+    pub fn flow(&self, x: usize, y: usize) -> f32 {
+        self.flow[(y / CELL) * self.grid.width + x / CELL]
+    }
+}
+
+/// The backdrop of the full ROI grid, sampled the first time a tile needs it,
+/// so a run resuming every tile from checkpoints never samples it.
+pub struct LazyBackdrop<'a> {
+    assets: &'a LayerBundle,
+    ctx: &'a SpatialContext,
+    backdrop: OnceCell<Backdrop>,
+}
+
+impl<'a> LazyBackdrop<'a> {
+    pub fn new(assets: &'a LayerBundle, ctx: &'a SpatialContext) -> Self {
+        Self {
+            assets,
+            ctx,
+            backdrop: OnceCell::new(),
+        }
+    }
+
+    pub fn get(&self) -> Result<&Backdrop> {
+        if let Some(backdrop) = self.backdrop.get() {
+            return Ok(backdrop);
+        }
+        let backdrop = Backdrop::sample(self.assets, self.ctx)?;
+        Ok(self.backdrop.get_or_init(|| backdrop))
+    }
+
+    /// Width and height in cells, known without sampling.
+    pub fn size(&self) -> (usize, usize) {
+        (
+            self.ctx.width.div_ceil(CELL),
+            self.ctx.height.div_ceil(CELL),
+        )
+    }
+}
@@ -1,17 +1,24 @@
-use crate::core::terrain::TerrainGrid;
+use crate::core::terrain::{ROI_MASK, TerrainGrid};
 use indicatif::ProgressBar;
-use rayon::iter::ParallelIterator;
+use rayon::iter::{IndexedParallelIterator, ParallelIterator};
 use rayon::slice::ParallelSlice;
 
+/// Elevation range over the pixels inside the ROI polygon, or the whole grid
+/// for rectangular ROIs.
 pub fn compute_elevation(grid: &mut TerrainGrid, bar: &ProgressBar) {
+    let w = grid.width;
+    let inside = grid.mask(ROI_MASK);
     let (min, max) = grid
-        .elevation
-        .par_chunks(grid.width)
-        .map(|chunk| {
+        .elevation()
+        .par_chunks(w)
+        .enumerate()
+        .map(|(y, chunk)| {
             bar.inc(1);
             chunk
                 .iter()
-                .fold((f32::MAX, f32::MIN), |(acc_min, acc_max), &val| {
+                .enumerate()
+                .filter(|(x, _)| inside.is_none_or(|inside| inside[y * w + x]))
+                .fold((f32::MAX, f32::MIN), |(acc_min, acc_max), (_, &val)| {
                     if val.is_nan() {
                         (acc_min, acc_max)
                     } else {
@@ -3,18 +3,28 @@ use crate::core::terrain::TerrainGrid;
 use noise::{NoiseFn, Perlin};
 use rayon::prelude::*;
 
+const PERSISTENCE: f64 = 0.5;
+const BASE_AMPLITUDE: f64 = 1.5;
+
+/// Upper bound in meters of the detail added to any pixel.
+pub const MAX_AMPLITUDE: f64 = BASE_AMPLITUDE / (1.0 - PERSISTENCE);
+
 pub fn apply_fbm(grid: &mut TerrainGrid, bar: &ProgressBar) {
     const OCTAVES: u32 = 4;
-    const PERSISTENCE: f64 = 0.5;
     const LACUNARITY: f64 = 2.0;
+    // Frequency of the first octave in cycles per meter
     const BASE_SCALE: f64 = 0.02;
-    const BASE_AMPLITUDE: f64 = 1.5;
     const SEED: u32 = 2024;
 
     let perlin = Perlin::new(SEED);
     let width = grid.width;
+    let pixel_size = grid.pixel_size;
+    // Noise is laid over the full ROI grid so tiles line up.
+    let (x_offset, y_offset) = grid.offset;
+    // Octaves finer than two pixels would only alias.
+    let nyquist = 0.5 / pixel_size;
 
-    grid.elevation
+    grid.elevation_mut()
         .par_chunks_mut(width)
         .enumerate()
         .for_each(|(y, row)| {
@@ -23,14 +33,17 @@ pub fn apply_fbm(grid: &mut TerrainGrid, bar: &ProgressBar) {
                     continue;
                 }
 
-                let nx = x as f64;
-                let ny = y as f64;
+                let nx = (x + x_offset) as f64 * pixel_size;
+                let ny = (y + y_offset) as f64 * pixel_size;
 
                 let mut amplitude = BASE_AMPLITUDE;
                 let mut frequency = BASE_SCALE;
                 let mut noise_acc = 0.0;
 
                 for _ in 0..OCTAVES {
+                    if frequency > nyquist {
+                        break;
+                    }
                     let n = perlin.get([nx * frequency, ny * frequency]);
                     noise_acc += n * amplitude;
 
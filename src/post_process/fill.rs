@@ -2,15 +2,39 @@ use crate::post_process::UNIT_LEN;
 use crate::utils::float::FloatEx;
 use indicatif::ProgressBar;
 use rayon::prelude::*;
+use std::cmp::Reverse;
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicBool, Ordering};
 
+/// Values that voids fall back to once the pyramid has coarsened the grid
+/// `levels` times, one per cell of the full ROI grid at that scale. Its cells
+/// line up with the pyramid's as long as the grid starts on a cell boundary,
+/// so a window of the grid fills exactly as the whole grid would.
+#[derive(Clone, Copy)]
+pub struct FillBase<'a, T> {
+    pub values: &'a [T],
+    pub width: usize,
+    /// Cell holding the grid's first pixel
+    pub origin: (usize, usize),
+    pub levels: u32,
+}
+
+impl<T> FillBase<'_, T> {
+    fn coarser(self) -> Self {
+        Self {
+            levels: self.levels - 1,
+            ..self
+        }
+    }
+}
+
 pub fn fill_voids_continuous(
     data: &mut Vec<f32>,
     aux: &mut [f32],
     width: usize,
     height: usize,
     iters: u64,
+    base: Option<FillBase<f32>>,
     bar: &ProgressBar,
 ) {
     let is_valid = |v: &f32| v.is_not_nan();
@@ -63,6 +87,7 @@ pub fn fill_voids_continuous(
         width,
         height,
         iters,
+        base,
         bar,
         is_valid,
         invalid_val,
@@ -77,6 +102,7 @@ pub fn fill_voids_discrete(
     width: usize,
     height: usize,
     iters: u64,
+    base: Option<FillBase<Option<u8>>>,
     bar: &ProgressBar,
 ) {
     let is_valid = |v: &Option<u8>| v.is_some();
@@ -87,9 +113,11 @@ pub fn fill_voids_discrete(
         for inner in vals.iter().flatten() {
             *counts.entry(*inner).or_insert(0) += 1;
         }
+        // Ties go to the lowest class, so the result does not depend on the
+        // map's iteration order.
         counts
             .into_iter()
-            .max_by_key(|&(_, c)| c)
+            .max_by_key(|&(v, c)| (c, Reverse(v)))
             .map(|(v, _)| Some(v))
             .unwrap_or(None)
     };
@@ -106,6 +134,7 @@ pub fn fill_voids_discrete(
         width,
         height,
         iters,
+        base,
         bar,
         is_valid,
         invalid_val,
@@ -121,6 +150,7 @@ fn fill_voids_pipeline<T, FValid, FInvalid, FReduce, FInterp>(
     width: usize,
     height: usize,
     iters: u64,
+    base: Option<FillBase<T>>,
     bar: &ProgressBar,
     is_valid: FValid,
     invalid_val: FInvalid,
@@ -133,7 +163,11 @@ fn fill_voids_pipeline<T, FValid, FInvalid, FReduce, FInterp>(
     FReduce: Fn(&[T]) -> T + Sync + Send + Copy,
     FInterp: Fn(&[T], usize, usize, usize, usize) -> T + Sync + Send + Copy,
 {
-    if width < UNIT_LEN || height < UNIT_LEN {
+    if let Some(base) = base.filter(|b| b.levels == 0) {
+        fill_from_base(data, width, base, bar, is_valid);
+        return;
+    }
+    if base.is_none() && (width < UNIT_LEN || height < UNIT_LEN) {
         iterate_core(
             data,
             aux,
@@ -170,6 +204,7 @@ fn fill_voids_pipeline<T, FValid, FInvalid, FReduce, FInterp>(
         small_w,
         small_h,
         iters,
+        base.map(FillBase::coarser),
         bar,
         is_valid,
         invalid_val,
@@ -189,6 +224,12 @@ fn fill_voids_pipeline<T, FValid, FInvalid, FReduce, FInterp>(
         interpolator,
     );
 
+    // Upsampling a complete base leaves no voids, and skipping the iterations
+    // keeps how far a fill reaches bounded.
+    if base.is_some() {
+        return;
+    }
+
     iterate_core(
         data,
         aux,
@@ -202,6 +243,27 @@ fn fill_voids_pipeline<T, FValid, FInvalid, FReduce, FInterp>(
     );
 }
 
+fn fill_from_base<T, FValid>(
+    data: &mut [T],
+    width: usize,
+    base: FillBase<T>,
+    bar: &ProgressBar,
+    is_valid: FValid,
+) where
+    T: Copy + Send + Sync,
+    FValid: Fn(&T) -> bool + Sync + Send,
+{
+    data.par_chunks_mut(width).enumerate().for_each(|(y, row)| {
+        let base_row = (base.origin.1 + y) * base.width + base.origin.0;
+        for (x, pixel) in row.iter_mut().enumerate() {
+            if !is_valid(pixel) {
+                *pixel = base.values[base_row + x];
+            }
+        }
+        bar.inc(1);
+    });
+}
+
 #[allow(clippy::too_many_arguments)]
 fn downsample_generic<T, FValid, FInvalid, FReduce>(
     src: &[T],
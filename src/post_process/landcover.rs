@@ -0,0 +1,67 @@
+use crate::core::terrain::{LANDCOVER, LANDCOVER_CONFIDENCE, TerrainGrid};
+use indicatif::ProgressBar;
+use rayon::prelude::*;
+
+/// Pixels below this confidence are re-classified from their neighbourhood.
+pub const LOW_CONFIDENCE: f32 = 0.5;
+pub const RADIUS: usize = 2;
+const CLASS_COUNT: usize = 256;
+
+/// Replaces low-confidence landcover classes with the confidence-weighted
+/// majority of the surrounding window. The pixel's own vote is kept, so a
+/// neighbourhood that is just as uncertain leaves it unchanged.
+pub fn reclassify_low_confidence(grid: &mut TerrainGrid, bar: &ProgressBar) {
+    let width = grid.width;
+    let height = grid.height;
+    let (Some(source), Some(confidence)) = (
+        grid.categorical(LANDCOVER),
+        grid.continuous(LANDCOVER_CONFIDENCE),
+    ) else {
+        bar.inc(height as u64);
+        return;
+    };
+    let mut landcover = source.to_vec();
+
+    landcover
+        .par_chunks_mut(width)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let mut votes = [0.0f32; CLASS_COUNT];
+
+            for (x, class) in row.iter_mut().enumerate() {
+                let idx = y * width + x;
+                let own = confidence[idx];
+                if own.is_nan() || own >= LOW_CONFIDENCE || class.is_none() {
+                    continue;
+                }
+
+                votes.fill(0.0);
+                for ny in y.saturating_sub(RADIUS)..(y + RADIUS + 1).min(height) {
+                    for nx in x.saturating_sub(RADIUS)..(x + RADIUS + 1).min(width) {
+                        let n_idx = ny * width + nx;
+                        let weight = confidence[n_idx];
+                        if let Some(c) = source[n_idx]
+                            && !weight.is_nan()
+                        {
+                            votes[c as usize] += weight;
+                        }
+                    }
+                }
+
+                let (best, _) = votes
+                    .iter()
+                    .enumerate()
+                    .max_by(|a, b| a.1.total_cmp(b.1))
+                    .unwrap();
+                if votes[best] > 0.0 {
+                    *class = Some(best as u8);
+                }
+            }
+
+            bar.inc(1);
+        });
+
+    if let Some(data) = grid.categorical_mut(LANDCOVER) {
+        *data = landcover;
+    }
+}
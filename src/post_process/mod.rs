@@ -1,51 +1,87 @@
+pub mod backdrop;
 mod elevation;
-mod fbm;
+pub mod fbm;
 pub mod fill;
+pub mod landcover;
 pub mod median;
 
+use crate::core::layer::LayerData;
 use crate::core::terrain::TerrainGrid;
+use crate::post_process::backdrop::{Backdrop, CELL};
 use crate::post_process::elevation::compute_elevation;
 use crate::post_process::fbm::apply_fbm;
 use crate::utils::progress::create_progress_bar;
 use anyhow::Result;
 use fill::{fill_voids_continuous, fill_voids_discrete};
+use landcover::reclassify_low_confidence;
 use median::apply_median;
 
 const UNIT_LEN: usize = 256;
-
-pub fn terrain_post_process(grid: &mut TerrainGrid) -> Result<()> {
+const ITERS_SMOOTH: u64 = 5;
+
+/// Times the void fill pyramid halves the grid before falling back to the
+/// backdrop.
+const FILL_LEVELS: u32 = 5;
+
+/// Pixels a filled void reads from: each pyramid level looks one cell past
+/// its own, adding up to under four cells of the coarsest level, plus the
+/// coarsest cell itself.
+const FILL_REACH: usize = 5 << FILL_LEVELS;
+
+/// Pixels around a tile that the stages below read from: void filling reaches
+/// `FILL_REACH`, low-confidence landcover looks `landcover::RADIUS` pixels out
+/// and the median filter one.
+pub const HALO: usize = FILL_REACH + landcover::RADIUS + 1;
+
+/// Voids are filled without a distance limit unless `backdrop` is set, in
+/// which case `grid` has to start on a backdrop cell boundary of the full ROI
+/// grid and voids wider than the fill pyramid reaches are filled from it.
+pub fn terrain_post_process(grid: &mut TerrainGrid, backdrop: Option<&Backdrop>) -> Result<()> {
     let h = grid.height;
     let w = grid.width;
-
-    const ITERS_SMOOTH: u64 = 5;
+    let offset = grid.offset;
+    debug_assert!(
+        backdrop.is_none() || offset.0.is_multiple_of(CELL) && offset.1.is_multiple_of(CELL)
+    );
 
     let count_continuous = get_continuous_layers(grid).len() as u64;
     let count_discrete = get_discrete_layers(grid).len() as u64;
     let count_median = get_median_layers(grid).len() as u64;
 
-    let ticks_per_fill = calc_fill_ticks(w, h, ITERS_SMOOTH);
+    let ticks_per_fill = calc_fill_ticks(w, h, ITERS_SMOOTH, backdrop.map(|_| FILL_LEVELS));
     let fill_steps = (count_continuous + count_discrete) * ticks_per_fill;
+    let reclassify_steps = h as u64;
     let median_steps = count_median * h as u64;
     let fbm_steps = h as u64;
     let get_elevation_steps = h as u64;
-    let total_steps = fill_steps + median_steps + fbm_steps + get_elevation_steps;
+    let total_steps =
+        fill_steps + reclassify_steps + median_steps + fbm_steps + get_elevation_steps;
 
     let bar = create_progress_bar(total_steps, "Terrain Post-Process");
 
     let mut f32_aux_buffer = vec![f32::NAN; w * h];
 
-    get_continuous_layers(grid).into_iter().for_each(|layer| {
-        fill_voids_continuous(layer, &mut f32_aux_buffer, w, h, ITERS_SMOOTH, &bar);
-    });
-
     let mut u8_aux_buffer = vec![Some(0u8); w * h];
 
-    get_discrete_layers(grid).into_iter().for_each(|layer| {
-        fill_voids_discrete(layer, &mut u8_aux_buffer, w, h, ITERS_SMOOTH, &bar);
-    });
+    for layer in &mut grid.layers {
+        let name = layer.meta.name.as_str();
+        match &mut layer.data {
+            LayerData::Continuous(data) => {
+                let base = backdrop.and_then(|b| b.fill_base(offset, |g| g.continuous(name)));
+                fill_voids_continuous(data, &mut f32_aux_buffer, w, h, ITERS_SMOOTH, base, &bar);
+            }
+            LayerData::Categorical(data) => {
+                let base = backdrop.and_then(|b| b.fill_base(offset, |g| g.categorical(name)));
+                fill_voids_discrete(data, &mut u8_aux_buffer, w, h, ITERS_SMOOTH, base, &bar);
+            }
+            LayerData::Mask(_) => {}
+        }
+    }
 
     drop(u8_aux_buffer);
 
+    reclassify_low_confidence(grid, &bar);
+
     get_median_layers(grid).into_iter().for_each(|layer| {
         apply_median(layer, &mut f32_aux_buffer, w, h, &bar);
     });
@@ -59,33 +95,43 @@ pub fn terrain_post_process(grid: &mut TerrainGrid) -> Result<()> {
 }
 
 fn get_continuous_layers(g: &mut TerrainGrid) -> Vec<&mut Vec<f32>> {
-    vec![
-        &mut g.elevation,
-        &mut g.hh,
-        &mut g.hv,
-        &mut g.inc,
-        &mut g.ls,
-        &mut g.sand,
-        &mut g.clay,
-        &mut g.soc,
-        &mut g.ph,
-        &mut g.sand_sub,
-        &mut g.clay_sub,
-        &mut g.ph_sub,
-    ]
+    g.layers
+        .iter_mut()
+        .filter_map(|l| match &mut l.data {
+            LayerData::Continuous(data) => Some(data),
+            _ => None,
+        })
+        .collect()
 }
 
 fn get_discrete_layers(g: &mut TerrainGrid) -> Vec<&mut Vec<Option<u8>>> {
-    vec![&mut g.landcover]
+    g.layers
+        .iter_mut()
+        .filter_map(|l| match &mut l.data {
+            LayerData::Categorical(data) => Some(data),
+            _ => None,
+        })
+        .collect()
 }
 
 fn get_median_layers(g: &mut TerrainGrid) -> Vec<&mut Vec<f32>> {
-    vec![&mut g.sand, &mut g.clay, &mut g.soc, &mut g.ph]
+    g.layers
+        .iter_mut()
+        .filter(|l| l.meta.median)
+        .filter_map(|l| match &mut l.data {
+            LayerData::Continuous(data) => Some(data),
+            _ => None,
+        })
+        .collect()
 }
 
-fn calc_fill_ticks(w: usize, h: usize, iters: u64) -> u64 {
-    if w < UNIT_LEN || h < UNIT_LEN {
-        return (h as u64) * (UNIT_LEN as u64);
+/// Progress ticks of one fill, which falls back to a base after `base_levels`
+/// pyramid levels when set.
+fn calc_fill_ticks(w: usize, h: usize, iters: u64, base_levels: Option<u32>) -> u64 {
+    match base_levels {
+        Some(0) => return h as u64,
+        None if w < UNIT_LEN || h < UNIT_LEN => return (h as u64) * (UNIT_LEN as u64),
+        _ => {}
     }
 
     let next_w = w.div_ceil(2);
@@ -94,9 +140,11 @@ fn calc_fill_ticks(w: usize, h: usize, iters: u64) -> u64 {
     let mut ticks = 0;
 
     ticks += next_h as u64;
-    ticks += calc_fill_ticks(next_w, next_h, iters);
+    ticks += calc_fill_ticks(next_w, next_h, iters, base_levels.map(|l| l - 1));
     ticks += h as u64;
-    ticks += (h as u64) * iters;
+    if base_levels.is_none() {
+        ticks += (h as u64) * iters;
+    }
 
     ticks
 }
@@ -0,0 +1,144 @@
+mod warp;
+
+use crate::scanner::provider::{PROVIDERS, RasterKind};
+use crate::scanner::types::{DataCatalog, ScanOptions};
+use crate::utils::progress::create_progress_bar;
+use anyhow::{Result, anyhow};
+use rayon::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+use warp::warp_to_wgs84;
+
+struct PreprocessJob {
+    src: PathBuf,
+    dst: PathBuf,
+    kind: RasterKind,
+}
+
+impl PreprocessJob {
+    /// An output at least as new as its source was finished by an earlier run.
+    fn is_up_to_date(&self) -> bool {
+        let modified = |p: &Path| fs::metadata(p).and_then(|m| m.modified()).ok();
+        match (modified(&self.src), modified(&self.dst)) {
+            (Some(src), Some(dst)) => dst >= src,
+            _ => false,
+        }
+    }
+
+    fn run(&self) -> Result<()> {
+        if let Some(parent) = self.dst.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        warp_to_wgs84(&self.src, &self.dst, self.kind)
+    }
+}
+
+/// Reprojects every raw download under `raw_root` into the catalog layout under
+/// `output_root`, then scans the result.
+pub async fn preprocess_datasets(
+    raw_root: &Path,
+    output_root: &Path,
+    force: bool,
+    scan_options: &ScanOptions,
+) -> Result<()> {
+    let (jobs, unrecognized) = collect_jobs(raw_root, output_root)?;
+
+    for path in &unrecognized {
+        println!(
+            "Skipped {}: not part of a known dataset layout",
+            path.display()
+        );
+    }
+
+    let total = jobs.len();
+    let pending: Vec<_> = jobs
+        .into_iter()
+        .filter(|job| force || !job.is_up_to_date())
+        .collect();
+    println!(
+        "Preprocessing {} of {} rasters ({} already up to date)",
+        pending.len(),
+        total,
+        total - pending.len()
+    );
+
+    let bar = create_progress_bar(pending.len() as u64, "Preprocess Datasets");
+    let failures: Vec<_> = pending
+        .par_iter()
+        .filter_map(|job| {
+            let result = job.run();
+            bar.inc(1);
+            result.err().map(|e| (&job.src, e))
+        })
+        .collect();
+    bar.finish();
+
+    for (path, err) in &failures {
+        eprintln!("Failed {}: {err}", path.display());
+    }
+
+    let catalog = DataCatalog::scan(output_root.to_path_buf(), scan_options).await?;
+    println!(
+        "Catalog now holds {} Alos Palsar scenes, {} Esa WorldCover tiles and {} Soil Grids tiles",
+        catalog.alos.len(),
+        catalog.esa.len(),
+        catalog.soil.len()
+    );
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("{} rasters failed to preprocess", failures.len()))
+    }
+}
+
+fn collect_jobs(raw_root: &Path, output_root: &Path) -> Result<(Vec<PreprocessJob>, Vec<PathBuf>)> {
+    if !raw_root.exists() {
+        return Err(anyhow!("Raw datasets directory {:?} not exists", raw_root));
+    }
+
+    let mut jobs = Vec::new();
+    let mut unrecognized = Vec::new();
+
+    for entry in WalkDir::new(raw_root).into_iter().filter_map(|e| e.ok()) {
+        let path = entry.path();
+
+        let is_tif = path
+            .extension()
+            .map(|ext| {
+                matches!(
+                    ext.to_string_lossy().to_lowercase().as_str(),
+                    "tif" | "tiff"
+                )
+            })
+            .unwrap_or(false);
+
+        if !path.is_file() || !is_tif {
+            continue;
+        }
+
+        let relative = path.strip_prefix(raw_root)?;
+        let provider_dir = relative.components().next().map(|c| c.as_os_str());
+        let file_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy())
+            .unwrap_or_default();
+
+        let kind = PROVIDERS
+            .iter()
+            .find(|p| provider_dir == Some(p.dir.as_ref()))
+            .and_then(|p| p.kind_of(&file_name));
+
+        match kind {
+            Some(kind) => jobs.push(PreprocessJob {
+                src: path.to_path_buf(),
+                dst: output_root.join(relative).with_extension("tif"),
+                kind,
+            }),
+            None => unrecognized.push(path.to_path_buf()),
+        }
+    }
+
+    Ok((jobs, unrecognized))
+}
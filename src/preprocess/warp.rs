@@ -0,0 +1,87 @@
+use crate::scanner::provider::RasterKind;
+use crate::utils::dataset::DatasetEx;
+use anyhow::{Result, anyhow};
+use gdal::cpl::CslStringList;
+use gdal::raster::GdalDataType;
+use gdal::spatial_ref::SpatialRef;
+use gdal::{Dataset, DriverManager};
+use gdal_sys::GDALResampleAlg;
+use std::ffi::CString;
+use std::fs;
+use std::path::Path;
+use std::ptr;
+
+/// Maximum error in pixels allowed for the approximated warp transformer.
+const MAX_ERROR: f64 = 0.125;
+const BLOCK_SIZE: &str = "256";
+/// Overviews are halved until their longer side would drop below this many
+/// pixels.
+const MIN_OVERVIEW_SIZE: usize = 256;
+
+/// Reprojects a raster to EPSG:4326 as a tiled ZSTD GeoTIFF with internal
+/// power-of-two overviews, which the loader reads for coarse output grids.
+/// The result is written next to `dst_path` first and renamed once complete,
+/// so an interrupted run never leaves a truncated output behind.
+pub fn warp_to_wgs84(src_path: &Path, dst_path: &Path, kind: RasterKind) -> Result<()> {
+    let src = Dataset::open_dataset(src_path)?;
+    let dst_wkt = CString::new(SpatialRef::from_epsg(4326)?.to_wkt()?)?;
+
+    let resample_alg = match kind {
+        RasterKind::Continuous => GDALResampleAlg::GRA_Bilinear,
+        RasterKind::Categorical => GDALResampleAlg::GRA_NearestNeighbour,
+    };
+
+    let c_warped = unsafe {
+        gdal_sys::GDALAutoCreateWarpedVRT(
+            src.c_dataset(),
+            ptr::null(),
+            dst_wkt.as_ptr(),
+            resample_alg,
+            MAX_ERROR,
+            ptr::null(),
+        )
+    };
+    if c_warped.is_null() {
+        return Err(anyhow!("Failed to set up warp for {:?}", src_path));
+    }
+    let warped = unsafe { Dataset::from_c_dataset(c_warped) };
+
+    let predictor = match warped.rasterband(1)?.band_type() {
+        GdalDataType::Float32 | GdalDataType::Float64 => "3",
+        _ => "2",
+    };
+
+    let mut options = CslStringList::new();
+    options.set_name_value("COMPRESS", "ZSTD")?;
+    options.set_name_value("PREDICTOR", predictor)?;
+    options.set_name_value("TILED", "YES")?;
+    options.set_name_value("BLOCKXSIZE", BLOCK_SIZE)?;
+    options.set_name_value("BLOCKYSIZE", BLOCK_SIZE)?;
+    options.set_name_value("BIGTIFF", "IF_NEEDED")?;
+
+    let tmp_path = dst_path.with_extension("tif.part");
+    let driver = DriverManager::get_driver_by_name("GTiff")?;
+    let mut output = warped.create_copy(&driver, &tmp_path, &options)?;
+    build_overviews(&mut output, kind)?;
+    drop(output);
+
+    fs::rename(&tmp_path, dst_path)?;
+    Ok(())
+}
+
+fn build_overviews(dataset: &mut Dataset, kind: RasterKind) -> Result<()> {
+    let resampling = match kind {
+        RasterKind::Continuous => "AVERAGE",
+        RasterKind::Categorical => "MODE",
+    };
+    let (w, h) = dataset.raster_size();
+    let levels: Vec<i32> = (1..)
+        .map(|exp| 1 << exp)
+        .take_while(|&factor| w.max(h) / factor as usize >= MIN_OVERVIEW_SIZE)
+        .collect();
+
+    if !levels.is_empty() {
+        dataset.build_overviews(resampling, &levels, &[])?;
+    }
+    Ok(())
+}
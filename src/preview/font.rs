@@ -0,0 +1,55 @@
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+/// Height in pixels of drawn text, shadow included.
+pub const HEIGHT: usize = GLYPH_HEIGHT + 1;
+/// Horizontal distance between the starts of neighbouring glyphs.
+const ADVANCE: usize = GLYPH_WIDTH + 1;
+
+const TEXT_COLOR: [u8; 3] = [255, 255, 255];
+const SHADOW_COLOR: [u8; 3] = [0, 0, 0];
+
+/// Rows of a glyph from the top, the leftmost pixel in the highest bit. Only
+/// the characters of chunk and region labels are covered.
+fn glyph(c: char) -> Option<[u8; GLYPH_HEIGHT]> {
+    Some(match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        'r' => [0b000, 0b101, 0b110, 0b100, 0b100],
+        _ => return None,
+    })
+}
+
+/// Width in pixels of `text` as drawn, shadow included.
+pub fn text_width(text: &str) -> usize {
+    text.chars().count() * ADVANCE
+}
+
+/// Draws `text` with its top left at `origin`, in white over a dark shadow
+/// so it stays legible on any background. `plot` receives every pixel set.
+pub fn draw_text(text: &str, origin: (usize, usize), mut plot: impl FnMut(usize, usize, [u8; 3])) {
+    for (offset, color) in [(1, SHADOW_COLOR), (0, TEXT_COLOR)] {
+        for (i, rows) in text.chars().filter_map(glyph).enumerate() {
+            for (dy, row) in rows.iter().enumerate() {
+                for dx in 0..GLYPH_WIDTH {
+                    if row & (1 << (GLYPH_WIDTH - 1 - dx)) != 0 {
+                        plot(
+                            origin.0 + i * ADVANCE + dx + offset,
+                            origin.1 + dy + offset,
+                            color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,528 @@
+mod font;
+
+use crate::core::context::{PixelWindow, SpatialContext};
+use crate::core::terrain::{ELEVATION, LANDCOVER, ROI_MASK, TerrainGrid};
+use crate::physics::PhysicsMap;
+use anyhow::Result;
+use clap::ValueEnum;
+use gdal::DriverManager;
+use gdal::cpl::CslStringList;
+use gdal::raster::Buffer;
+use std::collections::BTreeMap;
+use std::f32::consts::FRAC_PI_2;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Direction and height of the light for hillshading, in degrees.
+const SUN_AZIMUTH: f32 = 315.0;
+const SUN_ALTITUDE: f32 = 45.0;
+/// Share of the elevation colour kept on fully shaded slopes.
+const MIN_TINT: f32 = 0.55;
+/// Percentiles the colour ramps are stretched between, so outliers do not
+/// wash out the rest of the image.
+const STRETCH: (f32, f32) = (0.02, 0.98);
+
+const CHUNK_BLOCKS: usize = 16;
+const REGION_BLOCKS: usize = 512;
+/// Closest spacing in image pixels at which grid lines are still drawn.
+const MIN_LINE_SPACING: usize = 4;
+
+/// Low to high elevation: lowland green through tan and brown to snow.
+const TERRAIN_RAMP: &[[u8; 3]] = &[
+    [38, 115, 66],
+    [122, 168, 84],
+    [231, 214, 140],
+    [176, 124, 76],
+    [124, 98, 90],
+    [250, 250, 250],
+];
+const VIRIDIS_RAMP: &[[u8; 3]] = &[
+    [68, 1, 84],
+    [59, 82, 139],
+    [33, 145, 140],
+    [94, 201, 98],
+    [253, 231, 37],
+];
+const HEAT_RAMP: &[[u8; 3]] = &[[0, 0, 4], [120, 28, 109], [237, 105, 37], [252, 255, 164]];
+
+/// Images that can be rendered from the grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PreviewKind {
+    /// Relief shaded from slope and aspect, lit from the northwest
+    Hillshade,
+    /// Colour-ramped elevation over the hillshade
+    Elevation,
+    /// ESA WorldCover classes in their official palette
+    Landcover,
+    /// The shallowest layer of every soil property
+    Soil,
+    /// Topographic wetness index
+    Twi,
+    /// Heat load index
+    Hli,
+}
+
+/// Game grid drawn over the previews.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GridOverlay {
+    None,
+    /// Region file borders, labelled with the file names
+    Regions,
+    /// Chunk borders and coordinates as well as regions
+    Chunks,
+}
+
+/// How an image's values map to colours.
+#[derive(Clone, Copy)]
+enum Style {
+    Hillshade,
+    /// A ramp stretched over the values, optionally tinted by the hillshade
+    Ramp {
+        ramp: &'static [[u8; 3]],
+        shaded: bool,
+    },
+    /// A ramp over a fixed range of values
+    Fixed {
+        ramp: &'static [[u8; 3]],
+        min: f32,
+        max: f32,
+    },
+    Landcover,
+}
+
+/// Where an image's values are sampled from.
+enum Source {
+    Hillshade,
+    Terrain(String),
+    Physics(&'static str),
+}
+
+struct Image {
+    name: String,
+    source: Source,
+    style: Style,
+    /// One value per image pixel, NaN where missing; class codes are stored
+    /// as their value
+    values: Vec<f32>,
+}
+
+/// Downscaled PNG renders of the full ROI grid, sampled one tile at a time
+/// and written once every tile has been drawn.
+pub struct Previews {
+    dir: PathBuf,
+    kinds: Vec<PreviewKind>,
+    overlay: GridOverlay,
+    /// Size of the full ROI grid in pixels
+    grid_size: (usize, usize),
+    /// Grid pixels per image pixel along each axis
+    step: usize,
+    width: usize,
+    height: usize,
+    /// Image pixels inside the ROI with elevation data, bottom row first
+    visible: Vec<bool>,
+    shade: Vec<f32>,
+    /// Created on the first draw, once the grid's layers are known
+    images: Vec<Image>,
+}
+
+impl Previews {
+    /// Images of the grid `ctx` describes, no larger than `max_size` pixels
+    /// on either side.
+    pub fn new(
+        dir: &Path,
+        ctx: &SpatialContext,
+        kinds: &[PreviewKind],
+        overlay: GridOverlay,
+        max_size: usize,
+    ) -> Self {
+        let step = ctx.width.max(ctx.height).div_ceil(max_size.max(1)).max(1);
+        let (width, height) = (ctx.width.div_ceil(step), ctx.height.div_ceil(step));
+        Self {
+            dir: dir.to_path_buf(),
+            kinds: kinds.to_vec(),
+            overlay,
+            grid_size: (ctx.width, ctx.height),
+            step,
+            width,
+            height,
+            visible: vec![false; width * height],
+            shade: vec![f32::NAN; width * height],
+            images: Vec::new(),
+        }
+    }
+
+    /// Samples the image pixels that fall in `area` of the full ROI grid from
+    /// `grid`, which may be a tile of the ROI as long as it covers `area`.
+    pub fn draw(&mut self, grid: &TerrainGrid, physics_map: &PhysicsMap, area: PixelWindow) {
+        if self.images.is_empty() {
+            self.images = self.plan_images(grid);
+        }
+
+        let elevation = grid.elevation();
+        let inside = grid.mask(ROI_MASK);
+        let sources: Vec<Option<&[f32]>> = self
+            .images
+            .iter()
+            .map(|image| match &image.source {
+                Source::Hillshade => None,
+                Source::Terrain(name) => grid.continuous(name),
+                Source::Physics(name) => physics_map.layer(name),
+            })
+            .collect();
+        let landcover = grid.categorical(LANDCOVER);
+
+        let (sun_azimuth, sun_altitude) = (SUN_AZIMUTH.to_radians(), SUN_ALTITUDE.to_radians());
+        let cols: Vec<(usize, usize)> = (0..self.width)
+            .map(|px| (px, self.sample_at(px, self.grid_size.0)))
+            .filter(|&(_, gx)| gx >= area.x && gx < area.x + area.width)
+            .collect();
+
+        for py in 0..self.height {
+            let gy = self.sample_at(py, self.grid_size.1);
+            if gy < area.y || gy >= area.y + area.height {
+                continue;
+            }
+            for &(px, gx) in &cols {
+                let idx = (gy - grid.offset.1) * grid.width + (gx - grid.offset.0);
+                let p = py * self.width + px;
+
+                self.visible[p] = !elevation[idx].is_nan() && inside.is_none_or(|m| m[idx]);
+                let (slope, aspect) = (physics_map.slope[idx], physics_map.aspect[idx]);
+                self.shade[p] = hillshade(slope, aspect, sun_azimuth, sun_altitude);
+
+                for (image, source) in self.images.iter_mut().zip(&sources) {
+                    image.values[p] = match (image.style, source) {
+                        (Style::Landcover, _) => {
+                            landcover.and_then(|l| l[idx]).map_or(f32::NAN, f32::from)
+                        }
+                        (_, Some(values)) => values[idx],
+                        _ => f32::NAN,
+                    };
+                }
+            }
+        }
+    }
+
+    /// Colours every image, draws the grid overlay and writes the PNGs.
+    pub fn finish(self) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        for image in &self.images {
+            let mut rgba = self.colorize(image);
+            self.draw_overlay(&mut rgba);
+
+            let path = self.dir.join(format!("{}.png", image.name));
+            write_png(&path, self.width, self.height, &rgba)?;
+            println!("Preview: {:?}", path);
+        }
+        Ok(())
+    }
+
+    fn plan_images(&self, grid: &TerrainGrid) -> Vec<Image> {
+        let len = self.width * self.height;
+        let image = |name: &str, source, style| Image {
+            name: name.to_string(),
+            source,
+            style,
+            values: vec![f32::NAN; len],
+        };
+        let viridis = Style::Ramp {
+            ramp: VIRIDIS_RAMP,
+            shaded: false,
+        };
+
+        let mut images = Vec::new();
+        for kind in &self.kinds {
+            match kind {
+                PreviewKind::Hillshade => {
+                    images.push(image("hillshade", Source::Hillshade, Style::Hillshade))
+                }
+                PreviewKind::Elevation => images.push(image(
+                    ELEVATION,
+                    Source::Terrain(ELEVATION.to_string()),
+                    Style::Ramp {
+                        ramp: TERRAIN_RAMP,
+                        shaded: true,
+                    },
+                )),
+                PreviewKind::Landcover => images.push(image(
+                    LANDCOVER,
+                    Source::Terrain(LANDCOVER.to_string()),
+                    Style::Landcover,
+                )),
+                PreviewKind::Soil => {
+                    let mut topsoil = BTreeMap::new();
+                    for (key, _) in grid.soil_layers() {
+                        topsoil
+                            .entry(key.property)
+                            .and_modify(|k: &mut _| *k = key.min(*k))
+                            .or_insert(key);
+                    }
+                    for key in topsoil.into_values() {
+                        let name = grid
+                            .layers
+                            .iter()
+                            .find(|l| l.meta.soil == Some(key))
+                            .map(|l| l.meta.name.clone())
+                            .unwrap_or_default();
+                        images.push(image(&name, Source::Terrain(name.clone()), viridis));
+                    }
+                }
+                PreviewKind::Twi => images.push(image("twi", Source::Physics("twi"), viridis)),
+                PreviewKind::Hli => images.push(image(
+                    "hli",
+                    Source::Physics("hli"),
+                    Style::Fixed {
+                        ramp: HEAT_RAMP,
+                        min: 0.0,
+                        max: 1.0,
+                    },
+                )),
+            }
+        }
+        images
+    }
+
+    /// Grid pixel an image pixel is sampled from, at the centre of the grid
+    /// pixels it covers.
+    fn sample_at(&self, p: usize, len: usize) -> usize {
+        (p * self.step + self.step / 2).min(len - 1)
+    }
+
+    /// RGBA pixels of an image, top row first, with everything outside the
+    /// ROI left transparent.
+    fn colorize(&self, image: &Image) -> Vec<u8> {
+        let range = match image.style {
+            Style::Ramp { .. } => stretch_range(&image.values, &self.visible),
+            Style::Fixed { min, max, .. } => (min, max),
+            _ => (0.0, 1.0),
+        };
+
+        let mut rgba = vec![0; self.width * self.height * 4];
+        for (row, out) in rgba.chunks_exact_mut(self.width * 4).enumerate() {
+            let py = self.height - 1 - row;
+            for (px, pixel) in out.chunks_exact_mut(4).enumerate() {
+                let p = py * self.width + px;
+                let v = if matches!(image.style, Style::Hillshade) {
+                    self.shade[p]
+                } else {
+                    image.values[p]
+                };
+                if !self.visible[p] || v.is_nan() {
+                    continue;
+                }
+
+                let t = ((v - range.0) / (range.1 - range.0).max(f32::EPSILON)).clamp(0.0, 1.0);
+                let rgb = match image.style {
+                    Style::Hillshade => [(v * 255.0) as u8; 3],
+                    Style::Ramp { ramp, shaded } => {
+                        let rgb = sample_ramp(ramp, t);
+                        if shaded {
+                            let tint = MIN_TINT + (1.0 - MIN_TINT) * self.shade[p];
+                            rgb.map(|c| (c as f32 * tint) as u8)
+                        } else {
+                            rgb
+                        }
+                    }
+                    Style::Fixed { ramp, .. } => sample_ramp(ramp, t),
+                    Style::Landcover => landcover_color(v as u8),
+                };
+                pixel.copy_from_slice(&[rgb[0], rgb[1], rgb[2], 255]);
+            }
+        }
+        rgba
+    }
+
+    fn draw_overlay(&self, rgba: &mut [u8]) {
+        let mut grids = vec![];
+        if self.overlay == GridOverlay::Chunks {
+            grids.push((CHUNK_BLOCKS, [0, 0, 0], 0.3));
+        }
+        if self.overlay != GridOverlay::None {
+            grids.push((REGION_BLOCKS, [255, 255, 255], 0.8));
+        }
+
+        let (w, h) = (self.width, self.height);
+        let mut blend = |x: usize, y: usize, color: [u8; 3], alpha: f32| {
+            let pixel = &mut rgba[(y * w + x) * 4..][..4];
+            for (c, &target) in pixel.iter_mut().zip(&color) {
+                *c = (*c as f32 + (target as f32 - *c as f32) * alpha) as u8;
+            }
+            pixel[3] = pixel[3].max((alpha * 255.0) as u8);
+        };
+
+        for &(blocks, color, alpha) in &grids {
+            if blocks / self.step < MIN_LINE_SPACING {
+                continue;
+            }
+            for gx in (blocks..self.grid_size.0).step_by(blocks) {
+                for y in 0..h {
+                    blend(gx / self.step, y, color, alpha);
+                }
+            }
+            for gy in (blocks..self.grid_size.1).step_by(blocks) {
+                let y = h - 1 - gy / self.step;
+                for x in 0..w {
+                    blend(x, y, color, alpha);
+                }
+            }
+        }
+
+        // Drawn only when the label fits in `room` pixels along the axis it
+        // is spaced out on.
+        let mut label = |text: &str, size: usize, room: usize, origin: (usize, usize)| {
+            if size + 4 <= room {
+                font::draw_text(text, origin, |x, y, color| {
+                    if x < w && y < h {
+                        blend(x, y, color, 1.0);
+                    }
+                });
+            }
+        };
+        // Image row of the northern edge of the cell starting at grid row `gy`.
+        let top_row = |gy: usize, blocks: usize| {
+            h - 1 - ((gy + blocks).min(self.grid_size.1) - 1) / self.step
+        };
+
+        if self.overlay == GridOverlay::Chunks {
+            // Chunk coordinates along the bottom and right edges, like axis
+            // ticks, clear of the region labels.
+            let spacing = CHUNK_BLOCKS / self.step;
+            for gx in (0..self.grid_size.0).step_by(CHUNK_BLOCKS) {
+                let cx = (gx / CHUNK_BLOCKS).to_string();
+                let origin = (gx / self.step + 2, h.saturating_sub(font::HEIGHT + 2));
+                label(&cx, font::text_width(&cx), spacing, origin);
+            }
+            for gy in (0..self.grid_size.1).step_by(CHUNK_BLOCKS) {
+                let cz = (gy / CHUNK_BLOCKS).to_string();
+                let x = w.saturating_sub(font::text_width(&cz) + 2);
+                let origin = (x, top_row(gy, CHUNK_BLOCKS) + 2);
+                label(&cz, font::HEIGHT, spacing, origin);
+            }
+        }
+        if self.overlay != GridOverlay::None {
+            let spacing = REGION_BLOCKS / self.step;
+            for gx in (0..self.grid_size.0).step_by(REGION_BLOCKS) {
+                for gy in (0..self.grid_size.1).step_by(REGION_BLOCKS) {
+                    let name = format!("r.{}.{}", gx / REGION_BLOCKS, gy / REGION_BLOCKS);
+                    let origin = (gx / self.step + 2, top_row(gy, REGION_BLOCKS) + 2);
+                    label(&name, font::text_width(&name), spacing, origin);
+                }
+            }
+        }
+    }
+}
+
+/// Lambertian shade of a surface under a sun at compass bearing `azimuth` and
+/// `altitude` above the horizon. `aspect` is the downslope direction as the
+/// physics layers store it, clockwise from east. All angles are in radians.
+fn hillshade(slope: f32, aspect: f32, azimuth: f32, altitude: f32) -> f32 {
+    let bearing = aspect + FRAC_PI_2;
+    (slope.cos() * altitude.sin() + slope.sin() * altitude.cos() * (azimuth - bearing).cos())
+        .max(0.0)
+}
+
+/// Values at the [`STRETCH`] percentiles among the visible pixels.
+fn stretch_range(values: &[f32], visible: &[bool]) -> (f32, f32) {
+    let mut sorted: Vec<f32> = values
+        .iter()
+        .zip(visible)
+        .filter(|&(v, &vis)| vis && !v.is_nan())
+        .map(|(&v, _)| v)
+        .collect();
+    if sorted.is_empty() {
+        return (0.0, 1.0);
+    }
+    sorted.sort_by(f32::total_cmp);
+    let at = |q: f32| sorted[((sorted.len() - 1) as f32 * q).round() as usize];
+    (at(STRETCH.0), at(STRETCH.1))
+}
+
+fn sample_ramp(ramp: &[[u8; 3]], t: f32) -> [u8; 3] {
+    let pos = t * (ramp.len() - 1) as f32;
+    let i = (pos.floor() as usize).min(ramp.len() - 2);
+    let f = pos - i as f32;
+    let (a, b) = (ramp[i], ramp[i + 1]);
+    [0, 1, 2].map(|c| (a[c] as f32 + (b[c] as f32 - a[c] as f32) * f).round() as u8)
+}
+
+/// ESA WorldCover class colours.
+fn landcover_color(code: u8) -> [u8; 3] {
+    match code {
+        10 => [0, 100, 0],      // Tree cover
+        20 => [255, 187, 34],   // Shrubland
+        30 => [255, 255, 76],   // Grassland
+        40 => [240, 150, 255],  // Cropland
+        50 => [250, 0, 0],      // Built-up
+        60 => [180, 180, 180],  // Bare / sparse vegetation
+        70 => [240, 240, 240],  // Snow and ice
+        80 => [0, 100, 200],    // Permanent water bodies
+        90 => [0, 150, 160],    // Herbaceous wetland
+        95 => [0, 207, 117],    // Mangroves
+        100 => [250, 230, 160], // Moss and lichen
+        _ => [0, 0, 0],
+    }
+}
+
+fn write_png(path: &Path, width: usize, height: usize, rgba: &[u8]) -> Result<()> {
+    let mem = DriverManager::get_driver_by_name("MEM")?;
+    let dataset = mem.create_with_band_type::<u8, _>("", width, height, 4)?;
+    for band in 0..4 {
+        let data = rgba.iter().skip(band).step_by(4).copied().collect();
+        let mut buffer = Buffer::new((width, height), data);
+        dataset
+            .rasterband(band + 1)?
+            .write((0, 0), (width, height), &mut buffer)?;
+    }
+
+    let png = DriverManager::get_driver_by_name("PNG")?;
+    dataset.create_copy(&png, path, &CslStringList::new())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hillshade_lights_slopes_facing_the_sun() {
+        let (azimuth, altitude) = (SUN_AZIMUTH.to_radians(), SUN_ALTITUDE.to_radians());
+        let slope = 30f32.to_radians();
+        // Clockwise from east: northwest is 225°, southeast 45°.
+        let facing = hillshade(slope, 225f32.to_radians(), azimuth, altitude);
+        let away = hillshade(slope, 45f32.to_radians(), azimuth, altitude);
+        let flat = hillshade(0.0, 0.0, azimuth, altitude);
+
+        assert!((facing - (altitude - slope).cos()).abs() < 1e-5);
+        assert!(away < flat && flat < facing);
+    }
+
+    #[test]
+    fn sample_ramp_interpolates_between_stops() {
+        let ramp = [[0, 0, 0], [100, 200, 50], [200, 0, 250]];
+        assert_eq!(sample_ramp(&ramp, 0.0), [0, 0, 0]);
+        assert_eq!(sample_ramp(&ramp, 0.25), [50, 100, 25]);
+        assert_eq!(sample_ramp(&ramp, 0.5), [100, 200, 50]);
+        assert_eq!(sample_ramp(&ramp, 0.75), [150, 100, 150]);
+        assert_eq!(sample_ramp(&ramp, 1.0), [200, 0, 250]);
+    }
+
+    #[test]
+    fn stretch_range_uses_visible_pixels_only() {
+        let values: Vec<f32> = (0..=100)
+            .map(|v| v as f32)
+            .chain([f32::NAN, -1e6, 1e6])
+            .collect();
+        let mut visible = vec![true; values.len()];
+        let hidden = visible.len() - 2;
+        visible[hidden..].fill(false);
+
+        let (low, high) = stretch_range(&values, &visible);
+        let at = |q: f32| (100.0 * q).round();
+        assert_eq!((low, high), (at(STRETCH.0), at(STRETCH.1)));
+    }
+
+    #[test]
+    fn stretch_range_without_visible_pixels_is_unit() {
+        assert_eq!(stretch_range(&[f32::NAN, 5.0], &[true, false]), (0.0, 1.0));
+    }
+}
@@ -1,12 +1,12 @@
-use super::geo_utils::extract_bounds_async;
-use super::types::AlosTile;
+use super::geo_utils::extract_footprint_async;
+use super::types::{AlosTile, FootprintMode};
 use crate::scanner::path_utils::{get_entries, normalize_path};
 use crate::scanner::task_utils::run_all;
 use anyhow::{Result, anyhow};
 use std::path::{Path, PathBuf};
 use tokio::fs;
 
-pub async fn scan(root: PathBuf) -> Result<Vec<AlosTile>> {
+pub async fn scan(root: PathBuf, footprint_mode: FootprintMode) -> Result<Vec<AlosTile>> {
     if !root.exists() {
         return Ok(vec![]);
     }
@@ -14,13 +14,13 @@ pub async fn scan(root: PathBuf) -> Result<Vec<AlosTile>> {
     let tasks: Vec<_> = get_entries(&root)
         .await?
         .into_iter()
-        .map(|path| tokio::spawn(async move { try_load_scene(path).await }))
+        .map(|path| tokio::spawn(async move { try_load_scene(path, footprint_mode).await }))
         .collect();
 
     run_all(tasks).await
 }
 
-async fn try_load_scene(path: PathBuf) -> Result<Option<AlosTile>> {
+async fn try_load_scene(path: PathBuf, footprint_mode: FootprintMode) -> Result<Option<AlosTile>> {
     let id = path
         .file_name()
         .ok_or(anyhow!("Cannot get file name of {}", normalize_path(&path)))?
@@ -32,11 +32,11 @@ async fn try_load_scene(path: PathBuf) -> Result<Option<AlosTile>> {
         return Ok(None);
     }
 
-    let bounds = extract_bounds_async(&files.dem).await?;
+    let footprint = extract_footprint_async(&files.dem, footprint_mode).await?;
 
     Ok(Some(AlosTile {
         id,
-        bounds,
+        footprint,
         path_dem: files.dem,
         path_hh: files.hh,
         path_hv: files.hv,
@@ -1,64 +1,104 @@
+use super::epoch::select_epochs;
+use super::provider::{ALOS_PALSAR, ESA_WORLD_COVER, SOIL_GRIDS};
 use super::{alos, esa, soil, types::*};
+use crate::core::roi::Roi;
+use crate::core::soil::SoilLayerKey;
 use anyhow::Result;
-use geo::{Area, BooleanOps, MultiPolygon, Polygon, Rect};
+use geo::{Area, BooleanOps, MultiPolygon, Polygon, unary_union};
+use std::collections::BTreeSet;
 use std::path::PathBuf;
 
+const FULL_COV_THRESHOLD: f64 = 0.999;
+
 impl DataCatalog {
-    pub async fn scan(root: PathBuf) -> Result<Self> {
+    pub async fn scan(root: PathBuf, options: &ScanOptions) -> Result<Self> {
         let (alos_res, esa_res, soil_res) = tokio::try_join!(
-            alos::scan(root.join("alos_palsar")),
-            esa::scan(root.join("esa_world_cover")),
-            soil::scan(root.join("soil_grids")),
+            alos::scan(root.join(ALOS_PALSAR.dir), options.footprint_mode),
+            esa::scan(root.join(ESA_WORLD_COVER.dir), options.footprint_mode),
+            soil::scan(
+                root.join(SOIL_GRIDS.dir),
+                options.footprint_mode,
+                &options.soil_layers
+            ),
         )?;
 
+        let esa_res = select_epochs(esa_res, options.epoch_policy, |t| t.epoch);
+        let soil_res = select_epochs(soil_res, options.epoch_policy, |t| t.epoch);
+
+        let present: BTreeSet<SoilLayerKey> = soil_res
+            .iter()
+            .flat_map(|t| t.layers.keys().copied())
+            .collect();
+
         Ok(Self {
             alos: alos_res,
             esa: esa_res,
             soil: soil_res,
+            soil_layers: present.into_iter().collect(),
         })
     }
 
-    pub fn check_coverage(&self, rect: Rect<f64>) -> CoverageResult {
-        let target = Polygon::from(rect);
+    pub fn check_coverage(&self, roi: &Roi) -> CoverageResult {
+        let target = roi.target();
 
-        let alos_coverage = self.calc_ratio(&self.alos_polys(), &target);
-        let esa_coverage = self.calc_ratio(&self.esa_polys(), &target);
-        let soil_coverage = self.calc_ratio(&self.soil_polys(), &target);
+        let mut sources = vec![
+            self.calc_coverage(ALOS_PALSAR.name, &self.alos_polys(), &target),
+            self.calc_coverage(ESA_WORLD_COVER.name, &self.esa_polys(), &target),
+        ];
 
-        const FULL_COV_THRESHOLD: f64 = 0.999;
-        let is_full = [alos_coverage, esa_coverage, soil_coverage]
-            .iter()
-            .all(|r| *r > FULL_COV_THRESHOLD);
+        if self.soil_layers.is_empty() {
+            sources.push(self.calc_coverage(SOIL_GRIDS.name, &[], &target));
+        }
+        for key in &self.soil_layers {
+            let name = format!("{} {key}", SOIL_GRIDS.name);
+            sources.push(self.calc_coverage(&name, &self.soil_polys(key), &target));
+        }
+
+        let is_full = sources.iter().all(|s| s.ratio > FULL_COV_THRESHOLD);
 
         if is_full {
             CoverageResult::Full
         } else {
-            CoverageResult::Partial {
-                alos_coverage,
-                esa_coverage,
-                soil_coverage,
-            }
+            CoverageResult::Partial(sources)
         }
     }
 
-    fn calc_ratio(&self, source: &[Polygon<f64>], target: &Polygon<f64>) -> f64 {
-        let mut mp = MultiPolygon::new(vec![]);
-        for p in source {
-            mp = mp.union(&MultiPolygon::from(p.clone()));
+    pub(super) fn calc_coverage(
+        &self,
+        name: &str,
+        source: &[Polygon<f64>],
+        target: &MultiPolygon<f64>,
+    ) -> SourceCoverage {
+        let mp = unary_union(source);
+        let missing = target.difference(&mp);
+        let ratio = 1.0 - missing.unsigned_area() / target.unsigned_area();
+
+        SourceCoverage {
+            name: name.to_string(),
+            ratio,
+            missing,
         }
-        let intersection = mp.intersection(&MultiPolygon::from(target.clone()));
-        intersection.unsigned_area() / target.unsigned_area()
     }
 
-    fn alos_polys(&self) -> Vec<Polygon<f64>> {
-        self.alos.iter().map(|s| s.bounds.into()).collect()
+    pub(super) fn alos_polys(&self) -> Vec<Polygon<f64>> {
+        self.alos
+            .iter()
+            .flat_map(|s| s.footprint.polygon.iter().cloned())
+            .collect()
     }
 
-    fn esa_polys(&self) -> Vec<Polygon<f64>> {
-        self.esa.iter().map(|s| s.bounds.into()).collect()
+    pub(super) fn esa_polys(&self) -> Vec<Polygon<f64>> {
+        self.esa
+            .iter()
+            .flat_map(|s| s.footprint.polygon.iter().cloned())
+            .collect()
     }
 
-    fn soil_polys(&self) -> Vec<Polygon<f64>> {
-        self.soil.iter().map(|s| s.bounds.into()).collect()
+    pub(super) fn soil_polys(&self, key: &SoilLayerKey) -> Vec<Polygon<f64>> {
+        self.soil
+            .iter()
+            .filter(|s| s.layers.contains_key(key))
+            .flat_map(|s| s.footprint.polygon.iter().cloned())
+            .collect()
     }
 }
@@ -0,0 +1,35 @@
+use super::types::CoverageResult;
+use anyhow::Result;
+use geojson::{Feature, FeatureCollection, Geometry};
+use std::fs;
+use std::path::Path;
+
+impl CoverageResult {
+    pub fn write_missing_geojson(&self, path: &Path) -> Result<()> {
+        let features = match self {
+            Self::Full => vec![],
+            Self::Partial(sources) => sources
+                .iter()
+                .filter(|s| !s.missing.0.is_empty())
+                .map(|s| {
+                    let mut feature = Feature::from(Geometry::from(&s.missing));
+                    feature.set_property("source", s.name.clone());
+                    feature.set_property("coverage", s.ratio);
+                    feature
+                })
+                .collect(),
+        };
+
+        let collection = FeatureCollection {
+            bbox: None,
+            features,
+            foreign_members: None,
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, collection.to_string())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use super::types::{Epoch, EpochPolicy};
+use crate::utils::dataset::DatasetEx;
+use gdal::{Dataset, Metadata};
+use std::path::{Path, PathBuf};
+use tokio::task::spawn_blocking;
+
+const MIN_YEAR: u16 = 1950;
+const MAX_YEAR: u16 = 2100;
+
+/// Parses the epoch of a WorldCover tile id such as
+/// `ESA_WorldCover_10m_2021_v200_N30E093_Map`, which fixes the year and
+/// version to the fields after the product name.
+pub fn parse_esa_epoch(id: &str) -> Option<Epoch> {
+    let mut fields = id.strip_prefix("ESA_WorldCover_10m_")?.split('_');
+    let year = parse_year(fields.next()?)?;
+    let version = fields.next().and_then(parse_version).unwrap_or(0);
+    Some(Epoch { year, version })
+}
+
+/// Parses the epoch of a SoilGrids file from the directories it is kept in
+/// below its layer directory, such as `v2.0/` or `2020/`. The file name is
+/// not read, since SoilGrids tile names are made of grid indices.
+pub fn parse_soil_epoch(relative_path: &str) -> Option<Epoch> {
+    let (dirs, _) = relative_path.rsplit_once('/')?;
+    let mut year = None;
+    let mut version = None;
+
+    for dir in dirs.split('/') {
+        if let Some(y) = parse_year(dir) {
+            year.get_or_insert(y);
+        } else if let Some(v) = parse_version(dir) {
+            version.get_or_insert(v);
+        }
+    }
+
+    if year.is_none() && version.is_none() {
+        return None;
+    }
+    Some(Epoch {
+        year: year.unwrap_or(0),
+        version: version.unwrap_or(0),
+    })
+}
+
+fn parse_year(token: &str) -> Option<u16> {
+    let year = token.parse::<u16>().ok()?;
+    (token.len() == 4 && (MIN_YEAR..=MAX_YEAR).contains(&year)).then_some(year)
+}
+
+/// `v200` or `v2.0` as 200 and 20.
+fn parse_version(token: &str) -> Option<u32> {
+    let digits = token.strip_prefix(['v', 'V'])?.replace('.', "");
+    digits.parse().ok()
+}
+
+/// Falls back to the raster's acquisition timestamp when the name carried no
+/// epoch.
+pub async fn resolve_epoch_async(parsed: Option<Epoch>, path: &Path) -> Option<Epoch> {
+    if parsed.is_some() {
+        return parsed;
+    }
+    let path_owned: PathBuf = path.to_path_buf();
+    spawn_blocking(move || epoch_from_metadata(&path_owned))
+        .await
+        .ok()
+        .flatten()
+}
+
+fn epoch_from_metadata(path: &Path) -> Option<Epoch> {
+    let dataset = Dataset::open_dataset(path).ok()?;
+    let datetime = dataset.metadata_item("TIFFTAG_DATETIME", "")?;
+    let year = datetime.get(..4)?.parse::<u16>().ok()?;
+    (MIN_YEAR..=MAX_YEAR)
+        .contains(&year)
+        .then_some(Epoch { year, version: 0 })
+}
+
+/// Filters tiles according to the policy. Tiles without a known epoch are kept
+/// unless an epoch is pinned, and sort as the oldest.
+pub fn select_epochs<T, F>(tiles: Vec<T>, policy: EpochPolicy, epoch_of: F) -> Vec<T>
+where
+    F: Fn(&T) -> Option<Epoch>,
+{
+    match policy {
+        EpochPolicy::Pinned(year) => tiles
+            .into_iter()
+            .filter(|t| epoch_of(t).is_some_and(|e| e.year == year))
+            .collect(),
+        EpochPolicy::Newest => {
+            let newest = tiles.iter().map(&epoch_of).max().flatten();
+            tiles
+                .into_iter()
+                .filter(|t| epoch_of(t) == newest)
+                .collect()
+        }
+        EpochPolicy::NewestWithFallback => tiles,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn esa_epoch_comes_from_the_id_fields() {
+        let epoch = parse_esa_epoch("ESA_WorldCover_10m_2021_v200_N30E093_Map");
+        assert_eq!(
+            epoch,
+            Some(Epoch {
+                year: 2021,
+                version: 200
+            })
+        );
+        assert_eq!(parse_esa_epoch("N30E093_2021"), None);
+    }
+
+    #[test]
+    fn soil_epoch_ignores_year_like_tile_indices() {
+        assert_eq!(
+            parse_soil_epoch("tileSG-2000-2010/tile_2000_2010.tif"),
+            None
+        );
+        assert_eq!(
+            parse_soil_epoch("v2.0/tileSG-2000-2010/tile_2000_2010.tif"),
+            Some(Epoch {
+                year: 0,
+                version: 20
+            })
+        );
+        assert_eq!(parse_soil_epoch("tile_2020.tif"), None);
+    }
+}
@@ -1,12 +1,13 @@
-use super::geo_utils::extract_bounds_async;
-use super::types::EsaTile;
+use super::epoch::{parse_esa_epoch, resolve_epoch_async};
+use super::geo_utils::extract_footprint_async;
+use super::types::{EsaTile, FootprintMode};
 use crate::scanner::path_utils::get_entries;
 use crate::scanner::task_utils::run_all;
 use anyhow::{Result, anyhow};
 use std::path::{Path, PathBuf};
 use tokio::fs;
 
-pub async fn scan(root: PathBuf) -> Result<Vec<EsaTile>> {
+pub async fn scan(root: PathBuf, footprint_mode: FootprintMode) -> Result<Vec<EsaTile>> {
     if !root.exists() {
         return Ok(vec![]);
     }
@@ -14,27 +15,55 @@ pub async fn scan(root: PathBuf) -> Result<Vec<EsaTile>> {
     let tasks: Vec<_> = get_entries(&root)
         .await?
         .into_iter()
-        .map(|path| tokio::spawn(async move { try_load_tile(path).await }))
+        .map(|path| tokio::spawn(async move { try_load_tile(path, footprint_mode).await }))
         .collect();
 
     run_all(tasks).await
 }
 
-async fn try_load_tile(path: PathBuf) -> Result<Option<EsaTile>> {
+async fn try_load_tile(path: PathBuf, footprint_mode: FootprintMode) -> Result<Option<EsaTile>> {
     let id = get_id(&path)?;
     let map_file = path.join(get_map_file_name(&id));
     ensure_file_exists(&map_file).await?;
     let quality_file = path.join(get_input_quality_file_name(&id));
     ensure_file_exists(&quality_file).await?;
-    let bounds = extract_bounds_async(&map_file).await?;
+    let footprint = extract_footprint_async(&map_file, footprint_mode).await?;
+    let epoch = resolve_epoch_async(parse_esa_epoch(&id), &map_file).await;
 
     Ok(Some(EsaTile {
         id,
-        bounds,
+        epoch,
+        footprint,
         path_map: map_file,
+        path_quality: quality_file,
     }))
 }
 
+/// Id of the 3x3 degree WorldCover tile whose south-west corner is at
+/// (`lat`, `lon`), e.g. `ESA_WorldCover_10m_2021_v200_N30E093_Map`.
+pub fn grid_tile_id(year: u16, version: u32, lat: i32, lon: i32) -> String {
+    format!(
+        "ESA_WorldCover_10m_{year}_v{version}_{}_Map",
+        grid_cell_tag(lat, lon)
+    )
+}
+
+/// Grid cell part of a tile id, e.g. `N30E093`.
+pub fn grid_cell_tag(lat: i32, lon: i32) -> String {
+    let ns = if lat < 0 { 'S' } else { 'N' };
+    let ew = if lon < 0 { 'W' } else { 'E' };
+    format!("{ns}{:02}{ew}{:03}", lat.abs(), lon.abs())
+}
+
+/// Files a tile with the given id is expected to consist of below `root`.
+pub fn tile_paths(root: &Path, id: &str) -> Vec<PathBuf> {
+    let dir = root.join(id);
+    vec![
+        dir.join(get_map_file_name(id)),
+        dir.join(get_input_quality_file_name(id)),
+    ]
+}
+
 fn get_id(path: &Path) -> Result<String> {
     let file_name = path
         .file_name()
@@ -1,18 +1,26 @@
+use super::types::{Footprint, FootprintMode};
+use crate::core::lonlat::{normalize_lon, split_antimeridian, unwrap_ring};
 use crate::core::spatial::GeoTransform;
 use crate::utils::dataset::DatasetEx;
 use anyhow::{Context, Result, anyhow};
 use gdal::Dataset;
 use gdal::spatial_ref::{AxisMappingStrategy, CoordTransform, SpatialRef};
-use geo::{Coord, Rect};
+use geo::{
+    Coord, CoordsIter, Densify, Euclidean, LineString, MultiPolygon, Polygon, Rect, unary_union,
+};
+use std::collections::HashMap;
 use std::path::Path;
 use tokio::task::spawn_blocking;
 
-pub async fn extract_bounds_async(path: &Path) -> Result<Rect<f64>> {
+const EDGE_SAMPLES: usize = 64;
+const MASK_TRACE_SIZE: usize = 512;
+
+pub async fn extract_footprint_async(path: &Path, mode: FootprintMode) -> Result<Footprint> {
     let path_owned = path.to_path_buf();
-    spawn_blocking(move || extract_bounds(&path_owned)).await?
+    spawn_blocking(move || extract_footprint(&path_owned, mode)).await?
 }
 
-fn extract_bounds(path: &Path) -> Result<Rect<f64>> {
+fn extract_footprint(path: &Path, mode: FootprintMode) -> Result<Footprint> {
     let dataset = Dataset::open_dataset(path)?;
     let transform_pipeline = create_projection_pipeline(&dataset)?;
 
@@ -21,15 +29,107 @@ fn extract_bounds(path: &Path) -> Result<Rect<f64>> {
 
     let (w, h) = dataset.raster_size();
 
-    let corners_native = vec![
-        geo_transform.pixel_to_geo(0.0, 0.0),
-        geo_transform.pixel_to_geo(w as f64, 0.0),
-        geo_transform.pixel_to_geo(w as f64, h as f64),
-        geo_transform.pixel_to_geo(0.0, h as f64),
-    ];
+    let polygons_pixel = match mode {
+        FootprintMode::Extent => vec![Rect::new((0.0, 0.0), (w as f64, h as f64)).to_polygon()],
+        FootprintMode::ValidData => trace_valid_data(&dataset, w, h)?,
+    };
+
+    let max_segment = w.max(h) as f64 / EDGE_SAMPLES as f64;
+    let polygons_wgs84 = polygons_pixel
+        .iter()
+        .map(|p| {
+            let densified = Euclidean.densify(p, max_segment);
+            reproject_polygon(&densified, &geo_transform, &transform_pipeline)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let polygon = split_antimeridian(&MultiPolygon::new(polygons_wgs84));
+    let bounds = compute_bounding_box(&polygon.exterior_coords_iter().collect::<Vec<_>>())?;
+
+    Ok(Footprint { bounds, polygon })
+}
+
+/// Traces the valid-data area from the band's mask at reduced resolution,
+/// returning polygons in pixel space of the full-resolution raster.
+fn trace_valid_data(dataset: &Dataset, w: usize, h: usize) -> Result<Vec<Polygon<f64>>> {
+    let band = dataset.rasterband(1)?;
+    let mask = band.open_mask_band()?;
+
+    let scale = (w.max(h) as f64 / MASK_TRACE_SIZE as f64).max(1.0);
+    let mw = ((w as f64 / scale).ceil() as usize).max(1);
+    let mh = ((h as f64 / scale).ceil() as usize).max(1);
+    let sx = w as f64 / mw as f64;
+    let sy = h as f64 / mh as f64;
+
+    let buffer = mask
+        .read_as::<u8>((0, 0), (w, h), (mw, mh), None)
+        .context("Failed to read nodata mask")?;
+    let cells = buffer.data();
+
+    let mut closed = Vec::new();
+    let mut open: HashMap<(usize, usize), (usize, usize)> = HashMap::new();
+
+    for row in 0..mh {
+        let mut current = HashMap::new();
+        let mut col = 0;
+        while col < mw {
+            if cells[row * mw + col] == 0 {
+                col += 1;
+                continue;
+            }
+            let start = col;
+            while col < mw && cells[row * mw + col] != 0 {
+                col += 1;
+            }
+            let top = open.remove(&(start, col)).map_or(row, |(top, _)| top);
+            current.insert((start, col), (top, row + 1));
+        }
+        closed.extend(open.drain());
+        open = current;
+    }
+    closed.extend(open.drain());
+
+    if closed.is_empty() {
+        return Err(anyhow!("Raster contains no valid data"));
+    }
+
+    let runs: Vec<Polygon<f64>> = closed
+        .into_iter()
+        .map(|((left, right), (top, bottom))| {
+            Rect::new(
+                (left as f64 * sx, top as f64 * sy),
+                (right as f64 * sx, bottom as f64 * sy),
+            )
+            .to_polygon()
+        })
+        .collect();
+
+    Ok(unary_union(&runs).0)
+}
 
-    let corners_wgs84 = reproject_points(corners_native, &transform_pipeline)?;
-    compute_bounding_box(&corners_wgs84)
+fn reproject_polygon(
+    polygon: &Polygon<f64>,
+    geo_transform: &GeoTransform,
+    transform: &CoordTransform,
+) -> Result<Polygon<f64>> {
+    let reproject_ring = |ring: &LineString<f64>| -> Result<LineString<f64>> {
+        let native = ring
+            .coords()
+            .map(|c| geo_transform.pixel_to_geo(c.x, c.y))
+            .collect();
+        Ok(LineString::new(unwrap_ring(reproject_points(
+            native, transform,
+        )?)))
+    };
+
+    let exterior = reproject_ring(polygon.exterior())?;
+    let interiors = polygon
+        .interiors()
+        .iter()
+        .map(reproject_ring)
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Polygon::new(exterior, interiors))
 }
 
 fn reproject_points(
@@ -70,20 +170,39 @@ fn determine_source_srs(dataset: &Dataset) -> Result<SpatialRef> {
     }
 }
 
+/// Computes the smallest lon/lat box holding all coordinates. Longitudes are
+/// treated as circular, so a set straddling ±180° yields a box whose
+/// `max().x` extends past 180 rather than one spanning the globe.
 fn compute_bounding_box(coords: &[Coord<f64>]) -> Result<Rect<f64>> {
-    let mut min_x = f64::MAX;
-    let mut max_x = f64::MIN;
     let mut min_y = f64::MAX;
     let mut max_y = f64::MIN;
+    let mut lons = Vec::with_capacity(coords.len());
 
     for p in coords {
         if !p.x.is_finite() || !p.y.is_finite() {
             return Err(anyhow!("Non-finite coordinates after reprojection"));
         }
-        min_x = min_x.min(p.x);
-        max_x = max_x.max(p.x);
         min_y = min_y.min(p.y);
         max_y = max_y.max(p.y);
+        lons.push(normalize_lon(p.x));
+    }
+
+    if lons.is_empty() {
+        return Err(anyhow!("Cannot compute bounding box of empty geometry"));
+    }
+
+    lons.sort_by(f64::total_cmp);
+
+    let (mut min_x, mut max_x) = (lons[0], lons[lons.len() - 1]);
+    let mut widest_gap = 360.0 - (max_x - min_x);
+
+    for pair in lons.windows(2) {
+        let gap = pair[1] - pair[0];
+        if gap > widest_gap {
+            widest_gap = gap;
+            min_x = pair[1];
+            max_x = pair[0] + 360.0;
+        }
     }
 
     Ok(Rect::new(
@@ -1,18 +1,43 @@
 mod alos;
 mod catalog;
+mod coverage;
+mod epoch;
 mod esa;
 mod geo_utils;
 mod path_utils;
+mod plan;
+pub mod provider;
 mod soil;
 mod task_utils;
 pub mod types;
 
-use crate::scanner::types::DataCatalog;
+use crate::core::roi::Roi;
+use crate::scanner::types::{DataCatalog, ScanOptions};
 use anyhow::Result;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-const DATASETS_PATH: &str = "datasets";
+pub const DATASETS_PATH: &str = "datasets";
 
-pub async fn scan_datasets() -> Result<DataCatalog> {
-    DataCatalog::scan(PathBuf::from(DATASETS_PATH)).await
+pub async fn scan_datasets(options: &ScanOptions) -> Result<DataCatalog> {
+    DataCatalog::scan(PathBuf::from(DATASETS_PATH), options).await
+}
+
+/// Prints the tiles the ROI needs that are not available locally, and writes
+/// the same listing to `output` if given.
+pub async fn plan_datasets(roi: &Roi, options: &ScanOptions, output: Option<&Path>) -> Result<()> {
+    let root = PathBuf::from(DATASETS_PATH);
+    let plan = DataCatalog::scan(root.clone(), options)
+        .await?
+        .plan(roi, &root, options)?;
+
+    print!("{plan}");
+    if let Some(path) = output {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, plan.to_string())?;
+        println!("Plan written to {}", path.display());
+    }
+    Ok(())
 }
@@ -0,0 +1,211 @@
+use super::provider::{ALOS_PALSAR, ESA_WORLD_COVER, SOIL_GRIDS};
+use super::types::{DataCatalog, EpochPolicy, Footprint, ScanOptions};
+use super::{esa, soil};
+use crate::core::roi::Roi;
+use anyhow::{Result, anyhow};
+use geo::{Area, BooleanOps, BoundingRect, Intersects, MultiPolygon, Rect};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+const ESA_TILE_DEG: i32 = 3;
+/// SoilGrids has no tile names of its own in EPSG:4326, so missing data is
+/// listed as 1x1 degree cells named like WorldCover cells.
+const SOIL_TILE_DEG: i32 = 1;
+/// WorldCover releases as (year, product version).
+const ESA_RELEASES: [(u16, u32); 2] = [(2020, 100), (2021, 200)];
+
+pub struct SourcePlan {
+    pub name: String,
+    pub dir: PathBuf,
+    /// Local tiles intersecting the ROI
+    pub local: Vec<String>,
+    /// Grid tiles the ROI needs that are not on disk, with their expected files
+    pub missing_tiles: Vec<(String, Vec<PathBuf>)>,
+    /// Uncovered areas of sources whose tile names cannot be derived offline
+    pub missing_areas: Vec<Rect<f64>>,
+}
+
+pub struct TilePlan {
+    pub sources: Vec<SourcePlan>,
+}
+
+impl DataCatalog {
+    /// Works out which tiles the ROI needs from each source and which of them
+    /// are missing locally, without any network access.
+    pub fn plan(&self, roi: &Roi, root: &Path, options: &ScanOptions) -> Result<TilePlan> {
+        let target = roi.target();
+
+        let mut sources = vec![
+            self.plan_alos(&target, root),
+            self.plan_esa(&target, root, options.epoch_policy)?,
+        ];
+        sources.extend(options.soil_layers.iter().map(|key| {
+            let polys = self.soil_polys(key);
+            let coverage = self.calc_coverage("", &polys, &target);
+            let dir = root
+                .join(SOIL_GRIDS.dir)
+                .join(key.property.dir_name())
+                .join(key.depth.dir_name());
+
+            // Cells are listed wherever local tiles leave a gap, since local
+            // tiles need not follow the cell grid.
+            let missing_tiles = grid_cells(&coverage.missing, SOIL_TILE_DEG)
+                .into_iter()
+                .map(|(lat, lon)| {
+                    let id = soil::grid_tile_id(lat, lon);
+                    let path = dir.join(&id);
+                    (id, vec![path])
+                })
+                .collect();
+
+            SourcePlan {
+                name: format!("{} {key}", SOIL_GRIDS.name),
+                local: local_ids(
+                    self.soil
+                        .iter()
+                        .filter(|t| t.layers.contains_key(key))
+                        .map(|t| (&t.id, &t.footprint)),
+                    &target,
+                ),
+                dir,
+                missing_tiles,
+                missing_areas: vec![],
+            }
+        }));
+
+        Ok(TilePlan { sources })
+    }
+
+    fn plan_alos(&self, target: &MultiPolygon<f64>, root: &Path) -> SourcePlan {
+        let coverage = self.calc_coverage("", &self.alos_polys(), target);
+        SourcePlan {
+            name: ALOS_PALSAR.name.to_string(),
+            dir: root.join(ALOS_PALSAR.dir),
+            local: local_ids(self.alos.iter().map(|t| (&t.id, &t.footprint)), target),
+            missing_tiles: vec![],
+            missing_areas: area_bounds(&coverage.missing),
+        }
+    }
+
+    fn plan_esa(
+        &self,
+        target: &MultiPolygon<f64>,
+        root: &Path,
+        policy: EpochPolicy,
+    ) -> Result<SourcePlan> {
+        let dir = root.join(ESA_WORLD_COVER.dir);
+        let (year, version) = match policy {
+            EpochPolicy::Pinned(year) => ESA_RELEASES
+                .into_iter()
+                .find(|(y, _)| *y == year)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "No {} release for {year}, expected one of: {}",
+                        ESA_WORLD_COVER.name,
+                        ESA_RELEASES.map(|(y, _)| y.to_string()).join(", ")
+                    )
+                })?,
+            _ => ESA_RELEASES[ESA_RELEASES.len() - 1],
+        };
+
+        let mut missing_tiles = Vec::new();
+        for (lat, lon) in grid_cells(target, ESA_TILE_DEG) {
+            let cell_tag = format!("_{}_", esa::grid_cell_tag(lat, lon));
+            if self.esa.iter().any(|t| t.id.contains(&cell_tag)) {
+                continue;
+            }
+            let id = esa::grid_tile_id(year, version, lat, lon);
+            let paths = esa::tile_paths(&dir, &id);
+            missing_tiles.push((id, paths));
+        }
+
+        Ok(SourcePlan {
+            name: ESA_WORLD_COVER.name.to_string(),
+            dir,
+            local: local_ids(self.esa.iter().map(|t| (&t.id, &t.footprint)), target),
+            missing_tiles,
+            missing_areas: vec![],
+        })
+    }
+}
+
+/// South-west corners of the `tile_deg` sized degree cells intersecting `area`.
+fn grid_cells(area: &MultiPolygon<f64>, tile_deg: i32) -> Vec<(i32, i32)> {
+    let mut cells = Vec::new();
+    for part in area {
+        let Some(bounds) = part.bounding_rect() else {
+            continue;
+        };
+        let snap = |v: f64| (v / tile_deg as f64).floor() as i32 * tile_deg;
+
+        for lat in (snap(bounds.min().y)..=snap(bounds.max().y)).step_by(tile_deg as usize) {
+            for lon in (snap(bounds.min().x)..=snap(bounds.max().x)).step_by(tile_deg as usize) {
+                let cell = Rect::new(
+                    (lon as f64, lat as f64),
+                    ((lon + tile_deg) as f64, (lat + tile_deg) as f64),
+                );
+                // Cells only touching the ROI along an edge are not needed.
+                let overlap = part.intersection(&cell.to_polygon()).unsigned_area();
+                if overlap > 0.0 && !cells.contains(&(lat, lon)) {
+                    cells.push((lat, lon));
+                }
+            }
+        }
+    }
+    cells
+}
+
+fn local_ids<'a>(
+    tiles: impl Iterator<Item = (&'a String, &'a Footprint)>,
+    target: &MultiPolygon<f64>,
+) -> Vec<String> {
+    tiles
+        .filter(|(_, footprint)| footprint.polygon.intersects(target))
+        .map(|(id, _)| id.clone())
+        .collect()
+}
+
+fn area_bounds(missing: &MultiPolygon<f64>) -> Vec<Rect<f64>> {
+    missing.iter().filter_map(|p| p.bounding_rect()).collect()
+}
+
+impl fmt::Display for TilePlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for source in &self.sources {
+            writeln!(f, "{} ({})", source.name, source.dir.display())?;
+            writeln!(f, "  Local tiles: {}", source.local.len())?;
+            for id in &source.local {
+                writeln!(f, "    {id}")?;
+            }
+
+            if source.missing_tiles.is_empty() && source.missing_areas.is_empty() {
+                writeln!(f, "  Nothing missing")?;
+                continue;
+            }
+
+            for (id, paths) in &source.missing_tiles {
+                writeln!(f, "  Missing tile {id}")?;
+                for path in paths {
+                    writeln!(f, "    {}", path.display())?;
+                }
+            }
+            if !source.missing_areas.is_empty() {
+                writeln!(
+                    f,
+                    "  Missing areas (no offline tile grid, search by bounds as west,south,east,north):"
+                )?;
+                for area in &source.missing_areas {
+                    writeln!(
+                        f,
+                        "    {:.5},{:.5},{:.5},{:.5}",
+                        area.min().x,
+                        area.min().y,
+                        area.max().x,
+                        area.max().y
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
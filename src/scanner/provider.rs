@@ -0,0 +1,82 @@
+/// How a raster's values behave under resampling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RasterKind {
+    /// Physical quantities; interpolated bilinearly
+    Continuous,
+    /// Class codes and bit masks; nearest neighbour only
+    Categorical,
+}
+
+pub struct LayerDef {
+    pub suffix: &'static str,
+    pub kind: RasterKind,
+}
+
+/// Directory layout of one dataset provider below the datasets root.
+pub struct Provider {
+    pub name: &'static str,
+    pub dir: &'static str,
+    pub layers: &'static [LayerDef],
+}
+
+impl Provider {
+    pub fn kind_of(&self, file_name: &str) -> Option<RasterKind> {
+        self.layers
+            .iter()
+            .find(|l| file_name.ends_with(l.suffix))
+            .map(|l| l.kind)
+    }
+}
+
+pub const ALOS_PALSAR: Provider = Provider {
+    name: "Alos Palsar",
+    dir: "alos_palsar",
+    layers: &[
+        LayerDef {
+            suffix: ".dem.tif",
+            kind: RasterKind::Continuous,
+        },
+        LayerDef {
+            suffix: "_HH.tif",
+            kind: RasterKind::Continuous,
+        },
+        LayerDef {
+            suffix: "_HV.tif",
+            kind: RasterKind::Continuous,
+        },
+        LayerDef {
+            suffix: ".inc_map.tif",
+            kind: RasterKind::Continuous,
+        },
+        LayerDef {
+            suffix: ".ls_map.tif",
+            kind: RasterKind::Categorical,
+        },
+    ],
+};
+
+pub const ESA_WORLD_COVER: Provider = Provider {
+    name: "Esa WorldCover",
+    dir: "esa_world_cover",
+    layers: &[
+        LayerDef {
+            suffix: "_Map.tif",
+            kind: RasterKind::Categorical,
+        },
+        LayerDef {
+            suffix: "_InputQuality.tif",
+            kind: RasterKind::Continuous,
+        },
+    ],
+};
+
+pub const SOIL_GRIDS: Provider = Provider {
+    name: "Soil Grids",
+    dir: "soil_grids",
+    layers: &[LayerDef {
+        suffix: ".tif",
+        kind: RasterKind::Continuous,
+    }],
+};
+
+pub const PROVIDERS: [&Provider; 3] = [&ALOS_PALSAR, &ESA_WORLD_COVER, &SOIL_GRIDS];
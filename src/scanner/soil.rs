@@ -1,53 +1,44 @@
-use super::geo_utils::extract_bounds_async;
-use crate::scanner::path_utils::normalize_path;
-use crate::scanner::types::SoilTile;
-use anyhow::{Result, anyhow};
-use std::collections::{HashMap, HashSet};
+use super::epoch::{parse_soil_epoch, resolve_epoch_async};
+use super::esa::grid_cell_tag;
+use super::geo_utils::extract_footprint_async;
+use crate::core::soil::SoilLayerKey;
+use crate::scanner::types::{FootprintMode, SoilTile};
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
 
-pub async fn scan(root: PathBuf) -> Result<Vec<SoilTile>> {
-    let p_sand_top = root.join("sand/0-5");
-    let p_sand_sub = root.join("sand/30-60");
-    let p_clay_top = root.join("clay/0-5");
-    let p_clay_sub = root.join("clay/30-60");
-    let p_soc_top = root.join("soc/0-5");
-    let p_ph_top = root.join("phh2o/0-5");
-    let p_ph_sub = root.join("phh2o/30-60");
-
-    let map_sand_top = scan_single_layer(&p_sand_top, "Sand Top")?;
-    let map_sand_sub = scan_single_layer(&p_sand_sub, "Sand Sub")?;
-    let map_clay_top = scan_single_layer(&p_clay_top, "Clay Top")?;
-    let map_clay_sub = scan_single_layer(&p_clay_sub, "Clay Sub")?;
-    let map_soc_top = scan_single_layer(&p_soc_top, "SOC Top")?;
-    let map_ph_top = scan_single_layer(&p_ph_top, "pH Top")?;
-    let map_ph_sub = scan_single_layer(&p_ph_sub, "pH Sub")?;
-
-    let bundles = align_layers(
-        map_sand_top,
-        map_sand_sub,
-        map_clay_top,
-        map_clay_sub,
-        map_soc_top,
-        map_ph_top,
-        map_ph_sub,
-    )
-    .await;
+pub async fn scan(
+    root: PathBuf,
+    footprint_mode: FootprintMode,
+    layers: &[SoilLayerKey],
+) -> Result<Vec<SoilTile>> {
+    let mut layer_maps = Vec::new();
+
+    for key in layers {
+        let dir = root
+            .join(key.property.dir_name())
+            .join(key.depth.dir_name());
+
+        if dir.exists() {
+            layer_maps.push((*key, scan_single_layer(&dir)?));
+        }
+    }
+
+    let bundles = align_layers(layer_maps, footprint_mode).await;
 
     Ok(bundles)
 }
 
-type LayerMap = HashMap<String, PathBuf>;
+/// Id of the 1x1 degree cell whose south-west corner is at (`lat`, `lon`),
+/// as a file name below a layer directory, e.g. `N30E093.tif`.
+pub fn grid_tile_id(lat: i32, lon: i32) -> String {
+    format!("{}.tif", grid_cell_tag(lat, lon))
+}
 
-fn scan_single_layer(dir: &Path, layer_name: &str) -> Result<LayerMap> {
-    if !dir.exists() {
-        return Err(anyhow!(
-            "Directory not found for layer [{}]: {}",
-            layer_name,
-            normalize_path(dir),
-        ));
-    }
+type LayerMap = HashMap<String, PathBuf>;
 
+fn scan_single_layer(dir: &Path) -> Result<LayerMap> {
     let mut map = HashMap::new();
 
     for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
@@ -66,8 +57,11 @@ fn scan_single_layer(dir: &Path, layer_name: &str) -> Result<LayerMap> {
             continue;
         }
 
-        if let Some(file_name) = path.file_name().and_then(|s| s.to_str()) {
-            map.insert(file_name.to_string(), path.to_path_buf());
+        // Keyed by the path below the layer directory, so releases kept in
+        // versioned sub-directories do not collide.
+        if let Ok(relative) = path.strip_prefix(dir) {
+            let id = relative.to_string_lossy().replace('\\', "/");
+            map.insert(id, path.to_path_buf());
         }
     }
 
@@ -75,49 +69,34 @@ fn scan_single_layer(dir: &Path, layer_name: &str) -> Result<LayerMap> {
 }
 
 async fn align_layers(
-    sand_top: LayerMap,
-    sand_sub: LayerMap,
-    clay_top: LayerMap,
-    clay_sub: LayerMap,
-    soc_top: LayerMap,
-    ph_top: LayerMap,
-    ph_sub: LayerMap,
+    layer_maps: Vec<(SoilLayerKey, LayerMap)>,
+    footprint_mode: FootprintMode,
 ) -> Vec<SoilTile> {
-    let candidates: HashSet<String> = sand_top.keys().cloned().collect();
-    let mut bundles = Vec::new();
+    let mut candidates: BTreeMap<String, BTreeMap<SoilLayerKey, PathBuf>> = BTreeMap::new();
 
-    for id in candidates {
-        let maybe_bundle = async {
-            let p1 = sand_top.get(&id)?;
-            let p2 = sand_sub.get(&id)?;
-            let p3 = clay_top.get(&id)?;
-            let p4 = clay_sub.get(&id)?;
-            let p5 = soc_top.get(&id)?;
-            let p6 = ph_top.get(&id)?;
-            let p7 = ph_sub.get(&id)?;
-
-            let bounds = extract_bounds_async(p1).await.ok()?;
-
-            Some(SoilTile {
-                id: id.clone(),
-                bounds,
-                sand_top: p1.clone(),
-                sand_sub: p2.clone(),
-                clay_top: p3.clone(),
-                clay_sub: p4.clone(),
-                soc_top: p5.clone(),
-                ph_top: p6.clone(),
-                ph_sub: p7.clone(),
-            })
+    for (key, map) in layer_maps {
+        for (id, path) in map {
+            candidates.entry(id).or_default().insert(key, path);
         }
-        .await;
+    }
 
-        if let Some(bundle) = maybe_bundle {
-            bundles.push(bundle);
+    let mut bundles = Vec::new();
+
+    for (id, layers) in candidates {
+        let Some(first) = layers.values().next() else {
+            continue;
+        };
+
+        if let Ok(footprint) = extract_footprint_async(first, footprint_mode).await {
+            let epoch = resolve_epoch_async(parse_soil_epoch(&id), first).await;
+            bundles.push(SoilTile {
+                id,
+                epoch,
+                footprint,
+                layers,
+            });
         }
     }
 
-    bundles.sort_by(|a, b| a.id.cmp(&b.id));
-
     bundles
 }
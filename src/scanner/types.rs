@@ -1,4 +1,8 @@
-use geo::Rect;
+use crate::core::lonlat::wrapped_intersects;
+use crate::core::soil::SoilLayerKey;
+use clap::ValueEnum;
+use geo::{Coord, Intersects, MultiPolygon, Rect};
+use std::collections::BTreeMap;
 use std::fmt;
 use std::path::PathBuf;
 
@@ -6,12 +10,70 @@ pub struct DataCatalog {
     pub alos: Vec<AlosTile>,
     pub esa: Vec<EsaTile>,
     pub soil: Vec<SoilTile>,
+    pub soil_layers: Vec<SoilLayerKey>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    pub footprint_mode: FootprintMode,
+    pub soil_layers: Vec<SoilLayerKey>,
+    pub epoch_policy: EpochPolicy,
+}
+
+/// Dataset epoch parsed from a tile's name or metadata. Ordered by year, then
+/// by product version, so later releases of the same year sort higher.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Epoch {
+    pub year: u16,
+    pub version: u32,
+}
+
+impl fmt::Display for Epoch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.year, self.version) {
+            (0, v) => write!(f, "v{v}"),
+            (y, 0) => write!(f, "{y}"),
+            (y, v) => write!(f, "{y} v{v}"),
+        }
+    }
+}
+
+/// Which tiles to keep when a dataset holds several epochs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EpochPolicy {
+    /// Only tiles of the given year
+    Pinned(u16),
+    /// Only tiles of the newest epoch present
+    Newest,
+    /// All tiles; newer ones win wherever they overlap older ones
+    NewestWithFallback,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FootprintMode {
+    /// Raster extent with densified edges, reprojected to EPSG:4326
+    Extent,
+    /// Area traced from the nodata mask, excluding no-data collars
+    ValidData,
+}
+
+#[derive(Debug, Clone)]
+pub struct Footprint {
+    pub bounds: Rect<f64>,
+    pub polygon: MultiPolygon<f64>,
+}
+
+impl Footprint {
+    #[inline]
+    pub fn contains(&self, coord: &Coord<f64>) -> bool {
+        wrapped_intersects(&self.bounds, coord) && self.polygon.intersects(coord)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct AlosTile {
     pub id: String,
-    pub bounds: Rect<f64>,
+    pub footprint: Footprint,
 
     pub path_dem: PathBuf,
     pub path_hh: PathBuf,
@@ -23,33 +85,33 @@ pub struct AlosTile {
 #[derive(Debug, Clone)]
 pub struct EsaTile {
     pub id: String,
-    pub bounds: Rect<f64>,
+    pub epoch: Option<Epoch>,
+    pub footprint: Footprint,
 
     pub path_map: PathBuf,
+    pub path_quality: PathBuf,
 }
 
 #[derive(Debug, Clone)]
 pub struct SoilTile {
     pub id: String,
-    pub bounds: Rect<f64>,
+    pub epoch: Option<Epoch>,
+    pub footprint: Footprint,
 
-    pub sand_top: PathBuf,
-    pub sand_sub: PathBuf,
-    pub clay_top: PathBuf,
-    pub clay_sub: PathBuf,
-    pub soc_top: PathBuf,
-    pub ph_top: PathBuf,
-    pub ph_sub: PathBuf,
+    pub layers: BTreeMap<SoilLayerKey, PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct SourceCoverage {
+    pub name: String,
+    pub ratio: f64,
+    pub missing: MultiPolygon<f64>,
 }
 
 #[derive(Debug)]
 pub enum CoverageResult {
     Full,
-    Partial {
-        alos_coverage: f64,
-        esa_coverage: f64,
-        soil_coverage: f64,
-    },
+    Partial(Vec<SourceCoverage>),
 }
 
 impl CoverageResult {
@@ -62,19 +124,16 @@ impl fmt::Display for CoverageResult {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Full => write!(f, "All datasets fully cover the ROI."),
-            Self::Partial {
-                alos_coverage,
-                esa_coverage,
-                soil_coverage,
-            } => {
+            Self::Partial(sources) => {
                 writeln!(
                     f,
                     "Dataset insufficient, the current datasets does not cover the request ROI"
                 )?;
-                writeln!(f, "Coverages are:")?;
-                writeln!(f, "Alos Palsar: {:.2}%", alos_coverage * 100.0)?;
-                writeln!(f, "Esa WorldCover: {:.2}%", esa_coverage * 100.0)?;
-                write!(f, "Soil Grids: {:.2}%", soil_coverage * 100.0)
+                write!(f, "Coverages are:")?;
+                for source in sources {
+                    write!(f, "\n{}: {:.2}%", source.name, source.ratio * 100.0)?;
+                }
+                Ok(())
             }
         }
     }
@@ -0,0 +1,346 @@
+use crate::alignment::layers_align_and_resample;
+use crate::checkpoint::run_stages;
+use crate::core::context::{PixelWindow, SpatialContext};
+use crate::core::roi::OUTSIDE_MARGIN;
+use crate::core::terrain::{ELEVATION, ROI_MASK};
+use crate::exporter::{Outputs, soil_bottom_cm};
+use crate::loader::bundle::LayerBundle;
+use crate::loader::cache::block_cache;
+use crate::physics;
+use crate::post_process::backdrop::{self, LazyBackdrop};
+use crate::post_process::{self, fbm};
+use anyhow::{Result, anyhow};
+use std::path::Path;
+
+/// Tiles are written as whole region files, so their size is a multiple of this.
+pub const TILE_ALIGN: usize = 512;
+
+/// One tile of the ROI grid: the part it exports, and the window around it,
+/// grown by the halo, that it is processed in.
+struct Tile {
+    core: PixelWindow,
+    window: PixelWindow,
+}
+
+/// Runs the pipeline over square tiles of `tile_size` pixels, writing each
+/// straight to `outputs` so peak memory depends on the tile size
+/// rather than the ROI. Each tile is processed with a halo wide enough for
+/// every stage's reach, and against one coarse backdrop of the whole ROI for
+/// what reaches further, so tiles agree across their seams. Results match an
+/// untiled run except in voids wider than the fill pyramid reaches and along
+/// flow paths longer than `FLOW_REACH`, which take the backdrop's values.
+/// Tiles are checkpointed separately, so an interrupted run resumes tile by
+/// tile.
+pub fn run_tiled(
+    assets: &LayerBundle,
+    ctx: &SpatialContext,
+    tile_size: usize,
+    checkpoints_dir: Option<&Path>,
+    mut outputs: Outputs,
+) -> Result<()> {
+    let halo = tile_halo();
+    let tiles = plan_tiles(ctx, tile_size, halo);
+    println!(
+        "Tiled Mode: {} tiles of {} x {} pixels, {} pixel halo",
+        tiles.len(),
+        tile_size,
+        tile_size,
+        halo
+    );
+
+    if outputs.writes_world() {
+        let (min_elevation, max_elevation) = elevation_range(assets, ctx, &tiles)?;
+        outputs.plan_world(
+            min_elevation,
+            max_elevation,
+            soil_bottom_cm(assets.layers.iter().map(|l| &l.meta)),
+            ctx.pixel_size,
+        );
+    }
+
+    let backdrop = LazyBackdrop::new(assets, ctx);
+
+    let mut slope_sum = 0.0;
+    let mut slope_count = 0;
+
+    for (i, tile) in tiles.iter().enumerate() {
+        println!(
+            "Tile {}/{}: X[{}..{}] Z[{}..{}]",
+            i + 1,
+            tiles.len(),
+            tile.core.x,
+            tile.core.x + tile.core.width,
+            tile.core.y,
+            tile.core.y + tile.core.height
+        );
+
+        let tile_ctx = ctx.window(tile.window);
+        let (terrain, physics_map) =
+            run_stages(assets, &tile_ctx, Some(&backdrop), checkpoints_dir)?;
+        let (dx, dy) = (tile.core.x - tile.window.x, tile.core.y - tile.window.y);
+        for y in dy..dy + tile.core.height {
+            let row = y * terrain.width + dx;
+            slope_sum += physics_map.slope[row..row + tile.core.width]
+                .iter()
+                .sum::<f32>() as f64;
+        }
+        slope_count += tile.core.width * tile.core.height;
+
+        outputs.write(&terrain, &physics_map, tile.core)?;
+    }
+
+    let avg_slope = slope_sum / slope_count.max(1) as f64 / std::f64::consts::PI;
+    println!("Average Slope: {:.4}π rad", avg_slope);
+    println!("{}", block_cache().stats());
+
+    outputs.finish()
+}
+
+/// Halo around each tile: physics reads post-processed pixels up to its halo
+/// out, which read aligned pixels up to theirs, whose sampling depends on the
+/// ROI polygon up to `OUTSIDE_MARGIN` beyond them. Rounded to whole backdrop
+/// cells, so windows start on the cell boundaries void filling expects.
+fn tile_halo() -> usize {
+    (physics::HALO + post_process::HALO + OUTSIDE_MARGIN as usize).next_multiple_of(backdrop::CELL)
+}
+
+/// `tile_size` and `halo` have to be whole backdrop cells.
+fn plan_tiles(ctx: &SpatialContext, tile_size: usize, halo: usize) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    for y in (0..ctx.height).step_by(tile_size) {
+        for x in (0..ctx.width).step_by(tile_size) {
+            let core = PixelWindow {
+                x,
+                y,
+                width: tile_size.min(ctx.width - x),
+                height: tile_size.min(ctx.height - y),
+            };
+
+            let (x0, y0) = (x.saturating_sub(halo), y.saturating_sub(halo));
+            let x1 = (x + core.width + halo).min(ctx.width);
+            let y1 = (y + core.height + halo).min(ctx.height);
+            let window = PixelWindow {
+                x: x0,
+                y: y0,
+                width: x1 - x0,
+                height: y1 - y0,
+            };
+
+            tiles.push(Tile { core, window });
+        }
+    }
+    tiles
+}
+
+/// Elevation range inside the ROI, taken from a first pass over the raw
+/// elevation so the world's vertical layout is fixed before any tile is
+/// written. Void filling only interpolates between sampled values, so only
+/// the FBM detail can reach past this range.
+fn elevation_range(
+    assets: &LayerBundle,
+    ctx: &SpatialContext,
+    tiles: &[Tile],
+) -> Result<(f32, f32)> {
+    let elevation = assets.select(&[ELEVATION]);
+
+    let mut min = f32::MAX;
+    let mut max = f32::MIN;
+    for tile in tiles {
+        let grid = layers_align_and_resample(&elevation, &ctx.window(tile.core))?;
+        let inside = grid.mask(ROI_MASK);
+        for (idx, &v) in grid.elevation().iter().enumerate() {
+            if !v.is_nan() && inside.is_none_or(|inside| inside[idx]) {
+                min = min.min(v);
+                max = max.max(v);
+            }
+        }
+    }
+
+    if min > max {
+        return Err(anyhow!("No elevation data inside the ROI"));
+    }
+    let margin = fbm::MAX_AMPLITUDE as f32;
+    Ok((min - margin, max + margin))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::context::{Length, Snap};
+    use crate::core::layer::{LayerData, LayerKind, LayerMeta};
+    use crate::core::projection::ProjectionKind;
+    use crate::core::terrain::{LANDCOVER, LANDCOVER_CONFIDENCE, TerrainGrid};
+    use crate::physics::hydro::{FLOW_REACH, calc_twi_final, calc_upslope_paths};
+    use crate::physics::{PhysicsMap, physics_analyze};
+    use crate::post_process::backdrop::Backdrop;
+    use crate::post_process::terrain_post_process;
+    use geo::Coord;
+    use indicatif::ProgressBar;
+
+    const SIZE: usize = 1000;
+    const PIXEL_SIZE: f64 = 30.0;
+
+    /// Aligned layers of `window`, sampled `step` pixels apart, with a void
+    /// far wider than the fill pyramid reaches and scattered small ones.
+    fn synthetic(window: PixelWindow, step: usize) -> TerrainGrid {
+        let metas = vec![
+            LayerMeta::new(ELEVATION, "Elevation", LayerKind::Continuous, "m"),
+            LayerMeta::new(LANDCOVER, "Landcover", LayerKind::Categorical, "class"),
+            LayerMeta::new(
+                LANDCOVER_CONFIDENCE,
+                "Landcover Confidence",
+                LayerKind::Continuous,
+                "ratio",
+            ),
+        ];
+        let mut grid =
+            TerrainGrid::new(window.width, window.height, PIXEL_SIZE * step as f64, metas);
+        grid.offset = (window.x, window.y);
+
+        let pixels = (0..window.width * window.height).map(|i| {
+            let x = (window.x + i % window.width) * step + step / 2;
+            let y = (window.y + i / window.width) * step + step / 2;
+            let void = (300..500).contains(&x) && (350..500).contains(&y);
+            (x, y, void)
+        });
+        for layer in &mut grid.layers {
+            match (&mut layer.data, layer.meta.name.as_str()) {
+                (LayerData::Continuous(data), ELEVATION) => {
+                    for (v, (x, y, void)) in data.iter_mut().zip(pixels.clone()) {
+                        if !void && (x * 7 + y * 13) % 29 != 0 {
+                            let (fx, fy) = (x as f32, y as f32);
+                            *v = 200.0
+                                + 20.0 * (fx / 37.0).sin() * (fy / 53.0).cos()
+                                + 0.3 * fx
+                                + 0.2 * fy;
+                        }
+                    }
+                }
+                (LayerData::Continuous(data), _) => {
+                    for (v, (x, y, void)) in data.iter_mut().zip(pixels.clone()) {
+                        if !void {
+                            *v = 0.3 + ((x + y) % 10) as f32 / 14.0;
+                        }
+                    }
+                }
+                (LayerData::Categorical(data), _) => {
+                    for (v, (x, y, void)) in data.iter_mut().zip(pixels.clone()) {
+                        if !void && (x * 3 + y * 5) % 17 != 0 {
+                            *v = Some(((x / 40 + y / 60) % 5) as u8 * 10);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        grid
+    }
+
+    /// Post-processes and analyses `window` against `backdrop`, taking upslope
+    /// areas from it beyond `FLOW_REACH` unless `exact_flow` is set.
+    fn run(
+        ctx: &SpatialContext,
+        backdrop: &Backdrop,
+        window: PixelWindow,
+        exact_flow: bool,
+    ) -> (TerrainGrid, PhysicsMap) {
+        let mut terrain = synthetic(window, 1);
+        terrain_post_process(&mut terrain, Some(backdrop)).unwrap();
+        let flow_backdrop = (!exact_flow).then_some(backdrop);
+        let physics_map = physics_analyze(&terrain, &ctx.window(window), flow_backdrop).unwrap();
+        (terrain, physics_map)
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() <= 1e-4 * a.abs().max(1.0)
+    }
+
+    #[test]
+    fn tiled_run_matches_untiled() {
+        let ctx = SpatialContext::around(
+            Coord { x: 100.0, y: 30.0 },
+            Length::Blocks(SIZE),
+            Length::Blocks(SIZE),
+            PIXEL_SIZE,
+            Snap::None,
+            ProjectionKind::Adaptive,
+        )
+        .unwrap();
+        let coarse = ctx.coarsened(backdrop::CELL);
+        let backdrop = Backdrop::new(synthetic(
+            PixelWindow {
+                x: 0,
+                y: 0,
+                width: coarse.width,
+                height: coarse.height,
+            },
+            backdrop::CELL,
+        ));
+
+        let whole = PixelWindow {
+            x: 0,
+            y: 0,
+            width: SIZE,
+            height: SIZE,
+        };
+        let (terrain, physics_map) = run(&ctx, &backdrop, whole, true);
+        let (_, path_len) = calc_upslope_paths(&terrain, &ProgressBar::hidden());
+        let (mut exact, mut fallback) = (0, 0);
+
+        let tiles = plan_tiles(&ctx, 384, tile_halo());
+        assert!(tiles.iter().any(|t| t.window != whole));
+        for tile in tiles {
+            let (tile_terrain, tile_physics) = run(&ctx, &backdrop, tile.window, false);
+            let (dx, dy) = (tile.core.x - tile.window.x, tile.core.y - tile.window.y);
+            for y in 0..tile.core.height {
+                for x in 0..tile.core.width {
+                    let idx = (tile.core.y + y) * SIZE + tile.core.x + x;
+                    let tile_idx = (dy + y) * tile.window.width + dx + x;
+
+                    for (layer, tile_layer) in terrain.layers.iter().zip(&tile_terrain.layers) {
+                        let matches = match (&layer.data, &tile_layer.data) {
+                            (LayerData::Continuous(a), LayerData::Continuous(b)) => {
+                                close(a[idx], b[tile_idx])
+                            }
+                            (LayerData::Categorical(a), LayerData::Categorical(b)) => {
+                                a[idx] == b[tile_idx]
+                            }
+                            _ => false,
+                        };
+                        assert!(matches, "{} differs at {idx}", layer.meta.name);
+                    }
+                    for (name, _, _) in PhysicsMap::LAYERS {
+                        if name == "twi" {
+                            continue;
+                        }
+                        let (a, b) = (
+                            physics_map.layer(name).unwrap()[idx],
+                            tile_physics.layer(name).unwrap()[tile_idx],
+                        );
+                        assert!(close(a, b), "{name} differs at {idx}: {a} vs {b}");
+                    }
+
+                    // Within `FLOW_REACH` the tile sees the whole upslope path,
+                    // beyond it the area comes from the backdrop.
+                    let twi = tile_physics.twi[tile_idx];
+                    let expected = if path_len[idx] as usize <= FLOW_REACH {
+                        exact += 1;
+                        physics_map.twi[idx]
+                    } else {
+                        fallback += 1;
+                        let flow = backdrop
+                            .flow(tile.core.x + x, tile.core.y + y)
+                            .max((FLOW_REACH + 1) as f32);
+                        let slope = physics_map.slope[idx];
+                        calc_twi_final(&[flow], &[slope], PIXEL_SIZE, &ProgressBar::hidden())[0]
+                    };
+                    assert!(
+                        close(twi, expected),
+                        "twi differs at {idx}: {twi} vs {expected}"
+                    );
+                }
+            }
+        }
+        assert!(exact > 0 && fallback > 0);
+    }
+}